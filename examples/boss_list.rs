@@ -36,16 +36,21 @@ quick_main!(|| -> Result<()> {
 
     let (client, worker) = ClientBuilder::from_hyper_client(&hyper_client, &token).build();
 
-    // Fetch boss list once per 5 seconds
+    // Fetch bosses seen in the last 10 minutes, once per 5 seconds
     let interval = Interval::new(Duration::new(5, 0), &handle)
         .chain_err(|| "failed to create interval")?
         .then(|r| r.chain_err(|| "interval failed"))
-        .and_then(move |_| client.bosses())
+        .and_then(move |_| client.active_bosses(Duration::new(10 * 60, 0)))
         .for_each(|mut bosses| {
             bosses.sort_by_key(|b| b.level);
 
             for boss in bosses.iter() {
-                print!("{:<3} | {} ({:?})", boss.level, boss.name, boss.language,);
+                print!(
+                    "{:<3} | {} ({:?})",
+                    boss.level.map_or("?".to_string(), |l| l.to_string()),
+                    boss.name,
+                    boss.language,
+                );
 
                 for image in boss.image.iter() {
                     println!(" {}", image);
@@ -95,10 +95,10 @@ quick_main!(|| -> Result<()> {
         })
         .then(|r| r.chain_err(|| "server failed"));
 
-    // Send heartbeat every 30 seconds
+    // Every 30 seconds, send heartbeats to clients that haven't received any tweets
     let heartbeat = Interval::new(Duration::new(30, 0), &core.handle())
         .chain_err(|| "failed to create Interval")?
-        .for_each(move |_| Ok(petronel_client.heartbeat()))
+        .for_each(move |_| Ok(petronel_client.heartbeat_idle(Duration::new(30, 0))))
         .then(|r| r.chain_err(|| "heartbeat failed"));
 
     core.run(server.join3(petronel_worker, heartbeat))
@@ -222,10 +222,23 @@ impl Service for PetronelServer {
             let method = req.method();
 
             if method == &hyper::Method::Delete {
-                self.0.remove_bosses(move |ref meta| meta.boss.name == name);
+                let resp = self.0
+                    .remove_boss(name)
+                    .map(|removed| {
+                        if removed {
+                            Response::new().with_status(StatusCode::NoContent)
+                        } else {
+                            response(
+                                StatusCode::NotFound,
+                                &JsonError {
+                                    error: "boss not found".to_string(),
+                                },
+                            )
+                        }
+                    })
+                    .map_err(|_| hyper::Error::Incomplete);
 
-                let resp = Response::new().with_status(StatusCode::Accepted);
-                Box::new(futures::future::ok(resp)) as Self::Future
+                Box::new(resp) as Self::Future
             } else if method == &hyper::Method::Get {
                 let resp = self.0
                     .bosses()
@@ -0,0 +1,47 @@
+// Reads statuses from stdin, one JSON object per line, and writes each raid
+// tweet to stdout as a line of JSON. Statuses that aren't raid tweets and
+// lines that aren't JSON are skipped. Input can be recorded from Twitter
+// with `RaidInfoStream::record_to`.
+//
+//     cargo run --example stdin < statuses.jsonl
+//
+// With `--paced`, tweets are written with the same gaps between them as
+// when they were tweeted.
+
+#[macro_use]
+extern crate error_chain;
+
+extern crate futures;
+extern crate petronel;
+extern crate serde_json;
+extern crate tokio_core;
+
+use futures::Stream;
+use petronel::error::*;
+use petronel::raid::RaidInfoStream;
+use std::io::{self, BufReader};
+use tokio_core::reactor::Core;
+
+quick_main!(|| -> Result<()> {
+    let paced = ::std::env::args().any(|arg| arg == "--paced");
+
+    let mut core = Core::new().chain_err(|| "failed to create Core")?;
+
+    // Any `BufRead` works as a source, and `RaidInfoStreamBuilder` (or any
+    // other stream of `RaidInfo`) can be swapped in to read from Twitter
+    let stdin = BufReader::new(io::stdin());
+    let stream = RaidInfoStream::from_reader(stdin, &core.handle(), paced);
+
+    let stdout = io::stdout();
+    let future = stream.for_each(|raid_info| {
+        let mut out = stdout.lock();
+        serde_json::to_writer(&mut out, &raid_info.tweet)
+            .chain_err(|| "failed to serialize tweet")?;
+        out.write_all(b"\n")
+            .and_then(|()| out.flush())
+            .chain_err(|| "failed to write to stdout")
+    });
+
+    core.run(future).chain_err(|| "stream failed")?;
+    Ok(())
+});
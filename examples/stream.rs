@@ -8,6 +8,8 @@ extern crate tokio_core;
 use futures::Stream;
 use petronel::Token;
 use petronel::error::*;
+use petronel::raid::{RaidInfoStreamBuilder, ReconnectConfig};
+use std::time::Duration;
 use tokio_core::reactor::Core;
 
 fn env(name: &str) -> Result<String> {
@@ -24,8 +26,12 @@ quick_main!(|| -> Result<()> {
 
     let mut core = Core::new().chain_err(|| "failed to create Core")?;
 
-    let future = petronel::raid::RaidInfoStream::with_handle(&core.handle(), &token)
-        .for_each(|raid_info| Ok(println!("{:#?}", raid_info)));
+    let stream = RaidInfoStreamBuilder::new(&token)
+        .with_reconnect(ReconnectConfig::default())
+        .with_stall_timeout(Duration::from_secs(90))
+        .connect(&core.handle())
+        .chain_err(|| "failed to connect")?;
+    let future = stream.for_each(|raid_info| Ok(println!("{:#?}", raid_info)));
 
     core.run(future).chain_err(|| "stream failed")?;
     Ok(())
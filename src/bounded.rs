@@ -0,0 +1,223 @@
+// A bounded channel that can be used as a `Subscriber`. Unlike a regular
+// `Sink`, sending never waits for the receiver: if the queue is full, the
+// `DropPolicy` decides what to do with the message.
+
+use broadcast::Subscriber;
+use futures::{Async, Poll, Stream};
+use futures::task::{self, Task};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DropPolicy {
+    // Remove the oldest queued message to make space for the new one
+    DropOldest,
+    // Discard the new message
+    DropNewest,
+    // Return an error, which causes the subscriber to be removed
+    DisconnectSubscriber,
+}
+
+#[derive(Debug)]
+struct Inner<T> {
+    queue: VecDeque<T>,
+    capacity: usize,
+    policy: DropPolicy,
+    overflow_count: u64,
+    sender_count: usize,
+    receiver_alive: bool,
+    receiver_task: Option<Task>,
+}
+
+impl<T> Inner<T> {
+    fn notify(&mut self) {
+        if let Some(task) = self.receiver_task.take() {
+            task.notify();
+        }
+    }
+}
+
+pub fn channel<T>(capacity: usize, policy: DropPolicy) -> (Sender<T>, Receiver<T>) {
+    let inner = Rc::new(RefCell::new(Inner {
+        queue: VecDeque::with_capacity(capacity),
+        capacity,
+        policy,
+        overflow_count: 0,
+        sender_count: 1,
+        receiver_alive: true,
+        receiver_task: None,
+    }));
+
+    (Sender(inner.clone()), Receiver(inner))
+}
+
+#[derive(Debug)]
+pub struct Sender<T>(Rc<RefCell<Inner<T>>>);
+
+impl<T> Sender<T> {
+    // Number of times a message was sent while the queue was full
+    pub fn overflow_count(&self) -> u64 {
+        self.0.borrow().overflow_count
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.0.borrow_mut().sender_count += 1;
+        Sender(self.0.clone())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut inner = self.0.borrow_mut();
+        inner.sender_count -= 1;
+
+        if inner.sender_count == 0 {
+            inner.notify();
+        }
+    }
+}
+
+impl<T> Subscriber for Sender<T>
+where
+    T: Clone,
+{
+    type Item = T;
+
+    fn send(&mut self, message: &Self::Item) -> Result<(), ()> {
+        let mut inner = self.0.borrow_mut();
+
+        if !inner.receiver_alive {
+            return Err(());
+        }
+
+        if inner.queue.len() >= inner.capacity {
+            inner.overflow_count += 1;
+
+            match inner.policy {
+                DropPolicy::DropOldest => {
+                    inner.queue.pop_front();
+                }
+                DropPolicy::DropNewest => return Ok(()),
+                DropPolicy::DisconnectSubscriber => return Err(()),
+            }
+        }
+
+        inner.queue.push_back(message.clone());
+        inner.notify();
+        Ok(())
+    }
+
+    fn overflow_count(&self) -> u64 {
+        Sender::overflow_count(self)
+    }
+
+    fn is_closed(&self) -> bool {
+        !self.0.borrow().receiver_alive
+    }
+}
+
+#[derive(Debug)]
+#[must_use = "streams do nothing unless polled"]
+pub struct Receiver<T>(Rc<RefCell<Inner<T>>>);
+
+impl<T> Receiver<T> {
+    pub fn overflow_count(&self) -> u64 {
+        self.0.borrow().overflow_count
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut inner = self.0.borrow_mut();
+        inner.receiver_alive = false;
+        inner.queue.clear();
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut inner = self.0.borrow_mut();
+
+        if let Some(message) = inner.queue.pop_front() {
+            Ok(Async::Ready(Some(message)))
+        } else if inner.sender_count == 0 {
+            Ok(Async::Ready(None))
+        } else {
+            inner.receiver_task = Some(task::current());
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::Future;
+
+    fn send_all(sender: &mut Sender<u32>, messages: &[u32]) -> Vec<Result<(), ()>> {
+        messages.iter().map(|m| sender.send(m)).collect()
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let (mut tx, rx) = channel(3, DropPolicy::DropOldest);
+
+        assert!(send_all(&mut tx, &[1, 2, 3, 4, 5]).iter().all(Result::is_ok));
+        assert_eq!(tx.overflow_count(), 2);
+
+        drop(tx);
+        assert_eq!(rx.collect().wait().unwrap(), vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn drop_newest() {
+        let (mut tx, rx) = channel(3, DropPolicy::DropNewest);
+
+        assert!(send_all(&mut tx, &[1, 2, 3, 4, 5]).iter().all(Result::is_ok));
+        assert_eq!(rx.overflow_count(), 2);
+
+        drop(tx);
+        assert_eq!(rx.collect().wait().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn disconnect_subscriber() {
+        let (mut tx, rx) = channel(3, DropPolicy::DisconnectSubscriber);
+
+        assert_eq!(
+            send_all(&mut tx, &[1, 2, 3, 4]),
+            vec![Ok(()), Ok(()), Ok(()), Err(())]
+        );
+        assert_eq!(tx.overflow_count(), 1);
+
+        drop(tx);
+        assert_eq!(rx.collect().wait().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn receiver_dropped() {
+        let (mut tx, rx) = channel(3, DropPolicy::DropOldest);
+        drop(rx);
+
+        assert_eq!(tx.send(&1), Err(()));
+    }
+
+    #[test]
+    fn ends_when_all_senders_dropped() {
+        let (mut tx1, rx) = channel(3, DropPolicy::DropOldest);
+        let mut tx2 = tx1.clone();
+
+        tx1.send(&1).unwrap();
+        drop(tx1);
+        tx2.send(&2).unwrap();
+        drop(tx2);
+
+        assert_eq!(rx.collect().wait().unwrap(), vec![1, 2]);
+    }
+}
@@ -1,4 +1,5 @@
 use futures::Sink;
+use model::DateTime;
 use std::collections::HashMap;
 use std::hash::Hash;
 
@@ -6,6 +7,19 @@ pub trait Subscriber {
     type Item;
 
     fn send(&mut self, message: &Self::Item) -> Result<(), ()>;
+
+    // Number of messages that were accepted by `send`, but discarded
+    // because the subscriber couldn't keep up
+    fn overflow_count(&self) -> u64 {
+        0
+    }
+
+    // Whether the receiving end is known to be gone. Subscribers that can't
+    // tell are only removed once a send fails.
+    fn is_closed(&self) -> bool {
+        false
+    }
+
     fn maybe_send(&mut self, message: Option<&Self::Item>) -> Result<(), ()> {
         if let Some(msg) = message {
             self.send(msg)
@@ -40,7 +54,22 @@ impl Subscriber for NoOpSubscriber {
 }
 
 pub struct Broadcast<Id, S> {
-    subscribers: HashMap<Id, S>,
+    subscribers: HashMap<Id, Member<S>>,
+}
+
+struct Member<S> {
+    subscriber: S,
+    stats: DeliveryStats,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DeliveryStats {
+    pub delivered: u64,
+    // Messages sent to this member that the subscriber later discarded.
+    // Subscribers can be shared between broadcasts, so this only counts
+    // overflows that happened while sending this broadcast's messages.
+    pub dropped: u64,
+    pub last_delivered: Option<DateTime>,
 }
 
 impl<Id, S> Broadcast<Id, S>
@@ -65,19 +94,24 @@ where
     }
 
     pub fn get(&self, id: &Id) -> Option<&S> {
-        self.subscribers.get(id)
+        self.subscribers.get(id).map(|m| &m.subscriber)
     }
 
     pub fn get_mut(&mut self, id: &Id) -> Option<&mut S> {
-        self.subscribers.get_mut(id)
+        self.subscribers.get_mut(id).map(|m| &mut m.subscriber)
     }
 
     pub fn subscribe(&mut self, id: Id, subscriber: S) -> Option<S> {
-        self.subscribers.insert(id, subscriber)
+        let member = Member {
+            subscriber,
+            stats: DeliveryStats::default(),
+        };
+
+        self.subscribers.insert(id, member).map(|m| m.subscriber)
     }
 
     pub fn unsubscribe(&mut self, id: &Id) -> Option<S> {
-        self.subscribers.remove(id)
+        self.subscribers.remove(id).map(|m| m.subscriber)
     }
 
     pub(crate) fn maybe_send(&mut self, message: Option<&S::Item>) {
@@ -86,13 +120,89 @@ where
         }
     }
 
+    // Like `maybe_send`, but also records the time of delivery. Returns the
+    // number of messages dropped, as in `send_inner`.
+    pub(crate) fn maybe_send_at(&mut self, message: Option<&S::Item>, now: DateTime) -> u64 {
+        match message {
+            Some(msg) => self.send_inner(msg, Some(now), |_| true),
+            None => 0,
+        }
+    }
+
+    // Like `maybe_send_at`, but only to subscribers matching the predicate
+    pub(crate) fn maybe_send_at_where<P>(
+        &mut self,
+        message: Option<&S::Item>,
+        now: DateTime,
+        predicate: P,
+    ) -> u64
+    where
+        P: Fn(&Id) -> bool,
+    {
+        match message {
+            Some(msg) => self.send_inner(msg, Some(now), predicate),
+            None => 0,
+        }
+    }
+
     pub fn subscriber_count(&self) -> usize {
         self.subscribers.len()
     }
 
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&Id, &S) -> bool,
+    {
+        self.subscribers.retain(|id, member| f(id, &member.subscriber))
+    }
+
     pub fn send(&mut self, message: &S::Item) {
+        self.send_inner(message, None, |_| true);
+    }
+
+    // Returns the number of messages that were dropped because the
+    // subscriber's buffer was full. Sends to subscribers that are gone
+    // aren't counted, since nobody missed them.
+    fn send_inner<P>(&mut self, message: &S::Item, now: Option<DateTime>, predicate: P) -> u64
+    where
+        P: Fn(&Id) -> bool,
+    {
+        let mut dropped = 0;
+
         // Remove any subscribers that return an error
-        self.subscribers
-            .retain(|_, subscriber| subscriber.send(message).is_ok())
+        self.subscribers.retain(|id, member| {
+            if !predicate(id) {
+                return true;
+            }
+
+            let overflow_count = member.subscriber.overflow_count();
+            let is_ok = member.subscriber.send(message).is_ok();
+
+            // Subscribers that disconnect when full still overflow first
+            let overflowed = member.subscriber.overflow_count().saturating_sub(overflow_count);
+            member.stats.dropped += overflowed;
+            dropped += overflowed;
+
+            if is_ok {
+                member.stats.delivered += 1;
+                if now.is_some() {
+                    member.stats.last_delivered = now;
+                }
+            }
+
+            is_ok
+        });
+
+        dropped
+    }
+
+    pub fn stats<'a>(
+        &'a self,
+    ) -> Box<Iterator<Item = (&'a Id, &'a S, &'a DeliveryStats)> + 'a> {
+        Box::new(
+            self.subscribers
+                .iter()
+                .map(|(id, m)| (id, &m.subscriber, &m.stats)),
+        )
     }
 }
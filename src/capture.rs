@@ -0,0 +1,106 @@
+use chrono::Duration;
+use model::DateTime;
+use raid::RaidInfo;
+use serde_json;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const FLUSH_INTERVAL_MILLIS: i64 = 1000;
+
+// Appends each `RaidInfo` to a file as a line of JSON. Errors are logged
+// rather than returned, so a failing disk doesn't stop tweets from being
+// processed. Lines are buffered, and flushed at most once per interval.
+pub(crate) struct Capture {
+    writer: BufWriter<File>,
+    last_flush: Option<DateTime>,
+}
+
+impl Capture {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Capture {
+            writer: BufWriter::new(file),
+            last_flush: None,
+        })
+    }
+
+    pub fn write(&mut self, info: &RaidInfo, now: DateTime) {
+        let mut line = match serde_json::to_vec(info) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to serialize captured tweet: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        if let Err(e) = self.writer.write_all(&line) {
+            error!("Failed to write captured tweet: {}", e);
+        }
+
+        let interval = Duration::milliseconds(FLUSH_INTERVAL_MILLIS);
+        let is_due = self.last_flush
+            .map(|last| now.signed_duration_since(last) >= interval)
+            .unwrap_or(true);
+
+        if is_due {
+            if let Err(e) = self.writer.flush() {
+                error!("Failed to flush capture file: {}", e);
+            }
+            self.last_flush = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use model::{Language, RaidTweet};
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn capture_lines() {
+        let file_name = format!("petronel-capture-{}.json", ::std::process::id());
+        let path = ::std::env::temp_dir().join(file_name);
+        let _ = fs::remove_file(&path);
+
+        let info = |raid_id: &str| RaidInfo {
+            tweet: RaidTweet {
+                tweet_id: 0,
+                boss_name: "Lvl 60 Ozorotter".into(),
+                raid_id: raid_id.into(),
+                user_screen_name: "walfieee".into(),
+                user_display_name: "walfie".into(),
+                user_id: 1234567890,
+                user_image: None,
+                text: None,
+                created_at: Utc.timestamp(0, 0),
+                language: Language::English,
+                seq: 0,
+                raw: None,
+            },
+            image: None,
+            images: vec![],
+            level: Some(60),
+            raw: None,
+        };
+
+        {
+            let mut capture = Capture::open(&path).unwrap();
+            capture.write(&info("AAAA0001"), Utc.timestamp(0, 0));
+            capture.write(&info("AAAA0002"), Utc.timestamp(0, 0));
+        }
+
+        let lines = BufReader::new(File::open(&path).unwrap())
+            .lines()
+            .map(|line| serde_json::from_str::<RaidInfo>(&line.unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(lines, vec![info("AAAA0001"), info("AAAA0002")]);
+    }
+}
@@ -29,6 +29,28 @@ impl<T> CircularBuffer<T> {
     pub fn as_unordered_slice(&self) -> &[T] {
         self.buffer.as_slice()
     }
+
+    // Removes the oldest item matching the predicate. The other items keep
+    // their order, and the next `push` goes after the newest.
+    pub fn remove_first<F>(&mut self, predicate: F) -> Option<T>
+    where
+        F: Fn(&T) -> bool,
+    {
+        let older = self.buffer.drain(self.next_index..).collect::<Vec<_>>();
+        let newer = self.buffer.drain(..).collect::<Vec<_>>();
+
+        let mut removed = None;
+        for item in older.into_iter().chain(newer) {
+            if removed.is_none() && predicate(&item) {
+                removed = Some(item);
+            } else {
+                self.buffer.push(item);
+            }
+        }
+
+        self.next_index = self.buffer.len() % self.buffer.capacity().max(1);
+        removed
+    }
 }
 
 #[cfg(test)]
@@ -95,4 +117,21 @@ mod test {
 
         assert_eq!(buf.as_slices(), (&[3, 4][..], &[5, 6, 7][..]));
     }
+
+    #[test]
+    fn remove_first() {
+        let mut buf = CircularBuffer::with_capacity(4);
+
+        for i in 0..6 {
+            buf.push(i);
+        }
+
+        assert_eq!(buf.remove_first(|i| i % 2 == 1), Some(3));
+        assert_eq!(buf.remove_first(|i| *i > 10), None);
+        assert_eq!(buf.as_slices(), (&[][..], &[2, 4, 5][..]));
+
+        buf.push(6);
+        buf.push(7);
+        assert_eq!(buf.as_slices(), (&[4, 5, 6][..], &[7][..]));
+    }
 }
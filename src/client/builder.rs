@@ -1,8 +1,11 @@
 use Token;
 use broadcast::{Broadcast, NoOpSubscriber, Subscriber};
+use capture::Capture;
+use chrono;
 use circular_buffer::CircularBuffer;
-use client::{Client, Event, Worker};
-use client::worker::RaidBossEntry;
+use clock::{self, Clock};
+use client::{Client, Event, Hooks, Worker};
+use client::worker::{initial_image_history, initial_image_votes, RaidBossEntry};
 use error::*;
 use futures::Stream;
 use futures::unsync::mpsc;
@@ -11,13 +14,17 @@ use hyper::client::Connect;
 use id_pool::IdPool;
 use image_hash::{self, BossImageHash, HyperImageHasher, ImageHasher};
 use metrics::{self, Metrics};
-use model::{Message, RaidBossMetadata};
+use model::{BossLevel, BossName, Message, RaidBoss, RaidBossMetadata, RaidTweet};
 use raid::{RaidInfo, RaidInfoStream};
+use rate_limit::{RateLimit, TokenBucket};
 use std::collections::HashMap;
 use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
 
 #[derive(Clone, Debug)]
-pub struct ClientBuilder<H, S, Sub, F, M> {
+pub struct ClientBuilder<H, S, Sub, F, M, C> {
     stream: S,
     history_size: usize,
     image_hasher: H,
@@ -25,12 +32,22 @@ pub struct ClientBuilder<H, S, Sub, F, M> {
     bosses: Vec<RaidBossMetadata>,
     subscriber_type: PhantomData<Sub>,
     metrics: M,
+    clock: C,
+    sequence_offset: u64,
+    duplicate_window: Option<chrono::Duration>,
+    default_boss_level: Option<BossLevel>,
+    hooks: Hooks,
+    rate_limit: Option<RateLimit>,
+    capture_path: Option<PathBuf>,
+    keep_raw_json: bool,
+    image_history_size: usize,
+    max_text_len: Option<usize>,
 }
 
 const DEFAULT_HISTORY_SIZE: usize = 10;
 const MAX_CONCURRENT_IMAGE_HASHER_REQUESTS: usize = 5;
 
-impl ClientBuilder<(), (), (), (), metrics::NoOp> {
+impl ClientBuilder<(), (), (), (), metrics::NoOp, clock::System> {
     pub fn new() -> Self {
         ClientBuilder {
             stream: (),
@@ -40,6 +57,16 @@ impl ClientBuilder<(), (), (), (), metrics::NoOp> {
             bosses: Vec::new(),
             subscriber_type: PhantomData,
             metrics: metrics::NoOp,
+            clock: clock::System,
+            sequence_offset: 0,
+            duplicate_window: None,
+            default_boss_level: None,
+            hooks: Hooks::default(),
+            rate_limit: None,
+            capture_path: None,
+            keep_raw_json: false,
+            image_history_size: 0,
+            max_text_len: None,
         }
     }
 }
@@ -51,6 +78,7 @@ impl<'a, C>
         NoOpSubscriber,
         fn(Message) -> Option<()>,
         metrics::NoOp,
+        clock::System,
     > where
     C: Connect,
 {
@@ -67,19 +95,29 @@ impl<'a, C>
             filter_map_message: (|_| None) as fn(Message) -> Option<()>,
             subscriber_type: PhantomData,
             metrics: metrics::NoOp,
+            clock: clock::System,
+            sequence_offset: 0,
+            duplicate_window: None,
+            default_boss_level: None,
+            hooks: Hooks::default(),
+            rate_limit: None,
+            capture_path: None,
+            keep_raw_json: false,
+            image_history_size: 0,
+            max_text_len: None,
         }
     }
 }
 
-impl<H, S, Sub, F, M> ClientBuilder<H, S, Sub, F, M> {
+impl<H, S, Sub, F, M, C> ClientBuilder<H, S, Sub, F, M, C> {
     pub fn with_history_size(mut self, size: usize) -> Self {
         self.history_size = size;
         self
     }
 
-    pub fn with_stream<S2>(self, stream: S2) -> ClientBuilder<H, S2, Sub, F, M>
+    pub fn with_stream<S2>(self, stream: S2) -> ClientBuilder<H, S2, Sub, F, M, C>
     where
-        S: Stream<Item = RaidInfo, Error = Error>,
+        S2: Stream<Item = RaidInfo, Error = Error>,
     {
         ClientBuilder {
             stream,
@@ -89,10 +127,20 @@ impl<H, S, Sub, F, M> ClientBuilder<H, S, Sub, F, M> {
             filter_map_message: self.filter_map_message,
             subscriber_type: self.subscriber_type,
             metrics: self.metrics,
+            clock: self.clock,
+            sequence_offset: self.sequence_offset,
+            duplicate_window: self.duplicate_window,
+            default_boss_level: self.default_boss_level,
+            hooks: self.hooks,
+            rate_limit: self.rate_limit,
+            capture_path: self.capture_path,
+            keep_raw_json: self.keep_raw_json,
+            image_history_size: self.image_history_size,
+            max_text_len: self.max_text_len,
         }
     }
 
-    pub fn with_image_hasher<H2>(self, image_hasher: H2) -> ClientBuilder<H2, S, Sub, F, M> {
+    pub fn with_image_hasher<H2>(self, image_hasher: H2) -> ClientBuilder<H2, S, Sub, F, M, C> {
         ClientBuilder {
             stream: self.stream,
             history_size: self.history_size,
@@ -101,10 +149,20 @@ impl<H, S, Sub, F, M> ClientBuilder<H, S, Sub, F, M> {
             filter_map_message: self.filter_map_message,
             subscriber_type: self.subscriber_type,
             metrics: self.metrics,
+            clock: self.clock,
+            sequence_offset: self.sequence_offset,
+            duplicate_window: self.duplicate_window,
+            default_boss_level: self.default_boss_level,
+            hooks: self.hooks,
+            rate_limit: self.rate_limit,
+            capture_path: self.capture_path,
+            keep_raw_json: self.keep_raw_json,
+            image_history_size: self.image_history_size,
+            max_text_len: self.max_text_len,
         }
     }
 
-    pub fn with_subscriber<Sub2>(self) -> ClientBuilder<H, S, Sub2, F, M>
+    pub fn with_subscriber<Sub2>(self) -> ClientBuilder<H, S, Sub2, F, M, C>
     where
         Sub2: Subscriber,
     {
@@ -116,10 +174,20 @@ impl<H, S, Sub, F, M> ClientBuilder<H, S, Sub, F, M> {
             filter_map_message: self.filter_map_message,
             subscriber_type: PhantomData,
             metrics: self.metrics,
+            clock: self.clock,
+            sequence_offset: self.sequence_offset,
+            duplicate_window: self.duplicate_window,
+            default_boss_level: self.default_boss_level,
+            hooks: self.hooks,
+            rate_limit: self.rate_limit,
+            capture_path: self.capture_path,
+            keep_raw_json: self.keep_raw_json,
+            image_history_size: self.image_history_size,
+            max_text_len: self.max_text_len,
         }
     }
 
-    pub fn filter_map_message<F2, T>(self, f: F2) -> ClientBuilder<H, S, Sub, F2, M>
+    pub fn filter_map_message<F2, T>(self, f: F2) -> ClientBuilder<H, S, Sub, F2, M, C>
     where
         F2: Fn(Message) -> Option<T>,
     {
@@ -131,10 +199,20 @@ impl<H, S, Sub, F, M> ClientBuilder<H, S, Sub, F, M> {
             filter_map_message: f,
             subscriber_type: self.subscriber_type,
             metrics: self.metrics,
+            clock: self.clock,
+            sequence_offset: self.sequence_offset,
+            duplicate_window: self.duplicate_window,
+            default_boss_level: self.default_boss_level,
+            hooks: self.hooks,
+            rate_limit: self.rate_limit,
+            capture_path: self.capture_path,
+            keep_raw_json: self.keep_raw_json,
+            image_history_size: self.image_history_size,
+            max_text_len: self.max_text_len,
         }
     }
 
-    pub fn with_metrics<M2>(self, metrics: M2) -> ClientBuilder<H, S, Sub, F, M2>
+    pub fn with_metrics<M2>(self, metrics: M2) -> ClientBuilder<H, S, Sub, F, M2, C>
     where
         M2: Metrics,
     {
@@ -146,30 +224,169 @@ impl<H, S, Sub, F, M> ClientBuilder<H, S, Sub, F, M> {
             filter_map_message: self.filter_map_message,
             subscriber_type: self.subscriber_type,
             metrics,
+            clock: self.clock,
+            sequence_offset: self.sequence_offset,
+            duplicate_window: self.duplicate_window,
+            default_boss_level: self.default_boss_level,
+            hooks: self.hooks,
+            rate_limit: self.rate_limit,
+            capture_path: self.capture_path,
+            keep_raw_json: self.keep_raw_json,
+            image_history_size: self.image_history_size,
+            max_text_len: self.max_text_len,
         }
     }
 
+    pub fn with_clock<C2>(self, clock: C2) -> ClientBuilder<H, S, Sub, F, M, C2>
+    where
+        C2: Clock,
+    {
+        ClientBuilder {
+            stream: self.stream,
+            history_size: self.history_size,
+            image_hasher: self.image_hasher,
+            bosses: self.bosses,
+            filter_map_message: self.filter_map_message,
+            subscriber_type: self.subscriber_type,
+            metrics: self.metrics,
+            clock,
+            sequence_offset: self.sequence_offset,
+            duplicate_window: self.duplicate_window,
+            default_boss_level: self.default_boss_level,
+            hooks: self.hooks,
+            rate_limit: self.rate_limit,
+            capture_path: self.capture_path,
+            keep_raw_json: self.keep_raw_json,
+            image_history_size: self.image_history_size,
+            max_text_len: self.max_text_len,
+        }
+    }
+
+    // Sequence numbers assigned to tweets start after this value. To keep
+    // numbers unique across restarts, pass in `Client::last_seq` from before
+    // the restart.
+    pub fn with_sequence_offset(mut self, offset: u64) -> Self {
+        self.sequence_offset = offset;
+        self
+    }
+
+    // Tweets for a boss with the same raid ID as a tweet seen within this
+    // window are treated as the same raid, and are counted in
+    // `RaidBossMetadata::duplicate_count` rather than sent to subscribers.
+    // They don't get a sequence number. Popular raids tend to be posted by
+    // many users at once.
+    pub fn with_duplicate_window(mut self, window: Duration) -> Self {
+        self.duplicate_window =
+            Some(chrono::Duration::from_std(window).unwrap_or(chrono::Duration::max_value()));
+        self
+    }
+
+    // Level given to bosses whose level can't be parsed from their name.
+    // Defaults to `None`, meaning the level is unknown.
+    pub fn with_default_boss_level<L>(mut self, level: L) -> Self
+    where
+        L: Into<Option<BossLevel>>,
+    {
+        self.default_boss_level = level.into();
+        self
+    }
+
+    // Hooks are called synchronously from the worker, so they should be
+    // cheap and never block. Setting a hook again replaces the old one.
+    pub fn on_new_boss<Hook>(mut self, f: Hook) -> Self
+    where
+        Hook: Fn(&RaidBoss) + 'static,
+    {
+        self.hooks.on_new_boss = Some(Rc::new(f));
+        self
+    }
+
+    // Called for every raid tweet received, including ones held back by the
+    // rate limit, but not for duplicates within the duplicate window
+    pub fn on_raid<Hook>(mut self, f: Hook) -> Self
+    where
+        Hook: Fn(&RaidTweet) + 'static,
+    {
+        self.hooks.on_raid = Some(Rc::new(f));
+        self
+    }
+
+    pub fn on_boss_removed<Hook>(mut self, f: Hook) -> Self
+    where
+        Hook: Fn(&BossName) + 'static,
+    {
+        self.hooks.on_boss_removed = Some(Rc::new(f));
+        self
+    }
+
+    // Limits how many tweets per boss are sent to subscribers, allowing
+    // bursts of up to `tweets`, refilled at `tweets` per `per`. Tweets
+    // over the limit are still added to the boss' tweet history.
+    pub fn with_rate_limit(mut self, tweets: u32, per: Duration) -> Self {
+        self.rate_limit = Some(RateLimit {
+            capacity: tweets,
+            per: chrono::Duration::from_std(per).unwrap_or(chrono::Duration::max_value()),
+        });
+        self
+    }
+
+    // Appends every incoming `RaidInfo` to the file as a line of JSON,
+    // before duplicates are dropped, without its raw JSON. If the file can't
+    // be opened when the worker is built, the error is logged and nothing is
+    // captured.
+    pub fn capture_to<P>(mut self, path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.capture_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    // Moves `RaidInfo::raw` into `RaidTweet::raw`, so it's kept in each
+    // boss' tweet history. Otherwise, it's dropped when the worker receives
+    // the tweet. Raw JSON is only present if the stream includes it.
+    pub fn keep_raw_json(mut self) -> Self {
+        self.keep_raw_json = true;
+        self
+    }
+
+    // Shortens tweet text longer than `len` characters to `len` characters,
+    // ending with an ellipsis, before the tweet is stored or sent
+    pub fn with_max_text_len(mut self, len: usize) -> Self {
+        self.max_text_len = Some(len);
+        self
+    }
+
+    // Keeps up to `size` of the most recently seen images for each boss,
+    // ignoring size variants, for `Client::boss_images`
+    pub fn with_image_history(mut self, size: usize) -> Self {
+        self.image_history_size = size;
+        self
+    }
+
     pub fn with_bosses(mut self, bosses: Vec<RaidBossMetadata>) -> Self {
         self.bosses = bosses;
         self
     }
 
-    pub fn build(self) -> (Client<Sub, M::Export>, Worker<H, S, Sub, F, M>)
+    pub fn build(self) -> (Client<Sub, M::Export>, Worker<H, S, Sub, F, M, C>)
     where
         S: Stream<Item = RaidInfo, Error = Error>,
         H: ImageHasher,
         Sub: Subscriber + Clone, // TODO: Change Sub to not require Clone
         F: Fn(Message) -> Option<Sub::Item>,
         M: Metrics,
+        C: Clock,
     {
         let (tx, rx) = mpsc::unbounded();
 
         // When the Twitter stream ends, fail with an error
+        let to_event = |info| Event::NewRaidInfo(Box::new(info));
         let stream_events = self.stream
             .chain(::futures::stream::once(Err(Error::from_kind(
                 ErrorKind::Closed,
             ))))
-            .map(Event::NewRaidInfo as fn(RaidInfo) -> Event<Sub, M::Export>);
+            .map(to_event as fn(RaidInfo) -> Event<Sub, M::Export>);
 
         let to_read_error = |()| Ok(Event::ClientReadError);
         let rx = rx.or_else(to_read_error as fn(()) -> Result<Event<Sub, M::Export>>);
@@ -191,17 +408,36 @@ impl<H, S, Sub, F, M> ClientBuilder<H, S, Sub, F, M> {
         let cached_boss_list = (self.filter_map_message)(Message::BossList(&[]));
 
         let mut bosses = HashMap::new();
-        for boss_data in self.bosses.into_iter() {
+        for mut boss_data in self.bosses.into_iter() {
             let boss_name = boss_data.boss.name.clone();
+
+            // Metadata from before votes were stored starts with the current image
+            if boss_data.image_votes.is_empty() {
+                boss_data.image_votes = initial_image_votes(boss_data.boss.image.as_ref());
+            }
+
             let entry = RaidBossEntry {
+                image_history: initial_image_history(
+                    boss_data.boss.image.as_ref(),
+                    self.image_history_size,
+                ),
                 boss_data,
                 broadcast: Broadcast::new(),
                 recent_tweets: CircularBuffer::with_capacity(self.history_size),
+                rate_limiter: self.rate_limit.map(TokenBucket::new),
             };
 
             bosses.insert(boss_name, entry);
         }
 
+        let capture = self.capture_path.and_then(|path| match Capture::open(&path) {
+            Ok(capture) => Some(capture),
+            Err(e) => {
+                error!("Failed to open capture file {}: {}", path.display(), e);
+                None
+            }
+        });
+
         let mut worker = Worker {
             hash_requester,
             id_pool: IdPool::new(),
@@ -210,10 +446,27 @@ impl<H, S, Sub, F, M> ClientBuilder<H, S, Sub, F, M> {
             tweet_history_size: self.history_size,
             requested_bosses: HashMap::new(),
             subscribers: Broadcast::new(),
+            firehose: Broadcast::new(),
+            firehose_filters: HashMap::new(),
+            subscribed_at: HashMap::new(),
+            removed_boss_watchers: Vec::new(),
+            follow_patterns: HashMap::new(),
+            follow_limits: HashMap::new(),
             heartbeat: (self.filter_map_message)(Message::Heartbeat),
             filter_map_message: self.filter_map_message,
             cached_boss_list,
             metrics: self.metrics,
+            clock: self.clock,
+            last_seq: self.sequence_offset,
+            duplicate_window: self.duplicate_window,
+            default_boss_level: self.default_boss_level,
+            hooks: self.hooks,
+            rate_limit: self.rate_limit,
+            capture,
+            keep_raw_json: self.keep_raw_json,
+            image_history_size: self.image_history_size,
+            max_text_len: self.max_text_len,
+            dropped_events: 0,
         };
 
         worker.update_cached_boss_list();
@@ -1,8 +1,15 @@
-use super::{AsyncResult, Event, RemoveBossesPredicate, Subscription};
+use super::{AsyncResult, Event, RemoveBossesPredicate, Subscription, SubscriptionFilter};
+use chrono;
 use futures::unsync::{mpsc, oneshot};
 use id_pool::Id as SubId;
-use model::{BossName, RaidBoss, RaidBossMetadata, RaidTweet};
+use model::{BossFilter, BossImageUrl, BossName, BossSortKey, DateTime, RaidBoss,
+            RaidBossMetadata, RaidTweet, SubscriptionInfo, SubscriptionStats, TweetPage,
+            TweetPosition};
+use rate_limit::{RateLimit, RateLimitPolicy};
+use regex::Regex;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct Client<Sub, M = ()>(pub(crate) mpsc::UnboundedSender<Event<Sub, M>>);
@@ -24,7 +31,7 @@ impl<Sub, M> Client<Sub, M> {
     {
         let (tx, rx) = oneshot::channel();
         self.send(f(tx));
-        AsyncResult(rx)
+        AsyncResult::new(rx)
     }
 
     pub fn subscribe(&self, subscriber: Sub) -> AsyncResult<Subscription<Sub, M>> {
@@ -35,6 +42,20 @@ impl<Sub, M> Client<Sub, M> {
         })
     }
 
+    // Like `subscribe`, but also returns every boss, sorted by name. Both are
+    // taken at the same point, so every change to the bosses after the
+    // snapshot is sent to the subscriber as a `BossUpdate`.
+    pub fn subscribe_with_snapshot(
+        &self,
+        subscriber: Sub,
+    ) -> AsyncResult<(Vec<RaidBoss>, Subscription<Sub, M>)> {
+        self.request(|sender| Event::SubscriberSubscribeWithSnapshot {
+            subscriber,
+            sender,
+            client: self.clone(),
+        })
+    }
+
     pub(crate) fn subscriber_unsubscribe(&self, id: SubId) {
         self.send(Event::SubscriberUnsubscribe(id));
     }
@@ -43,10 +64,49 @@ impl<Sub, M> Client<Sub, M> {
         self.send(Event::SubscriberFollow { id, boss_name });
     }
 
+    pub(crate) fn subscriber_follow_many(&self, id: SubId, boss_names: Vec<BossName>) {
+        self.send(Event::SubscriberFollowMany { id, boss_names });
+    }
+
+    pub(crate) fn subscriber_follow_with_backlog(&self, id: SubId, boss_name: BossName) {
+        self.send(Event::SubscriberFollowWithBacklog { id, boss_name });
+    }
+
+    pub(crate) fn subscriber_follow_with_info(&self, id: SubId, boss_name: BossName) {
+        self.send(Event::SubscriberFollowWithInfo { id, boss_name });
+    }
+
+    pub(crate) fn subscriber_follow_rate_limited(
+        &self,
+        id: SubId,
+        boss_name: BossName,
+        limit: RateLimit,
+        policy: RateLimitPolicy,
+    ) {
+        self.send(Event::SubscriberFollowRateLimited {
+            id,
+            boss_name,
+            limit,
+            policy,
+        });
+    }
+
+    pub(crate) fn subscriber_follow_matching(&self, id: SubId, pattern: Regex) {
+        self.send(Event::SubscriberFollowMatching { id, pattern });
+    }
+
     pub(crate) fn subscriber_unfollow(&self, id: SubId, boss_name: BossName) {
         self.send(Event::SubscriberUnfollow { id, boss_name });
     }
 
+    pub(crate) fn subscriber_follow_all(&self, id: SubId, filter: SubscriptionFilter) {
+        self.send(Event::SubscriberFollowAll { id, filter });
+    }
+
+    pub(crate) fn subscriber_unfollow_all(&self, id: SubId) {
+        self.send(Event::SubscriberUnfollowAll(id));
+    }
+
     pub(crate) fn subscriber_get_bosses(&self, id: SubId) {
         self.send(Event::SubscriberGetBosses(id))
     }
@@ -55,8 +115,29 @@ impl<Sub, M> Client<Sub, M> {
         self.send(Event::SubscriberGetTweets { id, boss_name })
     }
 
+    // Sorted by name, like `bosses_filtered`
     pub fn bosses(&self) -> AsyncResult<Vec<RaidBoss>> {
-        self.request(Event::ClientGetBosses)
+        self.bosses_filtered(BossFilter::default())
+    }
+
+    pub fn bosses_sorted(&self, sort_key: BossSortKey) -> AsyncResult<Vec<RaidBoss>> {
+        self.request(|tx| Event::ClientGetBossesSorted {
+            sort_key,
+            sender: tx,
+        })
+    }
+
+    // Sorted by name, so the order is the same between calls
+    pub fn bosses_filtered(&self, filter: BossFilter) -> AsyncResult<Vec<RaidBoss>> {
+        self.request(|tx| Event::ClientGetBosses { filter, sender: tx })
+    }
+
+    // Returns bosses that have been seen within the given duration,
+    // most recently seen first
+    pub fn active_bosses(&self, within: Duration) -> AsyncResult<Vec<RaidBoss>> {
+        let within = chrono::Duration::from_std(within).unwrap_or(chrono::Duration::max_value());
+
+        self.request(|tx| Event::ClientGetActiveBosses { within, sender: tx })
     }
 
     pub fn tweets<B>(&self, boss_name: B) -> AsyncResult<Vec<Arc<RaidTweet>>>
@@ -69,6 +150,116 @@ impl<Sub, M> Client<Sub, M> {
         })
     }
 
+    // Like `tweets`, but returns `None` if the boss doesn't exist, rather
+    // than an empty list
+    pub fn tweets_checked<B>(&self, boss_name: B) -> AsyncResult<Option<Vec<Arc<RaidTweet>>>>
+    where
+        B: Into<BossName>,
+    {
+        self.request(|tx| Event::ClientGetTweetsChecked {
+            boss_name: boss_name.into(),
+            sender: tx,
+        })
+    }
+
+    // Images seen for the boss, oldest first. Empty unless
+    // `ClientBuilder::with_image_history` is set.
+    pub fn boss_images<B>(&self, boss_name: B) -> AsyncResult<Vec<BossImageUrl>>
+    where
+        B: Into<BossName>,
+    {
+        self.request(|tx| Event::ClientGetBossImages {
+            boss_name: boss_name.into(),
+            sender: tx,
+        })
+    }
+
+    // Fetches tweets for multiple bosses in a single request. Bosses that
+    // don't exist are left out of the result.
+    pub fn tweets_multi<I, B>(
+        &self,
+        boss_names: I,
+    ) -> AsyncResult<HashMap<BossName, Vec<Arc<RaidTweet>>>>
+    where
+        I: IntoIterator<Item = B>,
+        B: Into<BossName>,
+    {
+        self.request(|tx| Event::ClientGetTweetsMulti {
+            boss_names: boss_names.into_iter().map(Into::into).collect(),
+            sender: tx,
+        })
+    }
+
+    // Like `tweets`, but also includes tweets from the boss' translations,
+    // sorted by creation time, with duplicate raid IDs removed
+    pub fn tweets_combined<B>(&self, boss_name: B) -> AsyncResult<Vec<Arc<RaidTweet>>>
+    where
+        B: Into<BossName>,
+    {
+        self.request(|tx| Event::ClientGetTweetsCombined {
+            boss_name: boss_name.into(),
+            sender: tx,
+        })
+    }
+
+    // Returns the most recent tweets across all bosses, newest first
+    pub fn all_tweets(&self, limit: usize) -> AsyncResult<Vec<Arc<RaidTweet>>> {
+        self.request(|tx| Event::ClientGetAllTweets { limit, sender: tx })
+    }
+
+    // Returns tweets for the boss created after `since`, oldest first
+    pub fn tweets_since<B>(&self, boss_name: B, since: DateTime) -> AsyncResult<Vec<Arc<RaidTweet>>>
+    where
+        B: Into<BossName>,
+    {
+        self.request(|tx| Event::ClientGetTweetsSince {
+            boss_name: boss_name.into(),
+            since,
+            sender: tx,
+        })
+    }
+
+    // Returns up to `limit` tweets for the boss from before `before`, newest
+    // first. Passing the oldest returned tweet back in (as `&*tweet`) gets
+    // the next page, without skipping tweets from the same second.
+    pub fn tweets_before<B, P>(
+        &self,
+        boss_name: B,
+        before: P,
+        limit: usize,
+    ) -> AsyncResult<Vec<Arc<RaidTweet>>>
+    where
+        B: Into<BossName>,
+        P: Into<TweetPosition>,
+    {
+        self.request(|tx| Event::ClientGetTweetsBefore {
+            boss_name: boss_name.into(),
+            before: before.into(),
+            limit,
+            sender: tx,
+        })
+    }
+
+    // Returns tweets for the boss that arrived after `cursor`, oldest first.
+    // Start with a cursor of 0, then pass the returned cursor back in to
+    // only get tweets that haven't been seen yet.
+    pub fn tweets_after<B>(&self, boss_name: B, cursor: u64) -> AsyncResult<TweetPage>
+    where
+        B: Into<BossName>,
+    {
+        self.request(|tx| Event::ClientGetTweetsAfter {
+            boss_name: boss_name.into(),
+            cursor,
+            sender: tx,
+        })
+    }
+
+    // Like `tweets_after`, but across all bosses. Cursors from either one
+    // work with the other, since sequence numbers are shared by all bosses.
+    pub fn all_tweets_after(&self, cursor: u64) -> AsyncResult<TweetPage> {
+        self.request(|tx| Event::ClientGetAllTweetsAfter { cursor, sender: tx })
+    }
+
     pub fn export_metadata(&self) -> AsyncResult<Vec<RaidBossMetadata>> {
         self.request(Event::ClientExportMetadata)
     }
@@ -77,6 +268,50 @@ impl<Sub, M> Client<Sub, M> {
         self.request(Event::ClientExportMetrics)
     }
 
+    // Returns delivery stats for each followed boss of each subscription
+    pub fn subscription_stats(&self) -> AsyncResult<Vec<SubscriptionStats>> {
+        self.request(Event::ClientGetSubscriptionStats)
+    }
+
+    // Lists live subscriptions, sorted by ID
+    pub fn subscriptions(&self) -> AsyncResult<Vec<SubscriptionInfo>> {
+        self.request(Event::ClientGetSubscriptions)
+    }
+
+    // Total number of times a tweet wasn't received by a follower, because
+    // the subscriber's buffer was full. Per-subscription counts are in
+    // `subscription_stats`.
+    pub fn dropped_events(&self) -> AsyncResult<u64> {
+        self.request(Event::ClientGetDroppedEvents)
+    }
+
+    // The `seq` of the latest tweet, or the sequence offset if there hasn't
+    // been one. Passing it to `ClientBuilder::with_sequence_offset` after a
+    // restart keeps sequence numbers increasing, even for tweets that are no
+    // longer in any boss' history.
+    pub fn last_seq(&self) -> AsyncResult<u64> {
+        self.request(Event::ClientGetLastSeq)
+    }
+
+    // Stops sending messages to a subscription, using the ID from
+    // `Subscription::id`. The worker drops its copies of the subscriber, so
+    // channel-based subscribers see the end of their stream.
+    pub fn kill_subscription(&self, id: u32) {
+        self.send(Event::ClientKillSubscription(SubId::from_u32(id)));
+    }
+
+    // Resolves to whether the boss existed. Like `remove_bosses`, followers
+    // are kept, in case the boss shows up again.
+    pub fn remove_boss<B>(&self, boss_name: B) -> AsyncResult<bool>
+    where
+        B: Into<BossName>,
+    {
+        self.request(|tx| Event::ClientRemoveBoss {
+            boss_name: boss_name.into(),
+            sender: tx,
+        })
+    }
+
     pub fn remove_bosses<F>(&self, f: F)
     where
         F: Fn(&RaidBossMetadata) -> bool + 'static,
@@ -86,7 +321,23 @@ impl<Sub, M> Client<Sub, M> {
         ))));
     }
 
+    // Stream of bosses as they're removed, whether by `remove_boss` or
+    // `remove_bosses`. Ends when the worker stops.
+    pub fn removed_bosses(&self) -> mpsc::UnboundedReceiver<BossName> {
+        let (tx, rx) = mpsc::unbounded();
+        self.send(Event::ClientWatchRemovedBosses(tx));
+        rx
+    }
+
     pub fn heartbeat(&self) {
         self.send(Event::SubscriberHeartbeat);
     }
+
+    // Sends a heartbeat only to subscribers that haven't received a tweet
+    // within the given duration. Heartbeats don't count as deliveries, so an
+    // idle subscriber gets one every time this is called.
+    pub fn heartbeat_idle(&self, within: Duration) {
+        let within = chrono::Duration::from_std(within).unwrap_or(chrono::Duration::max_value());
+        self.send(Event::SubscriberHeartbeatIdle(within));
+    }
 }
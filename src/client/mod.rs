@@ -5,21 +5,29 @@ mod subscription;
 
 pub use self::builder::ClientBuilder;
 pub use self::client::Client;
-pub use self::subscription::Subscription;
+pub use self::subscription::{Subscription, SubscriptionFilter};
 pub use self::worker::Worker;
+use chrono::Duration;
 use error::*;
-use futures::{Future, Poll};
-use futures::unsync::oneshot;
+use futures::{future, Async, Future, Poll};
+use futures::unsync::{mpsc, oneshot};
 use id_pool::Id as SubId;
 use image_hash::ImageHash;
-use model::{BossName, RaidBoss, RaidBossMetadata, RaidTweet};
+use model::{BossFilter, BossImageUrl, BossName, BossSortKey, DateTime, RaidBoss,
+            RaidBossMetadata, RaidTweet, SubscriptionInfo, SubscriptionStats, TweetPage,
+            TweetPosition};
 use raid::RaidInfo;
+use rate_limit::{RateLimit, RateLimitPolicy};
+use regex::Regex;
+use std::collections::HashMap;
 use std::fmt;
+use std::rc::Rc;
 use std::sync::Arc;
+use tokio_core::reactor::{Handle, Timeout};
 
 #[derive(Debug)]
 pub(crate) enum Event<Sub, M> {
-    NewRaidInfo(RaidInfo),
+    NewRaidInfo(Box<RaidInfo>),
     NewImageHash {
         boss_name: BossName,
         image_hash: ImageHash,
@@ -29,32 +37,126 @@ pub(crate) enum Event<Sub, M> {
         id: SubId,
         boss_name: BossName,
     },
+    SubscriberFollowMany {
+        id: SubId,
+        boss_names: Vec<BossName>,
+    },
+    SubscriberFollowWithBacklog {
+        id: SubId,
+        boss_name: BossName,
+    },
+    SubscriberFollowWithInfo {
+        id: SubId,
+        boss_name: BossName,
+    },
+    SubscriberFollowRateLimited {
+        id: SubId,
+        boss_name: BossName,
+        limit: RateLimit,
+        policy: RateLimitPolicy,
+    },
+    SubscriberFollowMatching {
+        id: SubId,
+        pattern: Regex,
+    },
     SubscriberUnfollow {
         id: SubId,
         boss_name: BossName,
     },
+    SubscriberFollowAll {
+        id: SubId,
+        filter: SubscriptionFilter,
+    },
+    SubscriberUnfollowAll(SubId),
     SubscriberGetBosses(SubId),
     SubscriberGetTweets {
         id: SubId,
         boss_name: BossName,
     },
     SubscriberHeartbeat,
+    SubscriberHeartbeatIdle(Duration),
 
     SubscriberSubscribe {
         subscriber: Sub,
         client: Client<Sub, M>,
         sender: oneshot::Sender<Subscription<Sub, M>>,
     },
+    SubscriberSubscribeWithSnapshot {
+        subscriber: Sub,
+        client: Client<Sub, M>,
+        sender: oneshot::Sender<(Vec<RaidBoss>, Subscription<Sub, M>)>,
+    },
     SubscriberUnsubscribe(SubId),
 
-    ClientGetBosses(oneshot::Sender<Vec<RaidBoss>>),
+    ClientGetBosses {
+        filter: BossFilter,
+        sender: oneshot::Sender<Vec<RaidBoss>>,
+    },
+    ClientGetBossesSorted {
+        sort_key: BossSortKey,
+        sender: oneshot::Sender<Vec<RaidBoss>>,
+    },
+    ClientGetActiveBosses {
+        within: Duration,
+        sender: oneshot::Sender<Vec<RaidBoss>>,
+    },
     ClientGetTweets {
         boss_name: BossName,
         sender: oneshot::Sender<Vec<Arc<RaidTweet>>>,
     },
+    ClientGetTweetsChecked {
+        boss_name: BossName,
+        sender: oneshot::Sender<Option<Vec<Arc<RaidTweet>>>>,
+    },
+    ClientGetBossImages {
+        boss_name: BossName,
+        sender: oneshot::Sender<Vec<BossImageUrl>>,
+    },
+    ClientGetTweetsMulti {
+        boss_names: Vec<BossName>,
+        sender: oneshot::Sender<HashMap<BossName, Vec<Arc<RaidTweet>>>>,
+    },
+    ClientGetTweetsCombined {
+        boss_name: BossName,
+        sender: oneshot::Sender<Vec<Arc<RaidTweet>>>,
+    },
+    ClientGetAllTweets {
+        limit: usize,
+        sender: oneshot::Sender<Vec<Arc<RaidTweet>>>,
+    },
+    ClientGetTweetsSince {
+        boss_name: BossName,
+        since: DateTime,
+        sender: oneshot::Sender<Vec<Arc<RaidTweet>>>,
+    },
+    ClientGetTweetsBefore {
+        boss_name: BossName,
+        before: TweetPosition,
+        limit: usize,
+        sender: oneshot::Sender<Vec<Arc<RaidTweet>>>,
+    },
+    ClientGetTweetsAfter {
+        boss_name: BossName,
+        cursor: u64,
+        sender: oneshot::Sender<TweetPage>,
+    },
+    ClientGetAllTweetsAfter {
+        cursor: u64,
+        sender: oneshot::Sender<TweetPage>,
+    },
     ClientExportMetadata(oneshot::Sender<Vec<RaidBossMetadata>>),
     ClientExportMetrics(oneshot::Sender<M>),
+    ClientGetSubscriptionStats(oneshot::Sender<Vec<SubscriptionStats>>),
+    ClientGetSubscriptions(oneshot::Sender<Vec<SubscriptionInfo>>),
+    ClientGetDroppedEvents(oneshot::Sender<u64>),
+    ClientGetLastSeq(oneshot::Sender<u64>),
+    ClientKillSubscription(SubId),
+    ClientRemoveBoss {
+        boss_name: BossName,
+        sender: oneshot::Sender<bool>,
+    },
     ClientRemoveBosses(RemoveBossesPredicate),
+    ClientWatchRemovedBosses(mpsc::UnboundedSender<BossName>),
 
     ClientReadError,
 }
@@ -67,12 +169,138 @@ impl fmt::Debug for RemoveBossesPredicate {
     }
 }
 
-pub struct AsyncResult<T>(oneshot::Receiver<T>);
+// Callbacks set on `ClientBuilder`. These are `Rc` so the builder can
+// still be cloned.
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    pub(crate) on_new_boss: Option<Hook<RaidBoss>>,
+    pub(crate) on_raid: Option<Hook<RaidTweet>>,
+    pub(crate) on_boss_removed: Option<Hook<BossName>>,
+}
+pub(crate) type Hook<T> = Rc<Fn(&T)>;
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> ::std::result::Result<(), fmt::Error> {
+        f.debug_struct("Hooks")
+            .field("on_new_boss", &self.on_new_boss.is_some())
+            .field("on_raid", &self.on_raid.is_some())
+            .field("on_boss_removed", &self.on_boss_removed.is_some())
+            .finish()
+    }
+}
+
+// Resolves to the worker's response to a request. Fails with
+// `ErrorKind::Closed` if the worker stopped before responding, or with
+// `ErrorKind::Timeout` if a timeout is set and runs out first.
+pub struct AsyncResult<T> {
+    receiver: oneshot::Receiver<T>,
+    timeout: Option<Timeout>,
+}
+
+impl<T> AsyncResult<T> {
+    pub(crate) fn new(receiver: oneshot::Receiver<T>) -> Self {
+        AsyncResult {
+            receiver,
+            timeout: None,
+        }
+    }
+
+    // Stops waiting for the worker after the duration, e.g. if the worker is
+    // no longer being polled. If the timer can't be created, the error is
+    // logged, and the result waits without a timeout.
+    pub fn timeout(mut self, duration: ::std::time::Duration, handle: &Handle) -> Self {
+        match Timeout::new(duration, handle) {
+            Ok(timeout) => self.timeout = Some(timeout),
+            Err(e) => error!("Failed to create request timeout: {}", e),
+        }
+        self
+    }
+
+    // Same as `Future::map`, without needing the trait in scope
+    pub fn map<U, F>(self, f: F) -> future::Map<Self, F>
+    where
+        F: FnOnce(T) -> U,
+    {
+        Future::map(self, f)
+    }
+
+    pub fn into_boxed(self) -> Box<Future<Item = T, Error = Error>>
+    where
+        T: 'static,
+    {
+        Box::new(self)
+    }
+}
+
+impl<T> fmt::Debug for AsyncResult<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> ::std::result::Result<(), fmt::Error> {
+        f.write_str("AsyncResult")
+    }
+}
+
+impl<T> From<AsyncResult<T>> for Box<Future<Item = T, Error = Error>>
+where
+    T: 'static,
+{
+    fn from(result: AsyncResult<T>) -> Self {
+        result.into_boxed()
+    }
+}
+
 impl<T> Future for AsyncResult<T> {
     type Item = T;
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        self.0.poll().map_err(|_| ErrorKind::Closed.into())
+        match self.receiver.poll() {
+            Ok(Async::NotReady) => {}
+            other => return other.map_err(|_| ErrorKind::Closed.into()),
+        }
+
+        match self.timeout {
+            Some(ref mut timeout) => match timeout.poll() {
+                Ok(Async::NotReady) => Ok(Async::NotReady),
+                Ok(Async::Ready(())) => bail!(ErrorKind::Timeout),
+                Err(e) => Err(Error::with_chain(e, ErrorKind::Timeout)),
+            },
+            None => Ok(Async::NotReady),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio_core::reactor::Core;
+
+    #[test]
+    fn async_result() {
+        let (tx, rx) = oneshot::channel();
+        tx.send(2).unwrap();
+        assert_eq!(AsyncResult::new(rx).map(|n| n * 10).wait().unwrap(), 20);
+
+        let (tx, rx) = oneshot::channel::<()>();
+        drop(tx);
+        let boxed: Box<Future<Item = (), Error = Error>> = AsyncResult::new(rx).into();
+        match boxed.wait() {
+            Err(Error(ErrorKind::Closed, _)) => {}
+            other => panic!("expected Closed error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn async_result_timeout() {
+        let mut core = Core::new().unwrap();
+        let timeout = ::std::time::Duration::from_millis(10);
+
+        let (_tx, rx) = oneshot::channel::<()>();
+        match core.run(AsyncResult::new(rx).timeout(timeout, &core.handle())) {
+            Err(Error(ErrorKind::Timeout, _)) => {}
+            other => panic!("expected Timeout error, got {:?}", other),
+        }
+
+        let (tx, rx) = oneshot::channel();
+        tx.send(2).unwrap();
+        let result = AsyncResult::new(rx).timeout(timeout, &core.handle());
+        assert_eq!(core.run(result).unwrap(), 2);
     }
 }
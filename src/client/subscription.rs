@@ -1,7 +1,56 @@
 pub use client::Client;
 use id_pool::Id as SubId;
-use model::BossName;
+use model::{level_matches, BossLevel, BossName, Language, RaidBoss};
+use rate_limit::{RateLimit, RateLimitPolicy};
+use regex::Regex;
 use std::collections::HashSet;
+use std::ops::RangeInclusive;
+
+// Restricts which bosses' tweets are received by `follow_all_filtered`.
+// Bosses with an unknown level are excluded if `min_level` is set, unless
+// `include_unknown_level` is true. `language` is matched against the boss'
+// language, so tweets for a translated boss are still filtered by the
+// language of the boss in the tweet.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SubscriptionFilter {
+    pub min_level: Option<BossLevel>,
+    pub max_level: Option<BossLevel>,
+    pub include_unknown_level: bool,
+    pub language: Option<Language>,
+}
+
+impl SubscriptionFilter {
+    // Only bosses with a known level within the range
+    pub fn levels(range: RangeInclusive<BossLevel>) -> Self {
+        SubscriptionFilter {
+            min_level: Some(*range.start()),
+            max_level: Some(*range.end()),
+            ..SubscriptionFilter::default()
+        }
+    }
+
+    pub fn matches(&self, boss: &RaidBoss) -> bool {
+        self.matches_with_default(boss, None)
+    }
+
+    // Bosses with `default_level` are the ones whose level is unknown
+    pub(crate) fn matches_with_default(
+        &self,
+        boss: &RaidBoss,
+        default_level: Option<BossLevel>,
+    ) -> bool {
+        if self.language.map(|lang| lang != boss.language).unwrap_or(false) {
+            return false;
+        }
+
+        let level = match boss.level {
+            Some(level) if boss.level != default_level => Some(level),
+            _ => None,
+        };
+
+        level_matches(level, self.min_level, self.max_level, self.include_unknown_level)
+    }
+}
 
 // TODO: Figure out if there is a way to do this without owning `Client`
 #[must_use = "Subscriptions are cancelled when they go out of scope"]
@@ -13,6 +62,10 @@ pub struct Subscription<Sub, M = ()> {
 }
 
 impl<Sub, M> Subscription<Sub, M> {
+    pub fn id(&self) -> u32 {
+        self.id.as_u32()
+    }
+
     pub fn follow<B>(&mut self, boss_name: B)
     where
         B: Into<BossName>,
@@ -22,6 +75,66 @@ impl<Sub, M> Subscription<Sub, M> {
         self.client.subscriber_follow(self.id.clone(), name);
     }
 
+    // Follows multiple bosses with a single request to the worker
+    pub fn follow_many<I, B>(&mut self, boss_names: I)
+    where
+        I: IntoIterator<Item = B>,
+        B: Into<BossName>,
+    {
+        let names = boss_names.into_iter().map(Into::into).collect::<Vec<_>>();
+        self.following.extend(names.iter().cloned());
+        self.client.subscriber_follow_many(self.id.clone(), names);
+    }
+
+    // Like `follow`, but first sends the boss' recent tweets, oldest first.
+    // No tweets are missed or repeated between the backlog and new tweets.
+    pub fn follow_with_backlog<B>(&mut self, boss_name: B)
+    where
+        B: Into<BossName>,
+    {
+        let name = boss_name.into();
+        self.following.insert(name.clone());
+        self.client
+            .subscriber_follow_with_backlog(self.id.clone(), name);
+    }
+
+    // Like `follow`, but first sends a `BossUpdate` for the boss, so clients
+    // can show its level and image before any tweets. If the boss hasn't
+    // been seen yet, the `BossUpdate` sent to all subscribers when it's
+    // first seen comes right before its first tweet.
+    pub fn follow_with_info<B>(&mut self, boss_name: B)
+    where
+        B: Into<BossName>,
+    {
+        let name = boss_name.into();
+        self.following.insert(name.clone());
+        self.client.subscriber_follow_with_info(self.id.clone(), name);
+    }
+
+    // Like `follow`, but tweets for this boss over the limit aren't sent to
+    // this subscription. Following the boss again replaces the limit.
+    pub fn follow_rate_limited<B>(
+        &mut self,
+        boss_name: B,
+        limit: RateLimit,
+        policy: RateLimitPolicy,
+    ) where
+        B: Into<BossName>,
+    {
+        let name = boss_name.into();
+        self.following.insert(name.clone());
+        self.client
+            .subscriber_follow_rate_limited(self.id.clone(), name, limit, policy);
+    }
+
+    // Follows every boss whose name matches, including bosses that haven't
+    // been seen yet. The pattern is only run once per boss, not per tweet.
+    // Patterns stay followed until the subscription is dropped, and the
+    // worker ignores patterns past a fixed limit.
+    pub fn follow_matching(&mut self, pattern: Regex) {
+        self.client.subscriber_follow_matching(self.id.clone(), pattern);
+    }
+
     pub fn unfollow<B>(&mut self, boss_name: B)
     where
         B: Into<BossName>,
@@ -31,6 +144,24 @@ impl<Sub, M> Subscription<Sub, M> {
         self.client.subscriber_unfollow(self.id.clone(), name);
     }
 
+    // Receive tweets for every boss, including ones that haven't been seen
+    // yet. Tweets for bosses that are also followed individually will be
+    // received twice.
+    pub fn follow_all(&mut self) {
+        self.follow_all_filtered(SubscriptionFilter::default())
+    }
+
+    // Like `follow_all`, but only for bosses that match the filter. Calling
+    // this again replaces the existing filter.
+    pub fn follow_all_filtered(&mut self, filter: SubscriptionFilter) {
+        self.client.subscriber_follow_all(self.id.clone(), filter);
+    }
+
+    pub fn unfollow_all(&mut self) {
+        self.client.subscriber_unfollow_all(self.id.clone());
+    }
+
+    // Sends a `BossList`, sorted by name
     pub fn get_bosses(&self) {
         self.client.subscriber_get_bosses(self.id.clone())
     }
@@ -65,3 +196,39 @@ impl<Sub, M> Drop for Subscription<Sub, M> {
         self.non_consuming_unsubscribe();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn boss(level: Option<BossLevel>, language: Language) -> RaidBoss {
+        RaidBoss {
+            name: format!("Lvl {:?} Ozorotter", level).into(),
+            level,
+            image: None,
+            language,
+            translations: HashSet::new(),
+            element: None,
+        }
+    }
+
+    #[test]
+    fn filter_levels() {
+        let filter = SubscriptionFilter::levels(100..=150);
+
+        assert!(!filter.matches(&boss(None, Language::English)));
+        assert!(!filter.matches(&boss(Some(75), Language::English)));
+        assert!(filter.matches(&boss(Some(100), Language::English)));
+        assert!(filter.matches(&boss(Some(150), Language::Japanese)));
+        assert!(!filter.matches(&boss(Some(200), Language::English)));
+    }
+
+    #[test]
+    fn filter_default_matches_all() {
+        let filter = SubscriptionFilter::default();
+
+        assert!(filter.matches(&boss(None, Language::English)));
+        assert!(filter.matches(&boss(Some(200), Language::Japanese)));
+    }
+}
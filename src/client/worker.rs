@@ -1,6 +1,9 @@
-use super::{Event, Subscription};
-use broadcast::{Broadcast, Subscriber};
+use super::{Event, Hooks, Subscription, SubscriptionFilter};
+use broadcast::{Broadcast, DeliveryStats, Subscriber};
+use capture::Capture;
+use chrono;
 use circular_buffer::CircularBuffer;
+use clock::Clock;
 use error::*;
 use futures::{Async, Future, Poll, Stream};
 use futures::stream::{Chain, FilterMap, Map, Once, OrElse, Select};
@@ -8,23 +11,129 @@ use futures::unsync::mpsc;
 use id_pool::{Id as SubId, IdPool};
 use image_hash::{BossImageHash, ImageHash, ImageHashReceiver, ImageHashSender, ImageHasher};
 use metrics::Metrics;
-use model::{BossLevel, BossName, Message, RaidBoss, RaidBossMetadata, RaidTweet};
+use model::{BossFilter, BossImageUrl, BossLevel, BossName, BossSortKey, DateTime, Message,
+            RaidBoss, RaidBossMetadata, RaidTweet, SubscriptionInfo, SubscriptionStats,
+            TweetPage};
 use raid::RaidInfo;
+use rate_limit::{RateLimit, RateLimitPolicy, TokenBucket};
+use regex::Regex;
 use std::collections::{HashMap, HashSet};
+use std::cmp::Reverse;
 use std::collections::hash_map::Entry;
 use std::iter::FromIterator;
 use std::sync::Arc;
 
-const DEFAULT_BOSS_LEVEL: BossLevel = 0;
+// Total across all subscriptions. Each pattern is checked against every
+// boss when it's followed, and against new bosses when they're first seen.
+const MAX_FOLLOW_PATTERNS: usize = 100;
+
+// Distinct images counted per boss. Bosses rarely have more than a few.
+const MAX_IMAGE_VOTES: usize = 8;
 
 pub(crate) struct RaidBossEntry<Sub> {
     pub(crate) boss_data: RaidBossMetadata,
     pub(crate) recent_tweets: CircularBuffer<Arc<RaidTweet>>,
     pub(crate) broadcast: Broadcast<SubId, Sub>,
+    pub(crate) rate_limiter: Option<TokenBucket>,
+    // Distinct images seen, if `ClientBuilder::with_image_history` is set
+    pub(crate) image_history: Option<CircularBuffer<BossImageUrl>>,
+}
+
+pub(crate) fn initial_image_history(
+    image: Option<&BossImageUrl>,
+    size: usize,
+) -> Option<CircularBuffer<BossImageUrl>> {
+    if size == 0 {
+        return None;
+    }
+
+    let mut history = CircularBuffer::with_capacity(size);
+    if let Some(image) = image {
+        history.push(image.clone());
+    }
+    Some(history)
+}
+
+// Images that only differ in size are only kept once. Seeing one again
+// moves it to the back, so the least recently seen image is evicted first.
+fn record_image(history: &mut CircularBuffer<BossImageUrl>, image: &BossImageUrl) {
+    let key = image_vote_key(image);
+    let seen = history.remove_first(|seen| image_vote_key(seen) == key);
+    history.push(seen.unwrap_or_else(|| image.clone()));
+}
+
+pub(crate) fn initial_image_votes(image: Option<&BossImageUrl>) -> HashMap<BossImageUrl, u32> {
+    let mut votes = HashMap::new();
+    if let Some(image) = image {
+        votes.insert(image_vote_key(image), 1);
+    }
+    votes
+}
+
+fn image_vote_key(image: &BossImageUrl) -> BossImageUrl {
+    BossImageUrl::from(image.split_variant().0)
+}
+
+// Counts a vote for the image, and returns true if it now has more votes
+// than the boss' current image. Once the table is full, the least voted
+// image other than the current one is forgotten to make room.
+fn vote_image(
+    votes: &mut HashMap<BossImageUrl, u32>,
+    current: Option<&BossImageUrl>,
+    image: &BossImageUrl,
+) -> bool {
+    let key = image_vote_key(image);
+    let current_key = current.map(image_vote_key);
+
+    if !votes.contains_key(&key) && votes.len() >= MAX_IMAGE_VOTES {
+        let least_voted = votes
+            .iter()
+            .filter(|&(k, _)| Some(k) != current_key.as_ref())
+            .min_by_key(|&(_, count)| *count)
+            .map(|(k, _)| k.clone());
+
+        if let Some(k) = least_voted {
+            votes.remove(&k);
+        }
+    }
+
+    let count = {
+        let count = votes.entry(key.clone()).or_insert(0);
+        *count = count.saturating_add(1);
+        *count
+    };
+
+    match current_key {
+        None => true,
+        Some(ref current_key) if *current_key == key => false,
+        Some(ref current_key) => count > votes.get(current_key).cloned().unwrap_or(0),
+    }
+}
+
+// Keeps at most `max_len` characters, including the ellipsis. Cutting at a
+// char index means multi-byte characters are never split.
+fn truncate_text(text: &mut String, max_len: usize) {
+    if text.chars().count() <= max_len {
+        return;
+    }
+
+    let keep = max_len.saturating_sub(1);
+    let end = text.char_indices().nth(keep).map_or(text.len(), |(i, _)| i);
+    text.truncate(end);
+    if max_len > 0 {
+        text.push('…');
+    }
+}
+
+pub(crate) struct FollowLimit {
+    bucket: TokenBucket,
+    policy: RateLimitPolicy,
+    // Tweets not sent since the last `Message::Skipped`
+    skipped: u64,
 }
 
 #[must_use = "futures do nothing unless polled"]
-pub struct Worker<H, S, Sub, F, M>
+pub struct Worker<H, S, Sub, F, M, C>
 where
     Sub: Subscriber,
     H: ImageHasher,
@@ -47,18 +156,40 @@ where
     pub(crate) tweet_history_size: usize,
     pub(crate) requested_bosses: HashMap<BossName, Broadcast<SubId, Sub>>,
     pub(crate) subscribers: Broadcast<SubId, Sub>,
+    pub(crate) firehose: Broadcast<SubId, Sub>,
+    pub(crate) firehose_filters: HashMap<SubId, SubscriptionFilter>,
+    pub(crate) subscribed_at: HashMap<SubId, DateTime>,
+    pub(crate) removed_boss_watchers: Vec<mpsc::UnboundedSender<BossName>>,
+    pub(crate) hooks: Hooks,
     pub(crate) filter_map_message: F,
     pub(crate) cached_boss_list: Option<Sub::Item>,
     pub(crate) heartbeat: Option<Sub::Item>,
     pub(crate) metrics: M,
+    pub(crate) clock: C,
+    pub(crate) last_seq: u64,
+    pub(crate) duplicate_window: Option<chrono::Duration>,
+    pub(crate) default_boss_level: Option<BossLevel>,
+    pub(crate) rate_limit: Option<RateLimit>,
+    pub(crate) follow_patterns: HashMap<SubId, Vec<Regex>>,
+    // Keyed by boss name first, so bosses with no rate limited followers
+    // only need one lookup per tweet
+    pub(crate) follow_limits: HashMap<BossName, HashMap<SubId, FollowLimit>>,
+    pub(crate) capture: Option<Capture>,
+    pub(crate) keep_raw_json: bool,
+    pub(crate) image_history_size: usize,
+    pub(crate) max_text_len: Option<usize>,
+    // Tweets that weren't received by a follower, because the subscriber
+    // was full or failed
+    pub(crate) dropped_events: u64,
 }
 
-impl<H, S, Sub, F, M> Worker<H, S, Sub, F, M>
+impl<H, S, Sub, F, M, C> Worker<H, S, Sub, F, M, C>
 where
     H: ImageHasher,
     Sub: Subscriber + Clone,
     F: Fn(Message) -> Option<Sub::Item>,
     M: Metrics,
+    C: Clock,
 {
     fn handle_event(&mut self, event: Event<Sub, M::Export>) {
         use super::Event::*;
@@ -76,15 +207,86 @@ where
                     client,
                 });
             }
+            SubscriberSubscribeWithSnapshot {
+                subscriber,
+                sender,
+                client,
+            } => {
+                let bosses = self.bosses_filtered(&BossFilter::default());
+                let id = self.subscribe(subscriber);
+                let subscription = Subscription {
+                    id,
+                    following: HashSet::new(),
+                    client,
+                };
+                let _ = sender.send((bosses, subscription));
+            }
             SubscriberUnsubscribe(id) => {
                 self.unsubscribe(&id);
             }
             SubscriberFollow { id, boss_name } => {
                 self.follow(id, boss_name);
             }
+            SubscriberFollowMany { id, boss_names } => {
+                for boss_name in boss_names {
+                    self.follow(id.clone(), boss_name);
+                }
+            }
+            SubscriberFollowWithBacklog { id, boss_name } => {
+                self.send_backlog(&id, &boss_name);
+                self.follow(id, boss_name);
+            }
+            SubscriberFollowWithInfo { id, boss_name } => {
+                let boss = self.bosses.get(&boss_name).map(|e| &e.boss_data.boss);
+
+                if let (Some(sub), Some(boss)) = (self.subscribers.get_mut(&id), boss) {
+                    let message = (self.filter_map_message)(Message::BossUpdate(boss));
+                    let _ = sub.maybe_send(message.as_ref());
+                }
+
+                self.follow(id, boss_name);
+            }
+            SubscriberFollowRateLimited {
+                id,
+                boss_name,
+                limit,
+                policy,
+            } => {
+                self.follow(id.clone(), boss_name.clone());
+
+                if self.subscribers.get(&id).is_some() {
+                    let follow_limit = FollowLimit {
+                        bucket: TokenBucket::new(limit),
+                        policy,
+                        skipped: 0,
+                    };
+
+                    match self.follow_limits.entry(boss_name) {
+                        Entry::Occupied(mut entry) => {
+                            entry.get_mut().insert(id, follow_limit);
+                        }
+                        Entry::Vacant(entry) => {
+                            entry.insert(HashMap::from_iter(Some((id, follow_limit))));
+                        }
+                    }
+                }
+            }
+            SubscriberFollowMatching { id, pattern } => {
+                self.follow_matching(id, pattern);
+            }
             SubscriberUnfollow { id, boss_name } => {
                 self.unfollow(&id, boss_name);
             }
+            SubscriberFollowAll { id, filter } => {
+                if let Some(sub) = self.subscribers.get(&id) {
+                    self.firehose.subscribe(id.clone(), sub.clone());
+                    self.firehose_filters.insert(id, filter);
+                }
+            }
+            SubscriberUnfollowAll(id) => {
+                self.firehose.unsubscribe(&id);
+                self.firehose_filters.remove(&id);
+            }
             SubscriberGetBosses(id) => {
                 if let Some(sub) = self.subscribers.get_mut(&id) {
                     let _ = sub.maybe_send(self.cached_boss_list.as_ref());
@@ -101,10 +303,25 @@ where
                     let _ = sub.maybe_send(message.as_ref());
                 }
             }
-            SubscriberHeartbeat => self.subscribers.maybe_send(self.heartbeat.as_ref()),
+            SubscriberHeartbeat => {
+                self.subscribers.maybe_send(self.heartbeat.as_ref());
+                self.remove_closed_subscribers();
+            }
+            SubscriberHeartbeatIdle(within) => {
+                let now = self.clock.now();
+                let last_delivered = self.last_delivered_times();
+
+                self.subscribers.maybe_send_at_where(self.heartbeat.as_ref(), now, |id| {
+                    last_delivered
+                        .get(id)
+                        .map(|at| now.signed_duration_since(*at) > within)
+                        .unwrap_or(true)
+                });
+                self.remove_closed_subscribers();
+            }
 
             NewRaidInfo(r) => {
-                self.handle_raid_info(r);
+                self.handle_raid_info(*r);
             }
             NewImageHash {
                 boss_name,
@@ -113,9 +330,43 @@ where
                 self.handle_image_hash(boss_name, image_hash);
             }
 
-            ClientGetBosses(tx) => {
-                let _ = tx.send(Vec::from_iter(
-                    self.bosses.values().map(|e| e.boss_data.boss.clone()),
+            ClientGetBosses { filter, sender } => {
+                let _ = sender.send(self.bosses_filtered(&filter));
+            }
+            ClientGetBossesSorted { sort_key, sender } => {
+                let mut bosses = self.bosses
+                    .values()
+                    .map(|e| &e.boss_data)
+                    .collect::<Vec<_>>();
+
+                match sort_key {
+                    BossSortKey::Level => bosses.sort_by(|a, b| {
+                        // Unknown levels go last
+                        let a = (a.boss.level.is_none(), a.boss.level, a.boss.name.as_str());
+                        let b = (b.boss.level.is_none(), b.boss.level, b.boss.name.as_str());
+                        a.cmp(&b)
+                    }),
+                    BossSortKey::LastSeen => bosses.sort_by_key(|data| Reverse(data.last_seen)),
+                    BossSortKey::Name => bosses.sort_by(|a, b| a.boss.name.cmp(&b.boss.name)),
+                }
+
+                let _ = sender.send(Vec::from_iter(
+                    bosses.into_iter().map(|data| data.boss.clone()),
+                ));
+            }
+            ClientGetActiveBosses { within, sender } => {
+                let now = self.clock.now();
+
+                let mut active = self.bosses
+                    .values()
+                    .map(|e| &e.boss_data)
+                    .filter(|data| now.signed_duration_since(data.last_seen) <= within)
+                    .collect::<Vec<_>>();
+
+                active.sort_by_key(|data| Reverse(data.last_seen));
+
+                let _ = sender.send(Vec::from_iter(
+                    active.into_iter().map(|data| data.boss.clone()),
                 ));
             }
             ClientGetTweets { boss_name, sender } => {
@@ -127,6 +378,142 @@ where
 
                 let _ = sender.send(tweets);
             }
+            ClientGetTweetsChecked { boss_name, sender } => {
+                let tweets = self.bosses
+                    .get(&boss_name)
+                    .map(|e| e.recent_tweets.as_unordered_slice().to_vec());
+
+                let _ = sender.send(tweets);
+            }
+            ClientGetBossImages { boss_name, sender } => {
+                let images = self.bosses
+                    .get(&boss_name)
+                    .and_then(|e| e.image_history.as_ref())
+                    .map_or(vec![], |history| {
+                        let (older, newer) = history.as_slices();
+                        older.iter().chain(newer).cloned().collect()
+                    });
+
+                let _ = sender.send(images);
+            }
+            ClientGetTweetsMulti { boss_names, sender } => {
+                let tweets = boss_names
+                    .into_iter()
+                    .filter_map(|name| {
+                        self.bosses.get(&name).map(|e| {
+                            let tweets = e.recent_tweets.as_unordered_slice().to_vec();
+                            (name, tweets)
+                        })
+                    })
+                    .collect();
+
+                let _ = sender.send(tweets);
+            }
+            ClientGetTweetsCombined { boss_name, sender } => {
+                let tweets = self.bosses.get(&boss_name).map_or(vec![], |e| {
+                    let translations = &e.boss_data.boss.translations;
+
+                    if translations.is_empty() {
+                        e.recent_tweets.as_unordered_slice().to_vec()
+                    } else {
+                        let buffers = translations
+                            .iter()
+                            .filter_map(|name| self.bosses.get(name))
+                            .chain(Some(e))
+                            .map(|entry| entry.recent_tweets.as_unordered_slice());
+
+                        merge_tweets(buffers, self.tweet_history_size * 2)
+                    }
+                });
+
+                let _ = sender.send(tweets);
+            }
+            ClientGetAllTweets { limit, sender } => {
+                let mut tweets = self.bosses
+                    .values()
+                    .flat_map(|e| e.recent_tweets.as_unordered_slice().iter().cloned())
+                    .collect::<Vec<_>>();
+
+                // Tweets for bosses with translations are stored in
+                // multiple buffers, but share the same sequence number
+                tweets.sort_by_key(|t| Reverse((t.created_at, t.seq)));
+                tweets.dedup_by_key(|t| t.seq);
+                tweets.truncate(limit);
+
+                let _ = sender.send(tweets);
+            }
+            ClientGetTweetsSince {
+                boss_name,
+                since,
+                sender,
+            } => {
+                let mut tweets = self.bosses.get(&boss_name).map_or(vec![], |e| {
+                    e.recent_tweets
+                        .as_unordered_slice()
+                        .iter()
+                        .filter(|t| t.created_at > since)
+                        .cloned()
+                        .collect()
+                });
+
+                tweets.sort_by_key(|t| (t.created_at, t.seq));
+
+                let _ = sender.send(tweets);
+            }
+            ClientGetTweetsBefore {
+                boss_name,
+                before,
+                limit,
+                sender,
+            } => {
+                let mut tweets = self.bosses.get(&boss_name).map_or(vec![], |e| {
+                    e.recent_tweets
+                        .as_unordered_slice()
+                        .iter()
+                        .filter(|t| t.position() < before)
+                        .cloned()
+                        .collect()
+                });
+
+                tweets.sort_by_key(|t| Reverse(t.position()));
+                tweets.truncate(limit);
+
+                let _ = sender.send(tweets);
+            }
+            ClientGetTweetsAfter {
+                boss_name,
+                cursor,
+                sender,
+            } => {
+                let mut tweets = self.bosses.get(&boss_name).map_or(vec![], |e| {
+                    e.recent_tweets
+                        .as_unordered_slice()
+                        .iter()
+                        .filter(|t| t.seq > cursor)
+                        .cloned()
+                        .collect()
+                });
+
+                tweets.sort_by_key(|t| t.seq);
+
+                let cursor = tweets.last().map_or(cursor, |t| t.seq);
+                let _ = sender.send(TweetPage { tweets, cursor });
+            }
+            ClientGetAllTweetsAfter { cursor, sender } => {
+                let mut tweets = self.bosses
+                    .values()
+                    .flat_map(|e| e.recent_tweets.as_unordered_slice().iter())
+                    .filter(|t| t.seq > cursor)
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                // Translated bosses share tweets, like in `ClientGetAllTweets`
+                tweets.sort_by_key(|t| t.seq);
+                tweets.dedup_by_key(|t| t.seq);
+
+                let cursor = tweets.last().map_or(cursor, |t| t.seq);
+                let _ = sender.send(TweetPage { tweets, cursor });
+            }
             ClientExportMetadata(tx) => {
                 let _ = tx.send(Vec::from_iter(
                     self.bosses.values().map(|e| e.boss_data.clone()),
@@ -135,45 +522,128 @@ where
             ClientExportMetrics(tx) => {
                 let _ = tx.send(self.metrics.export());
             }
+            ClientGetSubscriptionStats(tx) => {
+                let followed = self.bosses
+                    .iter()
+                    .map(|(name, e)| (Some(name), &e.broadcast))
+                    .chain(self.requested_bosses.iter().map(|(name, b)| (Some(name), b)))
+                    .chain(Some((None, &self.firehose)));
+
+                let mut stats = Vec::new();
+                for (boss_name, broadcast) in followed {
+                    stats.extend(broadcast.stats().map(|(id, _, delivery)| {
+                        subscription_stats(id, boss_name, delivery)
+                    }));
+                }
+
+                stats.sort_by(|a, b| {
+                    let a = (a.subscription_id, a.boss_name.as_ref().map(|n| n.as_str()));
+                    let b = (b.subscription_id, b.boss_name.as_ref().map(|n| n.as_str()));
+                    a.cmp(&b)
+                });
+                let _ = tx.send(stats);
+            }
+            ClientGetSubscriptions(tx) => {
+                let _ = tx.send(self.subscription_infos());
+            }
+            ClientGetDroppedEvents(tx) => {
+                let _ = tx.send(self.dropped_events);
+            }
+            ClientGetLastSeq(tx) => {
+                let _ = tx.send(self.last_seq);
+            }
+            ClientKillSubscription(id) => {
+                self.kill_subscription(&id);
+            }
+            ClientRemoveBoss { boss_name, sender } => {
+                let _ = sender.send(self.remove_boss(&boss_name));
+            }
             ClientRemoveBosses(f) => {
                 self.remove_bosses(f.0);
             }
+            ClientWatchRemovedBosses(tx) => {
+                self.removed_boss_watchers.push(tx);
+            }
             ClientReadError => {} // This should never happen
         }
+
+        // Like `send_to_followers`, subscribers that fail to receive a message
+        // are removed without recycling their IDs
+        let count = self.subscribers.subscriber_count() as u32;
+        self.metrics.set_total_subscriber_count(count);
     }
 
     fn remove_bosses(&mut self, f: Box<Fn(&RaidBossMetadata) -> bool>) {
-        let (filter_map, subscribers, requested_bosses, metrics) = (
-            &self.filter_map_message,
-            &mut self.subscribers,
-            &mut self.requested_bosses,
-            &mut self.metrics,
-        );
+        let boss_names = self.bosses
+            .values()
+            .filter(|entry| (f)(&entry.boss_data))
+            .map(|entry| entry.boss_data.boss.name.clone())
+            .collect::<Vec<_>>();
 
-        self.bosses.retain(|_, entry| {
-            let should_remove = (f)(&entry.boss_data);
+        // The boss list is only rebuilt once, rather than for every boss
+        for boss_name in boss_names.iter() {
+            self.remove_boss_entry(boss_name);
+        }
+        if !boss_names.is_empty() {
+            self.update_cached_boss_list();
+        }
+    }
 
-            if should_remove {
-                let boss_name = &entry.boss_data.boss.name;
-                let message = (filter_map)(Message::BossRemove(boss_name));
-                subscribers.maybe_send(message.as_ref());
+    fn remove_boss(&mut self, boss_name: &BossName) -> bool {
+        let removed = self.remove_boss_entry(boss_name);
+        if removed {
+            self.update_cached_boss_list();
+        }
+        removed
+    }
 
-                // If there are existing subscribers, move them to `requested_bosses`
-                if !entry.broadcast.is_empty() {
-                    let broadcast = ::std::mem::replace(&mut entry.broadcast, Broadcast::new());
-                    requested_bosses.insert(boss_name.clone(), broadcast);
-                }
+    // Doesn't update `cached_boss_list`
+    fn remove_boss_entry(&mut self, boss_name: &BossName) -> bool {
+        let entry = match self.bosses.remove(boss_name) {
+            Some(entry) => entry,
+            None => return false,
+        };
 
-                metrics.remove_boss(boss_name);
-            }
+        info!("Removing boss {}", boss_name);
+        let message = (self.filter_map_message)(Message::BossRemove(boss_name));
+        self.subscribers.maybe_send(message.as_ref());
 
-            !should_remove
-        });
+        // If there are existing subscribers, move them to `requested_bosses`
+        if !entry.broadcast.is_empty() {
+            self.requested_bosses
+                .insert(boss_name.clone(), entry.broadcast);
+        }
+
+        self.removed_boss_watchers
+            .retain(|tx| tx.unbounded_send(boss_name.clone()).is_ok());
+
+        if let Some(ref f) = self.hooks.on_boss_removed {
+            f(boss_name);
+        }
+
+        self.metrics.remove_boss(boss_name);
+        true
+    }
+
+    // Sorted by name
+    fn bosses_filtered(&self, filter: &BossFilter) -> Vec<RaidBoss> {
+        let now = self.clock.now();
+
+        let mut bosses = self.bosses
+            .values()
+            .map(|e| &e.boss_data)
+            .filter(|data| filter.matches(data, now))
+            .map(|data| data.boss.clone())
+            .collect::<Vec<_>>();
+
+        bosses.sort_by(|a, b| a.name.cmp(&b.name));
+        bosses
     }
 
     fn subscribe(&mut self, subscriber: Sub) -> SubId {
         let id = self.id_pool.get();
         self.subscribers.subscribe(id.clone(), subscriber);
+        self.subscribed_at.insert(id.clone(), self.clock.now());
         self.metrics
             .set_total_subscriber_count(self.subscribers.subscriber_count() as u32);
         id
@@ -181,12 +651,156 @@ where
 
     fn unsubscribe(&mut self, id: &SubId) {
         self.subscribers.unsubscribe(id);
+        self.firehose.unsubscribe(id);
+        self.firehose_filters.remove(id);
+        self.subscribed_at.remove(id);
+
+        // Bosses followed by pattern aren't known to the `Subscription`, so
+        // it can't unfollow them itself
+        if self.follow_patterns.remove(id).is_some() {
+            self.unfollow_every_boss(id);
+        }
+        self.remove_follow_limits(id);
+
         self.metrics
             .set_total_subscriber_count(self.subscribers.subscriber_count() as u32);
         self.id_pool.recycle(id.clone());
     }
 
+    // Unlike `unsubscribe`, the ID isn't recycled, since the `Subscription`
+    // handle still exists. It gets recycled when the handle is dropped.
+    fn kill_subscription(&mut self, id: &SubId) {
+        if self.subscribers.unsubscribe(id).is_none() {
+            return;
+        }
+
+        info!("Killing subscription {}", id.as_u32());
+        self.firehose.unsubscribe(id);
+        self.firehose_filters.remove(id);
+        self.subscribed_at.remove(id);
+        self.follow_patterns.remove(id);
+        self.unfollow_every_boss(id);
+    }
+
+    fn remove_follow_limit(&mut self, id: &SubId, boss_name: &BossName) {
+        if let Entry::Occupied(mut entry) = self.follow_limits.entry(boss_name.clone()) {
+            entry.get_mut().remove(id);
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+        }
+    }
+
+    fn remove_follow_limits(&mut self, id: &SubId) {
+        for limits in self.follow_limits.values_mut() {
+            limits.remove(id);
+        }
+        self.follow_limits.retain(|_, limits| !limits.is_empty());
+    }
+
+    fn unfollow_every_boss(&mut self, id: &SubId) {
+        self.remove_follow_limits(id);
+
+        for (boss_name, entry) in self.bosses.iter_mut() {
+            if entry.broadcast.unsubscribe(id).is_some() {
+                self.metrics
+                    .set_follower_count(boss_name, entry.broadcast.subscriber_count() as u32);
+            }
+        }
+
+        for broadcast in self.requested_bosses.values_mut() {
+            broadcast.unsubscribe(id);
+        }
+        self.requested_bosses.retain(|_, broadcast| !broadcast.is_empty());
+    }
+
+    // Otherwise, subscribers are only removed from a boss when sending it a
+    // tweet fails, so a boss that stops getting tweets keeps them forever.
+    // Subscribers that failed to get the heartbeat are already gone from
+    // `subscribers`, so anything not in there is removed too.
+    fn remove_closed_subscribers(&mut self) {
+        self.subscribers.retain(|_, sub| !sub.is_closed());
+
+        let (subscribers, metrics) = (&self.subscribers, &mut self.metrics);
+        let is_alive = |id: &SubId, sub: &Sub| subscribers.get(id).is_some() && !sub.is_closed();
+
+        for (boss_name, entry) in self.bosses.iter_mut() {
+            let count = entry.broadcast.subscriber_count();
+            entry.broadcast.retain(&is_alive);
+
+            if entry.broadcast.subscriber_count() != count {
+                metrics.set_follower_count(boss_name, entry.broadcast.subscriber_count() as u32);
+            }
+        }
+
+        for broadcast in self.requested_bosses.values_mut() {
+            broadcast.retain(&is_alive);
+        }
+        self.requested_bosses.retain(|_, broadcast| !broadcast.is_empty());
+
+        self.firehose.retain(&is_alive);
+        self.firehose_filters.retain(|id, _| subscribers.get(id).is_some());
+        self.subscribed_at.retain(|id, _| subscribers.get(id).is_some());
+        self.follow_patterns.retain(|id, _| subscribers.get(id).is_some());
+
+        for limits in self.follow_limits.values_mut() {
+            limits.retain(|id, _| subscribers.get(id).is_some());
+        }
+        self.follow_limits.retain(|_, limits| !limits.is_empty());
+    }
+
+    fn subscription_infos(&self) -> Vec<SubscriptionInfo> {
+        let mut infos = Vec::new();
+        let mut indexes = HashMap::new();
+        for (id, _, _) in self.subscribers.stats() {
+            if let Some(subscribed_at) = self.subscribed_at.get(id) {
+                indexes.insert(id.clone(), infos.len());
+                infos.push(SubscriptionInfo {
+                    subscription_id: id.as_u32(),
+                    following: Vec::new(),
+                    follows_all: false,
+                    subscribed_at: *subscribed_at,
+                    delivered: 0,
+                    dropped: 0,
+                });
+            }
+        }
+
+        let followed = self.bosses
+            .iter()
+            .map(|(name, e)| (name, &e.broadcast))
+            .chain(self.requested_bosses.iter());
+
+        for (boss_name, broadcast) in followed {
+            for (id, _, delivery) in broadcast.stats() {
+                if let Some(&i) = indexes.get(id) {
+                    let info = &mut infos[i];
+                    info.following.push(boss_name.clone());
+                    info.delivered += delivery.delivered;
+                    info.dropped += delivery.dropped;
+                }
+            }
+        }
+
+        for (id, _, delivery) in self.firehose.stats() {
+            if let Some(&i) = indexes.get(id) {
+                let info = &mut infos[i];
+                info.follows_all = true;
+                info.delivered += delivery.delivered;
+                info.dropped += delivery.dropped;
+            }
+        }
+
+        for info in infos.iter_mut() {
+            info.following.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        }
+        infos.sort_by_key(|info| info.subscription_id);
+        infos
+    }
+
     fn follow(&mut self, id: SubId, boss_name: BossName) {
+        self.remove_follow_limit(&id, &boss_name);
+
         if let Some(sub) = self.subscribers.get(&id) {
             let subscriber = sub.clone();
 
@@ -209,7 +823,75 @@ where
         }
     }
 
+    fn follow_matching(&mut self, id: SubId, pattern: Regex) {
+        if self.subscribers.get(&id).is_none() {
+            return;
+        }
+
+        let pattern_count = self.follow_patterns.values().map(Vec::len).sum::<usize>();
+        if pattern_count >= MAX_FOLLOW_PATTERNS {
+            warn!("Ignoring boss name pattern {}, limit reached", pattern);
+            return;
+        }
+
+        let matching = self.bosses
+            .keys()
+            .filter(|name| pattern.is_match(name.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for boss_name in matching {
+            self.follow(id.clone(), boss_name);
+        }
+
+        match self.follow_patterns.entry(id) {
+            Entry::Occupied(mut entry) => entry.get_mut().push(pattern),
+            Entry::Vacant(entry) => {
+                entry.insert(vec![pattern]);
+            }
+        }
+    }
+
+    // The last time each subscriber was sent a tweet, from any boss it follows
+    fn last_delivered_times(&self) -> HashMap<SubId, DateTime> {
+        let broadcasts = self.bosses
+            .values()
+            .map(|e| &e.broadcast)
+            .chain(Some(&self.firehose));
+
+        let mut times = HashMap::new();
+        for broadcast in broadcasts {
+            for (id, _, stats) in broadcast.stats() {
+                if let Some(at) = stats.last_delivered {
+                    let time = times.entry(id.clone()).or_insert(at);
+                    if at > *time {
+                        *time = at;
+                    }
+                }
+            }
+        }
+
+        times
+    }
+
+    fn send_backlog(&mut self, id: &SubId, boss_name: &BossName) {
+        let (sub, entry) = match (self.subscribers.get_mut(id), self.bosses.get(boss_name)) {
+            (Some(sub), Some(entry)) => (sub, entry),
+            _ => return,
+        };
+
+        let mut tweets = entry.recent_tweets.as_unordered_slice().iter().collect::<Vec<_>>();
+        tweets.sort_by_key(|t| t.seq);
+
+        for tweet in tweets {
+            let message = (self.filter_map_message)(Message::Tweet(tweet));
+            let _ = sub.maybe_send(message.as_ref());
+        }
+    }
+
     fn unfollow(&mut self, id: &SubId, boss_name: BossName) {
+        self.remove_follow_limit(id, &boss_name);
+
         if let Some(entry) = self.bosses.get_mut(&boss_name) {
             entry.broadcast.unsubscribe(&id);
             self.metrics
@@ -265,18 +947,61 @@ where
     }
 
     pub(crate) fn update_cached_boss_list(&mut self) {
-        let updated = self.bosses
+        let mut updated = self.bosses
             .values()
             .map(|entry| &entry.boss_data.boss)
             .collect::<Vec<_>>();
 
+        // Sorted so the list is the same between calls if nothing changed
+        updated.sort_by(|a, b| a.name.cmp(&b.name));
+
         self.cached_boss_list = (self.filter_map_message)(Message::BossList(&updated))
     }
 
-    fn handle_raid_info(&mut self, info: RaidInfo) {
+    fn handle_raid_info(&mut self, mut info: RaidInfo) {
+        let now = self.clock.now();
+
+        // Captures don't include the raw JSON, since they'd be twice the size
+        let raw = info.raw.take();
+
+        if let Some(ref mut capture) = self.capture {
+            capture.write(&info, now);
+        }
+
+        if self.keep_raw_json {
+            info.tweet.raw = raw;
+        }
+
+        // Checked before anything else, so collapsed duplicates don't use up a
+        // sequence number or count towards the tweet metrics
+        if let Some(window) = self.duplicate_window {
+            if let Some(value) = self.bosses.get_mut(&info.tweet.boss_name) {
+                let created_at = info.tweet.created_at;
+                let is_duplicate = value.recent_tweets.as_unordered_slice().iter().any(|t| {
+                    let diff = created_at.signed_duration_since(t.created_at);
+                    t.raid_id == info.tweet.raid_id && diff < window && diff > -window
+                });
+
+                if is_duplicate {
+                    value.boss_data.last_seen = created_at;
+                    value.boss_data.total_seen += 1;
+                    value.boss_data.duplicate_count += 1;
+                    return;
+                }
+            }
+        }
+
+        if let (Some(max_len), Some(text)) = (self.max_text_len, info.tweet.text.as_mut()) {
+            truncate_text(text, max_len);
+        }
+
         self.metrics.inc_tweet_count(&info.tweet.boss_name);
 
-        let mapped_tweet_message = (self.filter_map_message)(Message::Tweet(&info.tweet));
+        self.last_seq += 1;
+        info.tweet.seq = self.last_seq;
+
+        let boss_name = info.tweet.boss_name.clone();
+        let mut mapped_tweet_message = (self.filter_map_message)(Message::Tweet(&info.tweet));
 
         // Currently, only one translated boss should exist at most, but in
         // case the game gets translated to another language, this should still
@@ -294,23 +1019,93 @@ where
         }
 
         let mut translations: Option<TranslationsExist> = None;
+        let mut is_updated_boss = false;
 
         let is_new_boss = match self.bosses.entry(info.tweet.boss_name.clone()) {
             Entry::Occupied(mut entry) => {
                 let value = entry.get_mut();
 
                 value.boss_data.last_seen = info.tweet.created_at;
+                value.boss_data.total_seen += 1;
 
-                value.broadcast.maybe_send(mapped_tweet_message.as_ref());
+                if let Some(ref f) = self.hooks.on_raid {
+                    f(&info.tweet);
+                }
 
-                if value.boss_data.boss.image.is_none() {
-                    if let Some(image_url) = info.image {
-                        self.hash_requester
-                            .request(value.boss_data.boss.name.clone(), &image_url);
-                        value.boss_data.boss.image = Some(image_url);
+                // Without a message, the tweet is still stored, but not sent
+                let is_throttled = value.rate_limiter.as_mut().map(|b| !b.try_take(now));
+                if is_throttled.unwrap_or(false) {
+                    value.boss_data.throttled_count += 1;
+                    mapped_tweet_message = None;
+                }
+
+                // If the level couldn't be determined from an earlier tweet,
+                // fill it in once a tweet arrives that has it
+                let current_level = value.boss_data.boss.level;
+                let is_unknown_level =
+                    current_level.is_none() || current_level == self.default_boss_level;
+                if is_unknown_level && info.level.is_some() {
+                    value.boss_data.boss.level = info.level;
+                    is_updated_boss = true;
+                }
+
+                // Bosses loaded from older metadata don't have an element
+                if value.boss_data.boss.element.is_none() {
+                    let element = value.boss_data.boss.name.parse_element();
+                    if element.is_some() {
+                        value.boss_data.boss.element = element;
+                        is_updated_boss = true;
+                    }
+                }
+
+                if let Some(image_url) = info.image.map(|url| url.normalize()) {
+                    if let Some(ref mut history) = value.image_history {
+                        record_image(history, &image_url);
+                    }
+
+                    let is_winner = vote_image(
+                        &mut value.boss_data.image_votes,
+                        value.boss_data.boss.image.as_ref(),
+                        &image_url,
+                    );
+
+                    let is_upgrade = match value.boss_data.boss.image {
+                        // The image seen most often replaces the current one
+                        _ if is_winner => {
+                            self.hash_requester
+                                .request(value.boss_data.boss.name.clone(), &image_url);
+                            true
+                        }
+                        // Same image in a larger size, so the hash stays the same
+                        Some(ref current) => image_url.is_larger_variant_of(current),
+                        None => false,
+                    };
+
+                    if is_upgrade {
+                        value.boss_data.boss.image = Some(image_url);
+                        is_updated_boss = true;
                     }
                 }
 
+                // Only notify subscribers if something actually changed
+                if is_updated_boss {
+                    let message =
+                        (self.filter_map_message)(Message::BossUpdate(&value.boss_data.boss));
+                    self.subscribers.maybe_send(message.as_ref());
+                }
+
+                // Sent after any boss update, so followers see the new
+                // metadata before the tweet that came with it
+                self.dropped_events += send_to_followers(
+                    &mut value.broadcast,
+                    mapped_tweet_message.as_ref(),
+                    &value.boss_data.boss.name,
+                    self.follow_limits.get_mut(&value.boss_data.boss.name),
+                    &self.filter_map_message,
+                    &mut self.metrics,
+                    now,
+                );
+
                 let arc_tweet = Arc::new(info.tweet);
 
                 // If this boss has translations, send the tweet to that boss' subscribers too
@@ -336,26 +1131,57 @@ where
             }
             Entry::Vacant(entry) => {
                 let name = entry.key().clone();
+                info!("Adding new boss {}", name);
 
                 let mut broadcast = self.requested_bosses
                     .remove(&name)
                     .unwrap_or(Broadcast::new());
 
+                for (id, patterns) in self.follow_patterns.iter() {
+                    if patterns.iter().any(|p| p.is_match(name.as_str())) {
+                        if let Some(sub) = self.subscribers.get(id) {
+                            broadcast.subscribe(id.clone(), sub.clone());
+                        }
+                    }
+                }
+
                 let last_seen = info.tweet.created_at.clone();
                 let boss = RaidBoss {
-                    level: name.parse_level().unwrap_or(DEFAULT_BOSS_LEVEL),
+                    level: info.level.or(self.default_boss_level),
+                    element: name.parse_element(),
                     name: name,
-                    image: info.image,
+                    image: info.image.map(|url| url.normalize()),
                     language: info.tweet.language,
                     translations: HashSet::with_capacity(1),
                 };
 
+                if let Some(ref f) = self.hooks.on_new_boss {
+                    f(&boss);
+                }
+                if let Some(ref f) = self.hooks.on_raid {
+                    f(&info.tweet);
+                }
+
                 {
                     let boss_message = Message::BossUpdate(&boss);
                     self.subscribers
                         .maybe_send((self.filter_map_message)(boss_message).as_ref());
 
-                    broadcast.maybe_send(mapped_tweet_message.as_ref());
+                    self.dropped_events += send_to_followers(
+                        &mut broadcast,
+                        mapped_tweet_message.as_ref(),
+                        &boss.name,
+                        self.follow_limits.get_mut(&boss.name),
+                        &self.filter_map_message,
+                        &mut self.metrics,
+                        now,
+                    );
+                }
+
+                // Subscribers might have followed this boss before it existed
+                if !broadcast.is_empty() {
+                    self.metrics
+                        .set_follower_count(&boss.name, broadcast.subscriber_count() as u32);
                 }
 
                 if let Some(ref image_url) = boss.image {
@@ -365,14 +1191,29 @@ where
                 let mut recent_tweets = CircularBuffer::with_capacity(self.tweet_history_size);
                 recent_tweets.push(Arc::new(info.tweet));
 
+                let mut rate_limiter = self.rate_limit.map(TokenBucket::new);
+                if let Some(ref mut bucket) = rate_limiter {
+                    bucket.try_take(now);
+                }
+
+                let image_votes = initial_image_votes(boss.image.as_ref());
+                let image_history =
+                    initial_image_history(boss.image.as_ref(), self.image_history_size);
+
                 entry.insert(RaidBossEntry {
+                    image_history,
                     boss_data: RaidBossMetadata {
                         boss,
                         last_seen,
                         image_hash: None,
+                        total_seen: 1,
+                        duplicate_count: 0,
+                        throttled_count: 0,
+                        image_votes,
                     },
                     broadcast,
                     recent_tweets,
+                    rate_limiter,
                 });
 
                 true
@@ -383,7 +1224,15 @@ where
         match translations {
             Some(TranslationsExist::One { boss_name, tweet }) => {
                 if let Some(value) = self.bosses.get_mut(&boss_name) {
-                    value.broadcast.maybe_send(mapped_tweet_message.as_ref());
+                    self.dropped_events += send_to_followers(
+                        &mut value.broadcast,
+                        mapped_tweet_message.as_ref(),
+                        &boss_name,
+                        self.follow_limits.get_mut(&boss_name),
+                        &self.filter_map_message,
+                        &mut self.metrics,
+                        now,
+                    );
                     value.recent_tweets.push(tweet);
                 }
             }
@@ -391,26 +1240,153 @@ where
             Some(TranslationsExist::Multiple { boss_names, tweet }) => {
                 for boss_name in boss_names {
                     if let Some(value) = self.bosses.get_mut(&boss_name) {
-                        value.broadcast.maybe_send(mapped_tweet_message.as_ref());
+                        self.dropped_events += send_to_followers(
+                            &mut value.broadcast,
+                            mapped_tweet_message.as_ref(),
+                            &boss_name,
+                            self.follow_limits.get_mut(&boss_name),
+                            &self.filter_map_message,
+                            &mut self.metrics,
+                            now,
+                        );
                         value.recent_tweets.push(tweet.clone());
                     }
                 }
             }
         }
 
-        if is_new_boss {
+        // Sent after the boss is updated, since filters depend on the boss' level
+        if let Some(entry) = self.bosses.get(&boss_name) {
+            let (boss, filters) = (&entry.boss_data.boss, &self.firehose_filters);
+            let default_level = self.default_boss_level;
+
+            self.dropped_events += self.firehose.maybe_send_at_where(
+                mapped_tweet_message.as_ref(),
+                now,
+                |id| {
+                    filters
+                        .get(id)
+                        .map(|f| f.matches_with_default(boss, default_level))
+                        .unwrap_or(true)
+                },
+            );
+        }
+
+        if is_new_boss || is_updated_boss {
             self.update_cached_boss_list();
         }
     }
 }
 
-impl<H, S, Sub, F, M> Future for Worker<H, S, Sub, F, M>
+// Subscribers that fail to receive a message are removed from the broadcast,
+// so the follower count needs to be updated. Their IDs aren't recycled, since
+// a `Subscription` that is dropped later would still unsubscribe that ID.
+// Tweets are only counted against a follow's rate limit if they'd be sent.
+// Returns the number of followers the tweet was dropped for.
+fn send_to_followers<Sub, M, F>(
+    broadcast: &mut Broadcast<SubId, Sub>,
+    message: Option<&Sub::Item>,
+    boss_name: &BossName,
+    limits: Option<&mut HashMap<SubId, FollowLimit>>,
+    filter_map_message: &F,
+    metrics: &mut M,
+    now: DateTime,
+) -> u64
+where
+    Sub: Subscriber,
+    M: Metrics,
+    F: Fn(Message) -> Option<Sub::Item>,
+{
+    if message.is_none() || broadcast.is_empty() {
+        return 0;
+    }
+
+    let count = broadcast.subscriber_count();
+
+    let dropped = if let Some(limits) = limits {
+        let mut throttled = HashSet::new();
+
+        for (id, limit) in limits.iter_mut() {
+            if broadcast.get(id).is_none() {
+                continue;
+            }
+
+            if !limit.bucket.try_take(now) {
+                if limit.policy == RateLimitPolicy::Coalesce {
+                    limit.skipped += 1;
+                }
+                throttled.insert(id.clone());
+            } else if limit.skipped > 0 {
+                let skipped = filter_map_message(Message::Skipped {
+                    boss_name: boss_name.clone(),
+                    count: limit.skipped,
+                });
+                if let Some(sub) = broadcast.get_mut(id) {
+                    let _ = sub.maybe_send(skipped.as_ref());
+                }
+                limit.skipped = 0;
+            }
+        }
+
+        broadcast.maybe_send_at_where(message, now, |id| !throttled.contains(id))
+    } else {
+        broadcast.maybe_send_at(message, now)
+    };
+
+    if broadcast.subscriber_count() != count {
+        metrics.set_follower_count(boss_name, broadcast.subscriber_count() as u32);
+    }
+
+    dropped
+}
+
+fn subscription_stats(
+    id: &SubId,
+    boss_name: Option<&BossName>,
+    delivery: &DeliveryStats,
+) -> SubscriptionStats {
+    SubscriptionStats {
+        subscription_id: id.as_u32(),
+        boss_name: boss_name.cloned(),
+        delivered: delivery.delivered,
+        dropped: delivery.dropped,
+        last_delivered: delivery.last_delivered,
+    }
+}
+
+// Combines multiple tweet buffers into one, sorted by creation time.
+// Tweets with the same raid ID are only included once, and only the
+// most recent `limit` tweets are kept.
+fn merge_tweets<'a, I>(buffers: I, limit: usize) -> Vec<Arc<RaidTweet>>
+where
+    I: IntoIterator<Item = &'a [Arc<RaidTweet>]>,
+{
+    let mut tweets = buffers
+        .into_iter()
+        .flat_map(|buffer| buffer.iter().cloned())
+        .collect::<Vec<_>>();
+
+    tweets.sort_by_key(|t| t.created_at);
+
+    let mut seen_raid_ids = HashSet::with_capacity(tweets.len());
+    tweets.retain(|t| seen_raid_ids.insert(t.raid_id.clone()));
+
+    if tweets.len() > limit {
+        let excess = tweets.len() - limit;
+        tweets.drain(..excess);
+    }
+
+    tweets
+}
+
+impl<H, S, Sub, F, M, C> Future for Worker<H, S, Sub, F, M, C>
 where
     H: ImageHasher,
     S: Stream<Item = RaidInfo, Error = Error>,
     Sub: Subscriber + Clone,
     F: Fn(Message) -> Option<Sub::Item>,
     M: Metrics,
+    C: Clock,
 {
     type Item = ();
     type Error = Error;
@@ -425,3 +1401,2121 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ClientBuilder;
+    use bounded;
+    use broadcast::NoOpSubscriber;
+    use clock;
+    use chrono::{TimeZone, Utc};
+    use futures::future;
+    use futures::unsync::oneshot;
+    use metrics;
+    use model::{Element, Language, TweetPosition};
+    use std::time::Duration;
+    use testing;
+    use tokio_core::reactor::Core;
+
+    struct NoOpImageHasher;
+    impl ImageHasher for NoOpImageHasher {
+        type Future = future::Empty<BossImageHash, Error>;
+
+        fn hash(&self, _boss_name: BossName, _uri: ::hyper::Uri) -> Self::Future {
+            future::empty()
+        }
+    }
+
+    fn boss(name: &str, last_seen: i64) -> RaidBossMetadata {
+        let name = BossName::from(name);
+
+        RaidBossMetadata {
+            boss: RaidBoss {
+                level: name.parse_level(),
+                element: name.parse_element(),
+                name,
+                image: None,
+                language: Language::English,
+                translations: HashSet::new(),
+            },
+            last_seen: Utc.timestamp(last_seen, 0),
+            image_hash: None,
+            total_seen: 0,
+            duplicate_count: 0,
+            throttled_count: 0,
+            image_votes: HashMap::new(),
+        }
+    }
+
+    fn boss_names(bosses: &[RaidBoss]) -> Vec<&str> {
+        bosses.iter().map(|b| b.name.as_str()).collect()
+    }
+
+    fn tweet(raid_id: &str, boss_name: &str, timestamp: i64) -> Arc<RaidTweet> {
+        Arc::new(RaidTweet {
+            tweet_id: 0,
+            boss_name: boss_name.into(),
+            raid_id: raid_id.to_string(),
+            user_screen_name: "walfieee".to_string(),
+            user_display_name: "walfie".to_string(),
+            user_id: 1234567890,
+            user_image: None,
+            text: None,
+            created_at: Utc.timestamp(timestamp, 0),
+            language: Language::English,
+            seq: 0,
+            raw: None,
+        })
+    }
+
+    fn raid_info(raid_id: &str, boss_name: &str, timestamp: i64) -> RaidInfo {
+        RaidInfo {
+            tweet: (*tweet(raid_id, boss_name, timestamp)).clone(),
+            image: None,
+            images: vec![],
+            level: None,
+            raw: None,
+        }
+    }
+
+    fn raid_ids(tweets: &[Arc<RaidTweet>]) -> Vec<&str> {
+        tweets.iter().map(|t| t.raid_id.as_str()).collect()
+    }
+
+    // Stays open, so the worker doesn't stop when there are no more tweets
+    fn open_stream() -> testing::MockStream {
+        testing::from_vec(vec![]).1
+    }
+
+    fn drop_message(_message: Message) -> Option<()> {
+        None
+    }
+
+    type TestBuilder = ClientBuilder<
+        NoOpImageHasher,
+        testing::MockStream,
+        NoOpSubscriber,
+        fn(Message) -> Option<()>,
+        metrics::NoOp,
+        clock::System,
+    >;
+
+    // For tests that call the worker directly, rather than through the client
+    fn builder() -> TestBuilder {
+        ClientBuilder::new()
+            .with_stream(open_stream())
+            .with_image_hasher(NoOpImageHasher)
+            .with_subscriber::<NoOpSubscriber>()
+            .filter_map_message(drop_message as fn(Message) -> Option<()>)
+    }
+
+    fn tweet_raid_id(message: Message) -> Option<String> {
+        match message {
+            Message::Tweet(tweet) => Some(tweet.raid_id.clone()),
+            _ => None,
+        }
+    }
+
+    // For tests that go through the client. Subscribers get whatever `f`
+    // maps each message to, and their type is inferred from `subscribe`.
+    fn client_builder<S, Sub, F>(
+        stream: S,
+        f: F,
+    ) -> ClientBuilder<NoOpImageHasher, S, Sub, F, metrics::NoOp, clock::System>
+    where
+        S: Stream<Item = RaidInfo, Error = Error>,
+        Sub: Subscriber,
+        F: Fn(Message) -> Option<Sub::Item>,
+    {
+        ClientBuilder::new()
+            .with_stream(stream)
+            .with_image_hasher(NoOpImageHasher)
+            .with_subscriber::<Sub>()
+            .filter_map_message(f)
+    }
+
+    #[test]
+    fn merge_tweets_sorted() {
+        let en = [
+            tweet("AAAA0003", "Lvl 120 Grimnir", 3),
+            tweet("AAAA0001", "Lvl 120 Grimnir", 1),
+        ];
+        let jp = [
+            tweet("AAAA0002", "Lv120 グリームニル", 2),
+            tweet("AAAA0004", "Lv120 グリームニル", 3),
+        ];
+
+        let merged = merge_tweets([&en[..], &jp[..]].iter().cloned(), 10);
+        assert_eq!(
+            raid_ids(&merged),
+            vec!["AAAA0001", "AAAA0002", "AAAA0003", "AAAA0004"]
+        );
+    }
+
+    #[test]
+    fn merge_tweets_dedup_raid_id() {
+        // Tweets forwarded to translated bosses exist in both buffers
+        let shared = tweet("AAAA0002", "Lvl 120 Grimnir", 2);
+        let en = [tweet("AAAA0001", "Lvl 120 Grimnir", 1), shared.clone()];
+        let jp = [shared, tweet("AAAA0002", "Lv120 グリームニル", 2)];
+
+        let merged = merge_tweets([&en[..], &jp[..]].iter().cloned(), 10);
+        assert_eq!(raid_ids(&merged), vec!["AAAA0001", "AAAA0002"]);
+    }
+
+    #[test]
+    fn merge_tweets_limit() {
+        let en = (0..5)
+            .map(|i| tweet(&format!("EN00000{}", i), "Lvl 120 Grimnir", i))
+            .collect::<Vec<_>>();
+        let jp = (0..5)
+            .map(|i| tweet(&format!("JP00000{}", i), "Lv120 グリームニル", i))
+            .collect::<Vec<_>>();
+
+        let merged = merge_tweets([&en[..], &jp[..]].iter().cloned(), 4);
+        assert_eq!(merged.len(), 4);
+        assert!(merged.iter().all(|t| t.created_at >= Utc.timestamp(3, 0)));
+    }
+
+    #[test]
+    fn active_bosses() {
+        let mut core = Core::new().unwrap();
+
+        let bosses = vec![
+            boss("Lvl 100 Ozorotter", 40),
+            boss("Lvl 60 Ozorotter", 100),
+            boss("Lvl 75 Ozorotter", 80),
+        ];
+
+        let clock = clock::Mock::new(Utc.timestamp(120, 0));
+
+        let (client, worker) = builder()
+            .with_bosses(bosses)
+            .with_clock(clock.clone())
+            .build();
+
+        core.handle().spawn(worker.map_err(|_| ()));
+
+        let active = core.run(client.active_bosses(Duration::from_secs(60)))
+            .unwrap();
+        assert_eq!(
+            boss_names(&active),
+            vec!["Lvl 60 Ozorotter", "Lvl 75 Ozorotter"]
+        );
+
+        let all = core.run(client.active_bosses(Duration::from_secs(120)))
+            .unwrap();
+        assert_eq!(
+            boss_names(&all),
+            vec!["Lvl 60 Ozorotter", "Lvl 75 Ozorotter", "Lvl 100 Ozorotter"]
+        );
+
+        clock.advance(::chrono::Duration::seconds(30));
+        let active = core.run(client.active_bosses(Duration::from_secs(60)))
+            .unwrap();
+        assert_eq!(boss_names(&active), vec!["Lvl 60 Ozorotter"]);
+    }
+
+    #[test]
+    fn tweets_after() {
+        let (_client, mut worker) = builder().build();
+
+        let get_page = |worker: &mut Worker<_, _, _, _, _, _>, cursor| {
+            let (sender, receiver) = oneshot::channel();
+            worker.handle_event(Event::ClientGetTweetsAfter {
+                boss_name: "Lvl 60 Ozorotter".into(),
+                cursor,
+                sender,
+            });
+            receiver.wait().unwrap()
+        };
+
+        worker.handle_raid_info(raid_info("AAAA0001", "Lvl 60 Ozorotter", 1));
+        worker.handle_raid_info(raid_info("AAAA0002", "Lvl 75 Ozorotter", 1));
+        worker.handle_raid_info(raid_info("AAAA0003", "Lvl 60 Ozorotter", 1));
+
+        // Sequence numbers are shared across bosses, so timestamp
+        // collisions don't matter
+        let page = get_page(&mut worker, 0);
+        assert_eq!(raid_ids(&page.tweets), vec!["AAAA0001", "AAAA0003"]);
+        assert_eq!(page.cursor, 3);
+
+        let empty = get_page(&mut worker, page.cursor);
+        assert!(empty.tweets.is_empty());
+        assert_eq!(empty.cursor, 3);
+
+        worker.handle_raid_info(raid_info("AAAA0004", "Lvl 75 Ozorotter", 1));
+        worker.handle_raid_info(raid_info("AAAA0005", "Lvl 60 Ozorotter", 1));
+
+        let page = get_page(&mut worker, page.cursor);
+        assert_eq!(raid_ids(&page.tweets), vec!["AAAA0005"]);
+        assert_eq!(page.cursor, 5);
+    }
+
+    #[test]
+    fn sequence_numbers() {
+        let (_client, mut worker) = builder()
+            .with_sequence_offset(100)
+            .with_duplicate_window(Duration::from_secs(60))
+            .build();
+
+        let boss_names = ["Lvl 60 Ozorotter", "Lvl 75 Ozorotter", "Lvl 100 Ozorotter"];
+        for i in 0..9 {
+            let boss_name = boss_names[i % boss_names.len()];
+            worker.handle_raid_info(raid_info(&format!("AAAA000{}", i), boss_name, 0));
+
+            // Collapsed duplicates don't use up a sequence number
+            if i == 4 {
+                worker.handle_raid_info(raid_info("AAAA0001", boss_name, 0));
+            }
+        }
+
+        let mut seqs = Vec::new();
+        for boss_name in boss_names.iter() {
+            let tweets = worker.bosses[&BossName::from(boss_name)]
+                .recent_tweets
+                .as_unordered_slice();
+
+            let boss_seqs = tweets.iter().map(|t| t.seq).collect::<Vec<_>>();
+            assert!(boss_seqs.windows(2).all(|w| w[0] < w[1]));
+
+            seqs.extend(boss_seqs);
+        }
+
+        seqs.sort();
+        assert_eq!(seqs, (101..110).collect::<Vec<_>>());
+
+        let (sender, receiver) = oneshot::channel();
+        worker.handle_event(Event::ClientGetLastSeq(sender));
+        assert_eq!(receiver.wait().unwrap(), 109);
+
+        let tweet = &worker.bosses[&BossName::from("Lvl 60 Ozorotter")]
+            .recent_tweets
+            .as_unordered_slice()[0];
+        let json = ::serde_json::to_value(&**tweet).unwrap();
+        assert_eq!(json["seq"], 101);
+    }
+
+    #[test]
+    fn tweets_checked() {
+        let (_client, mut worker) = builder()
+            .with_bosses(vec![boss("Lvl 60 Ozorotter", 0)])
+            .build();
+
+        let get_tweets = |worker: &mut Worker<_, _, _, _, _, _>, boss_name: &str| {
+            let (sender, receiver) = oneshot::channel();
+            worker.handle_event(Event::ClientGetTweetsChecked {
+                boss_name: boss_name.into(),
+                sender,
+            });
+            receiver.wait().unwrap()
+        };
+
+        assert_eq!(get_tweets(&mut worker, "Lvl 60 Ozorotter"), Some(vec![]));
+        assert_eq!(get_tweets(&mut worker, "Lvl 60 Ozorooter"), None);
+
+        worker.handle_raid_info(raid_info("AAAA0001", "Lvl 75 Ozorotter", 0));
+        let tweets = get_tweets(&mut worker, "Lvl 75 Ozorotter").unwrap();
+        assert_eq!(raid_ids(&tweets), vec!["AAAA0001"]);
+    }
+
+    #[test]
+    fn all_tweets() {
+        let mut en = boss("Lvl 120 Grimnir", 0);
+        let mut jp = boss("Lv120 グリームニル", 0);
+        en.boss.translations.insert(jp.boss.name.clone());
+        jp.boss.translations.insert(en.boss.name.clone());
+
+        let (_client, mut worker) = builder()
+            .with_bosses(vec![en, jp])
+            .build();
+
+        let get_all = |worker: &mut Worker<_, _, _, _, _, _>, limit| {
+            let (sender, receiver) = oneshot::channel();
+            worker.handle_event(Event::ClientGetAllTweets { limit, sender });
+            receiver.wait().unwrap()
+        };
+
+        worker.handle_raid_info(raid_info("AAAA0001", "Lvl 120 Grimnir", 1));
+        worker.handle_raid_info(raid_info("AAAA0002", "Lvl 60 Ozorotter", 3));
+        worker.handle_raid_info(raid_info("AAAA0003", "Lv120 グリームニル", 2));
+
+        assert_eq!(
+            raid_ids(&get_all(&mut worker, 10)),
+            vec!["AAAA0002", "AAAA0003", "AAAA0001"]
+        );
+        assert_eq!(
+            raid_ids(&get_all(&mut worker, 2)),
+            vec!["AAAA0002", "AAAA0003"]
+        );
+    }
+
+    #[test]
+    fn all_tweets_after() {
+        let mut en = boss("Lvl 120 Grimnir", 0);
+        let mut jp = boss("Lv120 グリームニル", 0);
+        en.boss.translations.insert(jp.boss.name.clone());
+        jp.boss.translations.insert(en.boss.name.clone());
+
+        let (_client, mut worker) = builder()
+            .with_bosses(vec![en, jp])
+            .build();
+
+        let get_page = |worker: &mut Worker<_, _, _, _, _, _>, cursor| {
+            let (sender, receiver) = oneshot::channel();
+            worker.handle_event(Event::ClientGetAllTweetsAfter { cursor, sender });
+            receiver.wait().unwrap()
+        };
+
+        worker.handle_raid_info(raid_info("AAAA0001", "Lvl 120 Grimnir", 3));
+        worker.handle_raid_info(raid_info("AAAA0002", "Lvl 60 Ozorotter", 2));
+        worker.handle_raid_info(raid_info("AAAA0003", "Lv120 グリームニル", 1));
+
+        // Ordered by arrival, and tweets shared by translations are only
+        // returned once
+        let page = get_page(&mut worker, 0);
+        assert_eq!(raid_ids(&page.tweets), vec!["AAAA0001", "AAAA0002", "AAAA0003"]);
+        assert_eq!(page.cursor, 3);
+
+        let empty = get_page(&mut worker, page.cursor);
+        assert!(empty.tweets.is_empty());
+        assert_eq!(empty.cursor, 3);
+
+        worker.handle_raid_info(raid_info("AAAA0004", "Lvl 75 Ozorotter", 0));
+        let page = get_page(&mut worker, page.cursor);
+        assert_eq!(raid_ids(&page.tweets), vec!["AAAA0004"]);
+        assert_eq!(page.cursor, 4);
+    }
+
+    #[test]
+    fn tweets_multi() {
+        let (_client, mut worker) = builder().build();
+
+        worker.handle_raid_info(raid_info("AAAA0001", "Lvl 60 Ozorotter", 0));
+        worker.handle_raid_info(raid_info("AAAA0002", "Lvl 75 Ozorotter", 0));
+        worker.handle_raid_info(raid_info("AAAA0003", "Lvl 100 Ozorotter", 0));
+
+        let (sender, receiver) = oneshot::channel();
+        worker.handle_event(Event::ClientGetTweetsMulti {
+            boss_names: vec![
+                "Lvl 60 Ozorotter".into(),
+                "Lvl 100 Ozorotter".into(),
+                "Lvl 120 Ozorotter".into(),
+            ],
+            sender,
+        });
+        let tweets = receiver.wait().unwrap();
+
+        assert_eq!(tweets.len(), 2);
+        assert_eq!(
+            raid_ids(&tweets[&BossName::from("Lvl 60 Ozorotter")]),
+            vec!["AAAA0001"]
+        );
+        assert_eq!(
+            raid_ids(&tweets[&BossName::from("Lvl 100 Ozorotter")]),
+            vec!["AAAA0003"]
+        );
+    }
+
+    #[test]
+    fn backfill_boss_level() {
+        let (_client, mut worker) = builder().build();
+
+        let boss_name = BossName::from("Lvl 60 Ozorotter");
+
+        worker.handle_raid_info(raid_info("AAAA0001", &boss_name, 0));
+        assert_eq!(worker.bosses[&boss_name].boss_data.boss.level, None);
+
+        let mut info = raid_info("AAAA0002", &boss_name, 1);
+        info.level = Some(60);
+        worker.handle_raid_info(info);
+        assert_eq!(worker.bosses[&boss_name].boss_data.boss.level, Some(60));
+
+        // Once known, the level isn't overwritten
+        let mut info = raid_info("AAAA0003", &boss_name, 2);
+        info.level = Some(75);
+        worker.handle_raid_info(info);
+        assert_eq!(worker.bosses[&boss_name].boss_data.boss.level, Some(60));
+    }
+
+    #[test]
+    fn backfill_boss_element() {
+        let mut grimnir = boss("Lvl 120 Grimnir", 0);
+        grimnir.boss.element = None;
+        let (_client, mut worker) = builder().with_bosses(vec![grimnir]).build();
+
+        let boss_name = BossName::from("Lvl 120 Grimnir");
+        assert_eq!(worker.bosses[&boss_name].boss_data.boss.element, None);
+
+        worker.handle_raid_info(raid_info("AAAA0001", &boss_name, 0));
+        assert_eq!(
+            worker.bosses[&boss_name].boss_data.boss.element,
+            Some(Element::Wind)
+        );
+    }
+
+    #[test]
+    fn follow_includes_translations() {
+        let en = boss("Lvl 120 Grimnir", 0);
+        let mut jp = boss("Lv120 グリームニル", 0);
+        jp.boss.language = Language::Japanese;
+        let (en_name, jp_name) = (en.boss.name.clone(), jp.boss.name.clone());
+
+        let (client, mut worker) = client_builder(open_stream(), |message| match message {
+            Message::Tweet(t) => Some(format!("{} {:?}", t.raid_id, t.language)),
+            _ => None,
+        }).with_duplicate_window(Duration::from_secs(60))
+            .with_bosses(vec![en, jp])
+            .build();
+
+        let (tx, rx) = mpsc::unbounded();
+        let (sender, receiver) = oneshot::channel();
+        worker.handle_event(Event::SubscriberSubscribe {
+            subscriber: tx,
+            client,
+            sender,
+        });
+        let sub = receiver.wait().unwrap();
+        worker.handle_event(Event::SubscriberFollow {
+            id: sub.id.clone(),
+            boss_name: en_name.clone(),
+        });
+
+        let jp_tweet = |raid_id, timestamp| {
+            let mut info = raid_info(raid_id, "Lv120 グリームニル", timestamp);
+            info.tweet.language = Language::Japanese;
+            info
+        };
+
+        // Not linked yet, so this isn't sent to the follower
+        worker.handle_raid_info(jp_tweet("AAAA0001", 0));
+
+        // The bosses are linked after the follow, and the follower gets
+        // tweets from both without following again
+        for boss_name in &[en_name, jp_name] {
+            worker.handle_event(Event::NewImageHash {
+                boss_name: boss_name.clone(),
+                image_hash: 1.into(),
+            });
+        }
+        worker.handle_raid_info(jp_tweet("BBBB0001", 10));
+
+        // Same raid posted in the other language
+        worker.handle_raid_info(raid_info("BBBB0001", "Lvl 120 Grimnir", 20));
+        worker.handle_raid_info(raid_info("CCCC0001", "Lvl 120 Grimnir", 30));
+
+        drop((worker, sub));
+        assert_eq!(
+            rx.collect().wait().unwrap(),
+            vec!["BBBB0001 Japanese", "CCCC0001 English"]
+        );
+    }
+
+    #[test]
+    fn rate_limit() {
+        let clock = clock::Mock::new(Utc.timestamp(0, 0));
+
+        let (client, mut worker) = client_builder(open_stream(), tweet_raid_id)
+            .with_clock(clock.clone())
+            .with_rate_limit(2, Duration::from_secs(10))
+            .build();
+
+        let (tx, rx) = mpsc::unbounded();
+        let (sender, receiver) = oneshot::channel();
+        worker.handle_event(Event::SubscriberSubscribe {
+            subscriber: tx,
+            client,
+            sender,
+        });
+        let sub = receiver.wait().unwrap();
+
+        let boss_name = BossName::from("Lvl 60 Ozorotter");
+        worker.handle_event(Event::SubscriberFollow {
+            id: sub.id.clone(),
+            boss_name: boss_name.clone(),
+        });
+
+        for i in 0..4 {
+            worker.handle_raid_info(raid_info(&format!("AAAA000{}", i), &boss_name, 0));
+        }
+
+        // One token is refilled every 5 seconds
+        clock.advance(::chrono::Duration::seconds(5));
+        worker.handle_raid_info(raid_info("BBBB0000", &boss_name, 5));
+        worker.handle_raid_info(raid_info("BBBB0001", &boss_name, 5));
+
+        let entry = &worker.bosses[&boss_name];
+        assert_eq!(entry.boss_data.throttled_count, 3);
+        assert_eq!(entry.recent_tweets.as_unordered_slice().len(), 6);
+
+        drop((worker, sub));
+        assert_eq!(
+            rx.collect().wait().unwrap(),
+            vec!["AAAA0000", "AAAA0001", "BBBB0000"]
+        );
+    }
+
+    #[test]
+    fn follow_rate_limited() {
+        let clock = clock::Mock::new(Utc.timestamp(0, 0));
+
+        let (client, mut worker) = client_builder(open_stream(), |message| match message {
+            Message::Tweet(tweet) => Some(tweet.raid_id.clone()),
+            Message::Skipped { boss_name, count } => {
+                Some(format!("skipped {} {}", count, boss_name))
+            }
+            _ => None,
+        }).with_clock(clock.clone())
+            .build();
+
+        let subscribe = |worker: &mut Worker<_, _, _, _, _, _>, policy| {
+            let (tx, rx) = mpsc::unbounded();
+            let (sender, receiver) = oneshot::channel();
+            worker.handle_event(Event::SubscriberSubscribe {
+                subscriber: tx,
+                client: client.clone(),
+                sender,
+            });
+            let sub = receiver.wait().unwrap();
+
+            worker.handle_event(Event::SubscriberFollowRateLimited {
+                id: sub.id.clone(),
+                boss_name: "Lvl 100 Fenrir".into(),
+                limit: RateLimit {
+                    capacity: 1,
+                    per: ::chrono::Duration::seconds(10),
+                },
+                policy,
+            });
+            (sub, rx)
+        };
+
+        let (coalesce, coalesce_rx) = subscribe(&mut worker, RateLimitPolicy::Coalesce);
+        let (dropping, dropping_rx) = subscribe(&mut worker, RateLimitPolicy::Drop);
+
+        worker.handle_raid_info(raid_info("AAAA0001", "Lvl 100 Fenrir", 0));
+        worker.handle_raid_info(raid_info("AAAA0002", "Lvl 100 Fenrir", 1));
+        worker.handle_raid_info(raid_info("AAAA0003", "Lvl 100 Fenrir", 2));
+        clock.advance(::chrono::Duration::seconds(10));
+        worker.handle_raid_info(raid_info("AAAA0004", "Lvl 100 Fenrir", 10));
+
+        // Following again without a limit removes it
+        worker.handle_event(Event::SubscriberFollow {
+            id: coalesce.id.clone(),
+            boss_name: "Lvl 100 Fenrir".into(),
+        });
+        worker.handle_raid_info(raid_info("AAAA0005", "Lvl 100 Fenrir", 11));
+        assert_eq!(worker.follow_limits[&BossName::from("Lvl 100 Fenrir")].len(), 1);
+
+        drop((worker, coalesce, dropping));
+        assert_eq!(
+            coalesce_rx.collect().wait().unwrap(),
+            vec!["AAAA0001", "skipped 2 Lvl 100 Fenrir", "AAAA0004", "AAAA0005"]
+        );
+        assert_eq!(dropping_rx.collect().wait().unwrap(), vec!["AAAA0001", "AAAA0004"]);
+    }
+
+    #[test]
+    fn default_boss_level() {
+        let (_client, mut worker) = builder()
+            .with_default_boss_level(-1)
+            .build();
+
+        let boss_name = BossName::from("Lvl 60 Ozorotter");
+
+        worker.handle_raid_info(raid_info("AAAA0001", &boss_name, 0));
+        assert_eq!(worker.bosses[&boss_name].boss_data.boss.level, Some(-1));
+
+        let mut info = raid_info("AAAA0002", &boss_name, 1);
+        info.level = Some(60);
+        worker.handle_raid_info(info);
+        assert_eq!(worker.bosses[&boss_name].boss_data.boss.level, Some(60));
+    }
+
+    #[test]
+    fn capture_without_raw_json() {
+        use std::fs::File;
+        use std::io::{BufRead, BufReader};
+
+        let file_name = format!("petronel-worker-capture-{}.json", ::std::process::id());
+        let path = ::std::env::temp_dir().join(file_name);
+        let _ = ::std::fs::remove_file(&path);
+
+        {
+            let (_client, mut worker) = builder().keep_raw_json().capture_to(&path).build();
+            let mut info = raid_info("AAAA0001", "Lvl 60 Ozorotter", 0);
+            info.raw = Some("{}".to_string());
+            worker.handle_raid_info(info);
+        }
+
+        let line = BufReader::new(File::open(&path).unwrap()).lines().next();
+        ::std::fs::remove_file(&path).unwrap();
+
+        let info = ::serde_json::from_str::<RaidInfo>(&line.unwrap().unwrap()).unwrap();
+        assert_eq!(info.tweet.raid_id, "AAAA0001");
+        assert_eq!(info.raw, None);
+        assert_eq!(info.tweet.raw, None);
+    }
+
+    #[test]
+    fn keep_raw_json() {
+        let history = |keep: bool| {
+            let mut builder = builder();
+            if keep {
+                builder = builder.keep_raw_json();
+            }
+            let (_client, mut worker) = builder.build();
+
+            let mut info = raid_info("AAAA0001", "Lvl 60 Ozorotter", 0);
+            info.raw = Some("{}".to_string());
+            worker.handle_raid_info(info);
+
+            let boss_name = BossName::from("Lvl 60 Ozorotter");
+            worker.bosses[&boss_name].recent_tweets.as_unordered_slice()[0].raw.clone()
+        };
+
+        assert_eq!(history(false), None);
+        assert_eq!(history(true), Some("{}".to_string()));
+    }
+
+    #[test]
+    fn truncate_long_text() {
+        let truncated = |text: &str, max_len: usize| {
+            let mut text = text.to_string();
+            truncate_text(&mut text, max_len);
+            text
+        };
+
+        assert_eq!(truncated("short", 5), "short");
+        assert_eq!(truncated("a bit longer", 5), "a bi…");
+        assert_eq!(truncated("救援お願いします", 4), "救援お…");
+        assert_eq!(truncated("🦦🦦🦦", 2), "🦦…");
+        assert_eq!(truncated("text", 0), "");
+
+        let (_client, mut worker) = builder()
+            .with_max_text_len(3)
+            .build();
+
+        let mut info = raid_info("AAAA0001", "Lvl 60 Ozorotter", 0);
+        info.tweet.text = Some("オオゾラッコ".to_string());
+        worker.handle_raid_info(info);
+
+        let boss_name = BossName::from("Lvl 60 Ozorotter");
+        let tweet = &worker.bosses[&boss_name].recent_tweets.as_unordered_slice()[0];
+        assert_eq!(tweet.text, Some("オオ…".to_string()));
+    }
+
+    #[test]
+    fn upgrade_boss_image() {
+        let (_client, mut worker) = builder().build();
+
+        let boss_name = BossName::from("Lvl 60 Ozorotter");
+        let mut send_image = |raid_id: &str, image: &str| {
+            let mut info = raid_info(raid_id, &boss_name, 0);
+            info.image = Some(image.into());
+            worker.handle_raid_info(info);
+            worker.bosses[&boss_name].boss_data.boss.image.clone().unwrap()
+        };
+
+        let small = "http://example.com/ozorotter.png:small";
+        let large = "http://example.com/ozorotter.png:large";
+        let other = "http://example.com/other.png:orig";
+
+        assert_eq!(send_image("AAAA0001", small), small.into());
+        assert_eq!(send_image("AAAA0002", large), large.into());
+        assert_eq!(send_image("AAAA0003", small), large.into());
+        assert_eq!(send_image("AAAA0004", other), large.into());
+
+        // Twitter media URLs are normalized to `:large`, unless they're `:orig`
+        let boss_name = BossName::from("Lvl 75 Ozorotter");
+        let mut send_image = |raid_id: &str, image: &str| {
+            let mut info = raid_info(raid_id, &boss_name, 0);
+            info.image = Some(image.into());
+            worker.handle_raid_info(info);
+            worker.bosses[&boss_name].boss_data.boss.image.clone().unwrap()
+        };
+
+        let small = "http://pbs.twimg.com/media/ozorotter.jpg:small";
+        let large = "https://pbs.twimg.com/media/ozorotter.jpg:large";
+        let orig = "https://pbs.twimg.com/media/ozorotter.jpg:orig";
+
+        assert_eq!(send_image("BBBB0001", small), large.into());
+        assert_eq!(send_image("BBBB0002", orig), orig.into());
+        assert_eq!(send_image("BBBB0003", small), orig.into());
+    }
+
+    #[test]
+    fn boss_image_voting() {
+        let (_client, mut worker) = builder().build();
+
+        let boss_name = BossName::from("Lvl 60 Ozorotter");
+        let mut send_image = |raid_id: &str, image: &str| {
+            let mut info = raid_info(raid_id, &boss_name, 0);
+            info.image = Some(image.into());
+            worker.handle_raid_info(info);
+            worker.bosses[&boss_name].boss_data.boss.image.clone().unwrap()
+        };
+
+        let wrong = "http://example.com/wrong.png";
+        let right = "http://example.com/ozorotter.png";
+        let right_large = "http://example.com/ozorotter.png:large";
+
+        assert_eq!(send_image("AAAA0001", wrong), wrong.into());
+        // A tie keeps the current image
+        assert_eq!(send_image("AAAA0002", right), wrong.into());
+        // Size variants count as the same image
+        assert_eq!(send_image("AAAA0003", right_large), right_large.into());
+        assert_eq!(send_image("AAAA0004", wrong), right_large.into());
+        assert_eq!(send_image("AAAA0005", wrong), wrong.into());
+
+        // Only a limited number of images are counted
+        for i in 0..20 {
+            send_image(&format!("BBBB{:04}", i), &format!("http://example.com/{}.png", i));
+        }
+        assert_eq!(worker.bosses[&boss_name].boss_data.image_votes.len(), MAX_IMAGE_VOTES);
+        assert_eq!(worker.bosses[&boss_name].boss_data.boss.image, Some(wrong.into()));
+    }
+
+    #[test]
+    fn boss_image_votes_from_metadata() {
+        let current = BossImageUrl::from("http://example.com/current.png");
+        let other = "http://example.com/other.png";
+
+        let mut boss_data = boss("Lvl 60 Ozorotter", 0);
+        boss_data.boss.image = Some(current.clone());
+        boss_data.image_votes.insert(current.clone(), 3);
+
+        // Survives a round trip through exported metadata
+        let json = ::serde_json::to_string(&boss_data).unwrap();
+        let boss_data = ::serde_json::from_str::<RaidBossMetadata>(&json).unwrap();
+        assert_eq!(boss_data.image_votes.get(&current), Some(&3));
+
+        let boss_name = boss_data.boss.name.clone();
+        let (_client, mut worker) = builder().with_bosses(vec![boss_data]).build();
+
+        for raid_id in &["AAAA0001", "AAAA0002", "AAAA0003"] {
+            let mut info = raid_info(raid_id, &boss_name, 0);
+            info.image = Some(other.into());
+            worker.handle_raid_info(info);
+        }
+        assert_eq!(worker.bosses[&boss_name].boss_data.boss.image, Some(current));
+    }
+
+    #[test]
+    fn boss_image_history() {
+        let (_client, mut worker) = builder()
+            .with_image_history(2)
+            .build();
+
+        let boss_name = BossName::from("Lvl 60 Ozorotter");
+        let images = [
+            "http://example.com/a.png",
+            "http://example.com/a.png:large",
+            "http://example.com/b.png",
+            "http://example.com/a.png",
+            "http://example.com/c.png",
+        ];
+        for (i, image) in images.iter().enumerate() {
+            let mut info = raid_info(&format!("AAAA000{}", i), &boss_name, 0);
+            info.image = Some((*image).into());
+            worker.handle_raid_info(info);
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        worker.handle_event(Event::ClientGetBossImages {
+            boss_name: boss_name.clone(),
+            sender,
+        });
+        assert_eq!(
+            receiver.wait().unwrap(),
+            vec![
+                BossImageUrl::from("http://example.com/a.png"),
+                BossImageUrl::from("http://example.com/c.png"),
+            ]
+        );
+    }
+
+    #[test]
+    fn follow_multiple_subscribers() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = testing::mock_stream();
+
+        let (client, worker) = client_builder(stream, tweet_raid_id).build();
+
+        core.handle().spawn(worker.map_err(|_| ()));
+
+        let (tx1, rx1) = mpsc::unbounded();
+        let (tx2, rx2) = mpsc::unbounded();
+        let mut sub1 = core.run(client.subscribe(tx1)).unwrap();
+        let mut sub2 = core.run(client.subscribe(tx2)).unwrap();
+
+        sub1.follow("Lvl 60 Ozorotter");
+        sub2.follow("Lvl 60 Ozorotter");
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.push(testing::raid_info("Lvl 60 Ozorotter", "AAAA0001"));
+        handle.push(testing::raid_info("Lvl 75 Ozorotter", "AAAA0002"));
+        handle.push(testing::raid_info("Lvl 60 Ozorotter", "AAAA0003"));
+        core.turn(Some(Duration::from_millis(0)));
+
+        let expected = vec!["AAAA0001".to_string(), "AAAA0003".to_string()];
+        assert_eq!(core.run(rx1.take(2).collect()).unwrap(), expected);
+        assert_eq!(core.run(rx2.take(2).collect()).unwrap(), expected);
+    }
+
+    #[test]
+    fn closed_subscribers_removed() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = testing::mock_stream();
+
+        let (client, worker) = client_builder(stream, |_| Some(()))
+            .with_metrics(metrics::simple(|m| ::serde_json::to_value(m).unwrap()))
+            .build();
+
+        core.handle().spawn(worker.map_err(|_| ()));
+
+        let (tx1, rx1) = mpsc::unbounded();
+        let (tx2, _rx2) = mpsc::unbounded();
+        let mut sub1 = core.run(client.subscribe(tx1)).unwrap();
+        let mut sub2 = core.run(client.subscribe(tx2)).unwrap();
+
+        handle.push(testing::raid_info("Lvl 60 Ozorotter", "AAAA0001"));
+        core.turn(Some(Duration::from_millis(0)));
+
+        sub1.follow("Lvl 60 Ozorotter");
+        sub2.follow("Lvl 60 Ozorotter");
+        core.turn(Some(Duration::from_millis(0)));
+
+        let metrics = core.run(client.export_metrics()).unwrap();
+        assert_eq!(metrics["total_subscriber_count"], 2);
+        assert_eq!(metrics["boss_counts"]["Lvl 60 Ozorotter"]["followers"], 2);
+
+        // The subscription is leaked, so it never unsubscribes
+        drop(rx1);
+        ::std::mem::forget(sub1);
+
+        handle.push(testing::raid_info("Lvl 60 Ozorotter", "AAAA0002"));
+        core.turn(Some(Duration::from_millis(0)));
+
+        let metrics = core.run(client.export_metrics()).unwrap();
+        assert_eq!(metrics["boss_counts"]["Lvl 60 Ozorotter"]["followers"], 1);
+
+        client.heartbeat();
+        let metrics = core.run(client.export_metrics()).unwrap();
+        assert_eq!(metrics["total_subscriber_count"], 1);
+    }
+
+    #[test]
+    fn follow_all() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = testing::mock_stream();
+
+        let (client, worker) = client_builder(stream, tweet_raid_id).build();
+
+        core.handle().spawn(worker.map_err(|_| ()));
+
+        let (tx1, rx1) = mpsc::unbounded();
+        let (tx2, rx2) = mpsc::unbounded();
+        let mut sub1 = core.run(client.subscribe(tx1)).unwrap();
+        let mut sub2 = core.run(client.subscribe(tx2)).unwrap();
+
+        sub1.follow_all();
+        sub2.follow_all();
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.push(testing::raid_info("Lvl 60 Ozorotter", "AAAA0001"));
+        handle.push(testing::raid_info("Lvl 75 Ozorotter", "AAAA0002"));
+        core.turn(Some(Duration::from_millis(0)));
+
+        sub2.unfollow_all();
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.push(testing::raid_info("Lvl 100 Ozorotter", "AAAA0003"));
+        handle.end();
+        core.turn(Some(Duration::from_millis(0)));
+        drop((client, sub1, sub2));
+
+        assert_eq!(
+            core.run(rx1.collect()).unwrap(),
+            vec!["AAAA0001", "AAAA0002", "AAAA0003"]
+        );
+        assert_eq!(core.run(rx2.collect()).unwrap(), vec!["AAAA0001", "AAAA0002"]);
+    }
+
+    #[test]
+    fn total_seen() {
+        let mut restored = boss("Lvl 75 Ozorotter", 0);
+        restored.total_seen = 100;
+
+        let (_client, mut worker) = builder()
+            .with_history_size(2)
+            .with_bosses(vec![restored])
+            .build();
+
+        for i in 0..5 {
+            let raid_id = format!("AAAA000{}", i);
+            worker.handle_raid_info(raid_info(&raid_id, "Lvl 60 Ozorotter", i));
+            worker.handle_raid_info(raid_info(&raid_id, "Lvl 75 Ozorotter", i));
+        }
+
+        let total_seen = |name: &str| worker.bosses[&BossName::from(name)].boss_data.total_seen;
+        assert_eq!(total_seen("Lvl 60 Ozorotter"), 5);
+        assert_eq!(total_seen("Lvl 75 Ozorotter"), 105);
+    }
+
+    #[test]
+    fn duplicate_window() {
+        let (_client, mut worker) = builder()
+            .with_duplicate_window(Duration::from_secs(60))
+            .build();
+
+        let boss_name = "Lvl 60 Ozorotter";
+        worker.handle_raid_info(raid_info("AAAA0000", boss_name, 0));
+        worker.handle_raid_info(raid_info("AAAA0000", boss_name, 10));
+        worker.handle_raid_info(raid_info("BBBB0000", boss_name, 20));
+        worker.handle_raid_info(raid_info("AAAA0000", boss_name, 30));
+        // Outside of the window, so this is treated as a new raid
+        worker.handle_raid_info(raid_info("AAAA0000", boss_name, 90));
+
+        let (sender, receiver) = oneshot::channel();
+        worker.handle_event(Event::ClientGetTweets {
+            boss_name: boss_name.into(),
+            sender,
+        });
+        assert_eq!(
+            raid_ids(&receiver.wait().unwrap()),
+            vec!["AAAA0000", "BBBB0000", "AAAA0000"]
+        );
+
+        let metadata = &worker.bosses[&BossName::from(boss_name)].boss_data;
+        assert_eq!(metadata.duplicate_count, 2);
+        assert_eq!(metadata.total_seen, 5);
+    }
+
+    #[test]
+    fn duplicate_window_sequence_numbers() {
+        let (_client, mut worker) = builder()
+            .with_duplicate_window(Duration::from_secs(60))
+            .build();
+
+        let boss_name = "Lvl 60 Ozorotter";
+        worker.handle_raid_info(raid_info("AAAA0000", boss_name, 0));
+        worker.handle_raid_info(raid_info("AAAA0000", boss_name, 10));
+        worker.handle_raid_info(raid_info("BBBB0000", boss_name, 20));
+        worker.handle_raid_info(raid_info("BBBB0000", boss_name, 30));
+        worker.handle_raid_info(raid_info("CCCC0000", boss_name, 40));
+
+        // Collapsed duplicates don't leave gaps
+        let seqs = worker.bosses[&BossName::from(boss_name)]
+            .recent_tweets
+            .as_unordered_slice()
+            .iter()
+            .map(|t| t.seq)
+            .collect::<Vec<_>>();
+        assert_eq!(seqs, vec![1, 2, 3]);
+        assert_eq!(worker.last_seq, 3);
+    }
+
+    #[test]
+    fn boss_update_messages() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = testing::mock_stream();
+
+        let (client, worker) = client_builder(stream, |message| match message {
+            Message::BossUpdate(boss) => Some(format!(
+                "{} {} {}",
+                boss.name,
+                boss.level.map_or("?".to_string(), |l| l.to_string()),
+                boss.image.as_ref().map_or("none", |i| i.as_str())
+            )),
+            Message::BossRemove(name) => Some(format!("remove {}", name)),
+            _ => None,
+        }).build();
+
+        core.handle().spawn(worker.map_err(|_| ()));
+
+        let (tx, rx) = mpsc::unbounded();
+        let sub = core.run(client.subscribe(tx)).unwrap();
+
+        let boss_name = "Ozorotter";
+        let image = "http://example.com/ozorotter.png";
+
+        handle.push(testing::raid_info(boss_name, "AAAA0001"));
+        handle.push(testing::raid_info(boss_name, "AAAA0002"));
+
+        let mut info = testing::raid_info(boss_name, "AAAA0003");
+        info.image = Some(image.into());
+        handle.push(info.clone());
+        handle.push(info.clone());
+
+        info.level = Some(60);
+        handle.push(info);
+        core.turn(Some(Duration::from_millis(0)));
+
+        client.remove_bosses(|_| true);
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.end();
+        core.turn(Some(Duration::from_millis(0)));
+        drop((client, sub));
+
+        assert_eq!(
+            core.run(rx.collect()).unwrap(),
+            vec![
+                "Ozorotter ? none".to_string(),
+                format!("Ozorotter ? {}", image),
+                format!("Ozorotter 60 {}", image),
+                "remove Ozorotter".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn bosses_sorted_by_name() {
+        let names = ["Lvl 75 Ozorotter", "Lvl 100 Ozorotter", "Lvl 60 Ozorotter"];
+
+        let boss_list = |message: Message| match message {
+            Message::BossList(bosses) => Some(
+                bosses
+                    .iter()
+                    .map(|b| b.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            _ => None,
+        };
+        let (_client, mut worker) =
+            client_builder::<_, mpsc::UnboundedSender<String>, _>(open_stream(), boss_list)
+                .with_bosses(names.iter().map(|name| boss(name, 0)).collect())
+                .build();
+
+        let (sender, receiver) = oneshot::channel();
+        worker.handle_event(Event::ClientGetBosses {
+            filter: BossFilter::default(),
+            sender,
+        });
+
+        let expected = vec!["Lvl 100 Ozorotter", "Lvl 60 Ozorotter", "Lvl 75 Ozorotter"];
+        assert_eq!(boss_names(&receiver.wait().unwrap()), expected);
+        assert_eq!(worker.cached_boss_list, Some(expected.join(", ")));
+    }
+
+    #[test]
+    fn remove_boss() {
+        let (_client, mut worker) = builder()
+            .with_bosses(vec![boss("Lvl 60 Ozorotter", 0), boss("Lvl 75 Ozorotter", 0)])
+            .build();
+
+        let remove_boss = |worker: &mut Worker<_, _, _, _, _, _>, boss_name: &str| {
+            let (sender, receiver) = oneshot::channel();
+            worker.handle_event(Event::ClientRemoveBoss {
+                boss_name: boss_name.into(),
+                sender,
+            });
+            receiver.wait().unwrap()
+        };
+
+        assert!(remove_boss(&mut worker, "Lvl 60 Ozorotter"));
+        assert!(!remove_boss(&mut worker, "Lvl 60 Ozorotter"));
+        assert!(!remove_boss(&mut worker, "Lvl 100 Ozorotter"));
+
+        let (sender, receiver) = oneshot::channel();
+        worker.handle_event(Event::ClientGetBosses {
+            filter: BossFilter::default(),
+            sender,
+        });
+        assert_eq!(
+            boss_names(&receiver.wait().unwrap()),
+            vec!["Lvl 75 Ozorotter"]
+        );
+    }
+
+    #[test]
+    fn follow_unseen_boss() {
+        let (client, mut worker) = client_builder(open_stream(), tweet_raid_id).build();
+
+        let subscribe = |worker: &mut Worker<_, _, _, _, _, _>| {
+            let (tx, rx) = mpsc::unbounded();
+            let (sender, receiver) = oneshot::channel();
+            worker.handle_event(Event::SubscriberSubscribe {
+                subscriber: tx,
+                client: client.clone(),
+                sender,
+            });
+            (receiver.wait().unwrap(), rx)
+        };
+
+        let (sub1, rx1) = subscribe(&mut worker);
+        let (sub2, rx2) = subscribe(&mut worker);
+
+        let boss_name = BossName::from("Lvl 250 Beelzebub");
+        for sub in &[&sub1, &sub2] {
+            worker.handle_event(Event::SubscriberFollow {
+                id: sub.id.clone(),
+                boss_name: boss_name.clone(),
+            });
+        }
+        assert_eq!(worker.requested_bosses[&boss_name].subscriber_count(), 2);
+
+        // Unfollowing a boss that was never seen only removes that follower,
+        // and the entry goes away once nobody is waiting for it
+        worker.handle_event(Event::SubscriberUnfollow {
+            id: sub2.id.clone(),
+            boss_name: boss_name.clone(),
+        });
+        assert_eq!(worker.requested_bosses[&boss_name].subscriber_count(), 1);
+
+        worker.handle_raid_info(raid_info("AAAA0001", &boss_name, 0));
+        assert!(worker.requested_bosses.is_empty());
+        assert_eq!(worker.bosses[&boss_name].broadcast.subscriber_count(), 1);
+
+        worker.handle_event(Event::SubscriberFollow {
+            id: sub2.id.clone(),
+            boss_name: "Lvl 300 Lucilius".into(),
+        });
+        worker.handle_event(Event::SubscriberUnfollow {
+            id: sub2.id.clone(),
+            boss_name: "Lvl 300 Lucilius".into(),
+        });
+        assert!(worker.requested_bosses.is_empty());
+
+        drop((worker, sub1, sub2));
+        assert_eq!(rx1.collect().wait().unwrap(), vec!["AAAA0001"]);
+        assert!(rx2.collect().wait().unwrap().is_empty());
+    }
+
+    #[test]
+    fn follow_matching() {
+        let (client, mut worker) = client_builder(open_stream(), tweet_raid_id).build();
+
+        worker.handle_raid_info(raid_info("AAAA0001", "Lvl 100 Fenrir", 0));
+        worker.handle_raid_info(raid_info("AAAA0002", "Lvl 60 Ozorotter", 1));
+
+        let (tx, rx) = mpsc::unbounded();
+        let (sender, receiver) = oneshot::channel();
+        worker.handle_event(Event::SubscriberSubscribe {
+            subscriber: tx,
+            client: client.clone(),
+            sender,
+        });
+        let sub = receiver.wait().unwrap();
+
+        worker.handle_event(Event::SubscriberFollowMatching {
+            id: sub.id.clone(),
+            pattern: Regex::new("Fenrir$").unwrap(),
+        });
+
+        // Bosses seen after the pattern was added are followed too
+        worker.handle_raid_info(raid_info("BBBB0001", "Lvl 100 Fenrir", 2));
+        worker.handle_raid_info(raid_info("BBBB0002", "Lvl 60 Ozorotter", 3));
+        worker.handle_raid_info(raid_info("BBBB0003", "Lvl 120 Fenrir", 4));
+        assert_eq!(
+            worker.bosses[&BossName::from("Lvl 120 Fenrir")]
+                .broadcast
+                .subscriber_count(),
+            1
+        );
+
+        worker.handle_event(Event::SubscriberUnsubscribe(sub.id.clone()));
+        assert!(worker.follow_patterns.is_empty());
+        for entry in worker.bosses.values() {
+            assert_eq!(entry.broadcast.subscriber_count(), 0);
+        }
+
+        drop((worker, sub));
+        assert_eq!(rx.collect().wait().unwrap(), vec!["BBBB0001", "BBBB0003"]);
+    }
+
+    #[test]
+    fn subscribe_with_snapshot() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = testing::mock_stream();
+
+        let (client, worker) = client_builder(stream, |message| match message {
+            Message::BossUpdate(boss) => Some(boss.name.to_string()),
+            _ => None,
+        }).with_bosses(vec![boss("Lvl 75 Ozorotter", 0), boss("Lvl 60 Ozorotter", 0)])
+            .build();
+
+        core.handle().spawn(worker.map_err(|_| ()));
+
+        handle.push(testing::raid_info("Lvl 100 Ozorotter", "AAAA0001"));
+        core.turn(Some(Duration::from_millis(0)));
+
+        let (tx, rx) = mpsc::unbounded();
+        let (bosses, sub) = core.run(client.subscribe_with_snapshot(tx)).unwrap();
+        assert_eq!(
+            boss_names(&bosses),
+            vec!["Lvl 100 Ozorotter", "Lvl 60 Ozorotter", "Lvl 75 Ozorotter"]
+        );
+
+        handle.push(testing::raid_info("Lvl 250 Beelzebub", "AAAA0002"));
+        handle.end();
+        core.turn(Some(Duration::from_millis(0)));
+        drop((client, sub));
+
+        // Bosses in the snapshot aren't sent again
+        assert_eq!(core.run(rx.collect()).unwrap(), vec!["Lvl 250 Beelzebub"]);
+    }
+
+    #[test]
+    fn follow_with_info() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = testing::mock_stream();
+
+        let (client, worker) = client_builder(stream, |message| match message {
+            Message::Tweet(tweet) => Some(format!("tweet {}", tweet.raid_id)),
+            Message::BossUpdate(boss) => Some(format!(
+                "boss {} {}",
+                boss.name,
+                boss.image.as_ref().map_or("none", |i| i.as_str())
+            )),
+            _ => None,
+        }).with_bosses(vec![boss("Lvl 60 Ozorotter", 0)])
+            .build();
+
+        core.handle().spawn(worker.map_err(|_| ()));
+
+        let (tx1, rx1) = mpsc::unbounded();
+        let mut sub1 = core.run(client.subscribe(tx1)).unwrap();
+        let (tx2, rx2) = mpsc::unbounded();
+        let mut sub2 = core.run(client.subscribe(tx2)).unwrap();
+
+        // Followed before the boss is ever seen
+        sub1.follow_with_info("Lvl 250 Beelzebub");
+        sub2.follow_with_info("Lvl 60 Ozorotter");
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.push(testing::raid_info("Lvl 250 Beelzebub", "AAAA0001"));
+        core.turn(Some(Duration::from_millis(0)));
+
+        // Updates are sent again when the boss changes
+        let mut info = testing::raid_info("Lvl 250 Beelzebub", "AAAA0002");
+        info.image = Some("http://example.com/beelzebub.png".into());
+        handle.push(info);
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.end();
+        core.turn(Some(Duration::from_millis(0)));
+        drop((client, sub1, sub2));
+
+        assert_eq!(
+            core.run(rx1.collect()).unwrap(),
+            vec![
+                "boss Lvl 250 Beelzebub none",
+                "tweet AAAA0001",
+                "boss Lvl 250 Beelzebub http://example.com/beelzebub.png",
+                "tweet AAAA0002",
+            ]
+        );
+
+        // Everyone gets boss updates, but only followers get tweets
+        assert_eq!(
+            core.run(rx2.collect()).unwrap(),
+            vec![
+                "boss Lvl 60 Ozorotter none",
+                "boss Lvl 250 Beelzebub none",
+                "boss Lvl 250 Beelzebub http://example.com/beelzebub.png",
+            ]
+        );
+    }
+
+    #[test]
+    fn removed_bosses() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = testing::mock_stream();
+
+        let (client, worker) = builder()
+            .with_stream(stream)
+            .with_bosses(vec![
+                boss("Lvl 60 Ozorotter", 0),
+                boss("Lvl 75 Ozorotter", 0),
+                boss("Lvl 100 Ozorotter", 0),
+            ])
+            .build();
+
+        let (worker_tx, worker_rx) = oneshot::channel();
+        core.handle()
+            .spawn(worker.then(|_| worker_tx.send(()).map_err(|_| ())));
+
+        let removed = client.removed_bosses();
+        assert!(core.run(client.remove_boss("Lvl 60 Ozorotter")).unwrap());
+        assert!(!core.run(client.remove_boss("Lvl 60 Ozorotter")).unwrap());
+        client.remove_bosses(|meta| meta.boss.name.as_str() == "Lvl 100 Ozorotter");
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.end();
+        core.run(worker_rx).unwrap();
+
+        assert_eq!(
+            core.run(removed.collect()).unwrap(),
+            vec!["Lvl 60 Ozorotter".into(), "Lvl 100 Ozorotter".into()] as Vec<BossName>
+        );
+    }
+
+    #[test]
+    fn hooks() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let (e1, e2, e3) = (events.clone(), events.clone(), events.clone());
+
+        let (_client, mut worker) = builder()
+            .with_duplicate_window(Duration::from_secs(60))
+            .on_new_boss(move |boss| e1.borrow_mut().push(format!("new {}", boss.name)))
+            .on_raid(move |tweet| e2.borrow_mut().push(format!("raid {}", tweet.raid_id)))
+            .on_boss_removed(move |name| e3.borrow_mut().push(format!("removed {}", name)))
+            .build();
+
+        worker.handle_raid_info(raid_info("AAAA0001", "Lvl 60 Ozorotter", 0));
+        worker.handle_raid_info(raid_info("AAAA0001", "Lvl 60 Ozorotter", 1));
+        worker.handle_raid_info(raid_info("AAAA0002", "Lvl 60 Ozorotter", 2));
+        worker.remove_boss(&"Lvl 60 Ozorotter".into());
+
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                "new Lvl 60 Ozorotter",
+                "raid AAAA0001",
+                "raid AAAA0002",
+                "removed Lvl 60 Ozorotter",
+            ]
+        );
+    }
+
+    #[test]
+    fn removed_boss_followers() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = testing::mock_stream();
+
+        let (client, worker) = client_builder(stream, |message| match message {
+            Message::Tweet(tweet) => Some(format!("tweet {}", tweet.raid_id)),
+            Message::BossRemove(name) => Some(format!("remove {}", name)),
+            _ => None,
+        }).build();
+
+        core.handle().spawn(worker.map_err(|_| ()));
+
+        let (follower_tx, follower_rx) = mpsc::unbounded();
+        let mut follower = core.run(client.subscribe(follower_tx)).unwrap();
+        follower.follow("Ozorotter");
+
+        let (firehose_tx, firehose_rx) = mpsc::unbounded();
+        let mut firehose = core.run(client.subscribe(firehose_tx)).unwrap();
+        firehose.follow_all();
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.push(testing::raid_info("Ozorotter", "AAAA0001"));
+        core.turn(Some(Duration::from_millis(0)));
+
+        // Expire bosses that haven't been seen in the last minute
+        let cutoff = Utc.timestamp(60, 0);
+        client.remove_bosses(move |meta| meta.last_seen < cutoff);
+        core.turn(Some(Duration::from_millis(0)));
+
+        // Followers are kept, and get tweets again once the boss comes back
+        let mut info = testing::raid_info("Ozorotter", "AAAA0002");
+        info.tweet.created_at = Utc.timestamp(120, 0);
+        handle.push(info);
+        core.turn(Some(Duration::from_millis(0)));
+
+        client.remove_bosses(|_| true);
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.end();
+        core.turn(Some(Duration::from_millis(0)));
+        drop((client, follower, firehose));
+
+        let expected = vec![
+            "tweet AAAA0001",
+            "remove Ozorotter",
+            "tweet AAAA0002",
+            "remove Ozorotter",
+        ];
+        assert_eq!(core.run(follower_rx.collect()).unwrap(), expected);
+        assert_eq!(core.run(firehose_rx.collect()).unwrap(), expected);
+    }
+
+    #[test]
+    fn follow_with_backlog() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = testing::mock_stream();
+
+        let (client, worker) = client_builder(stream, |message| match message {
+            Message::Tweet(tweet) => Some(tweet.seq),
+            _ => None,
+        }).with_history_size(5)
+            .build();
+
+        core.handle().spawn(worker.map_err(|_| ()));
+
+        let (tx, rx) = mpsc::unbounded();
+        let mut sub = core.run(client.subscribe(tx)).unwrap();
+
+        for i in 0..8 {
+            handle.push(testing::raid_info("Lvl 60 Ozorotter", &i.to_string()));
+        }
+        core.turn(Some(Duration::from_millis(0)));
+
+        // The follow may be handled at any point between these tweets
+        for i in 8..12 {
+            handle.push(testing::raid_info("Lvl 60 Ozorotter", &i.to_string()));
+        }
+        sub.follow_with_backlog("Lvl 60 Ozorotter");
+        for i in 12..16 {
+            handle.push(testing::raid_info("Lvl 60 Ozorotter", &i.to_string()));
+        }
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.end();
+        core.turn(Some(Duration::from_millis(0)));
+        drop((client, sub));
+
+        let seqs = core.run(rx.collect()).unwrap();
+        assert!(seqs.len() >= 8);
+        assert_eq!(seqs, (seqs[0]..17).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn heartbeat_removes_closed_subscribers() {
+        let (client, mut worker) = client_builder(open_stream(), |_| None::<()>)
+            .with_metrics(metrics::simple(|m| m.clone()))
+            .with_bosses(vec![boss("Lvl 60 Ozorotter", 0)])
+            .build();
+
+        let boss_name = BossName::from("Lvl 60 Ozorotter");
+        let subscribe = |worker: &mut Worker<_, _, _, _, _, _>| {
+            let (tx, rx) = bounded::channel(10, bounded::DropPolicy::DropNewest);
+            let (sender, receiver) = oneshot::channel();
+            worker.handle_event(Event::SubscriberSubscribe {
+                subscriber: tx,
+                client: client.clone(),
+                sender,
+            });
+            let sub = receiver.wait().unwrap();
+
+            for &boss_name in &["Lvl 60 Ozorotter", "Lvl 75 Ozorotter"] {
+                worker.handle_event(Event::SubscriberFollow {
+                    id: sub.id.clone(),
+                    boss_name: boss_name.into(),
+                });
+            }
+            worker.handle_event(Event::SubscriberFollowAll {
+                id: sub.id.clone(),
+                filter: SubscriptionFilter::default(),
+            });
+            worker.handle_event(Event::SubscriberFollowMatching {
+                id: sub.id.clone(),
+                pattern: Regex::new("Ozorotter$").unwrap(),
+            });
+
+            (sub, rx)
+        };
+
+        let (_sub1, rx1) = subscribe(&mut worker);
+        let (_sub2, _rx2) = subscribe(&mut worker);
+        assert_eq!(worker.bosses[&boss_name].broadcast.subscriber_count(), 2);
+
+        // No tweets arrive after the receiver is dropped
+        drop(rx1);
+        worker.handle_event(Event::SubscriberHeartbeat);
+
+        assert_eq!(worker.subscribers.subscriber_count(), 1);
+        assert_eq!(worker.bosses[&boss_name].broadcast.subscriber_count(), 1);
+        assert_eq!(
+            worker.requested_bosses[&BossName::from("Lvl 75 Ozorotter")].subscriber_count(),
+            1
+        );
+        assert_eq!(worker.firehose.subscriber_count(), 1);
+        assert_eq!(worker.firehose_filters.len(), 1);
+        assert_eq!(worker.subscribed_at.len(), 1);
+        assert_eq!(worker.follow_patterns.len(), 1);
+
+        let (sender, receiver) = oneshot::channel();
+        worker.handle_event(Event::ClientExportMetrics(sender));
+        let metrics = receiver.wait().unwrap();
+        assert_eq!(metrics.total_subscriber_count(), 1);
+        assert_eq!(metrics.follower_count(&boss_name), Some(1));
+    }
+
+    #[test]
+    fn follower_metrics() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = testing::mock_stream();
+
+        let (client, worker) = client_builder(stream, |_| None::<()>)
+            .with_metrics(metrics::simple(|m| m.clone()))
+            .build();
+
+        core.handle().spawn(worker.map_err(|_| ()));
+
+        let boss_name = BossName::from("Lvl 60 Ozorotter");
+        let (tx1, _rx1) = mpsc::unbounded();
+        let (tx2, _rx2) = mpsc::unbounded();
+        let mut sub1 = core.run(client.subscribe(tx1)).unwrap();
+        let mut sub2 = core.run(client.subscribe(tx2)).unwrap();
+
+        // Follow before the boss has been seen
+        sub1.follow(boss_name.clone());
+        sub2.follow(boss_name.clone());
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.push(testing::raid_info(boss_name.clone(), "AAAA0001"));
+        core.turn(Some(Duration::from_millis(0)));
+
+        let metrics = core.run(client.export_metrics()).unwrap();
+        assert_eq!(metrics.total_subscriber_count(), 2);
+        assert_eq!(metrics.follower_count(&boss_name), Some(2));
+        assert_eq!(metrics.tweet_count(&boss_name), Some(1));
+
+        drop(sub1);
+        let metrics = core.run(client.export_metrics()).unwrap();
+        assert_eq!(metrics.total_subscriber_count(), 1);
+        assert_eq!(metrics.follower_count(&boss_name), Some(1));
+
+        sub2.unfollow(boss_name.clone());
+        let metrics = core.run(client.export_metrics()).unwrap();
+        assert_eq!(metrics.follower_count(&boss_name), Some(0));
+    }
+
+    #[test]
+    fn subscription_stats() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = testing::mock_stream();
+
+        let (client, worker) = client_builder(stream, |message| match message {
+            Message::Tweet(_) => Some(()),
+            _ => None,
+        }).with_clock(|| Utc.timestamp(100, 0))
+            .build();
+
+        core.handle().spawn(worker.map_err(|_| ()));
+
+        let (tx1, _rx1) = bounded::channel(2, bounded::DropPolicy::DropNewest);
+        let (tx2, rx2) = bounded::channel(10, bounded::DropPolicy::DropNewest);
+        let mut sub1 = core.run(client.subscribe(tx1)).unwrap();
+        let mut sub2 = core.run(client.subscribe(tx2)).unwrap();
+
+        sub1.follow_many(vec!["Lvl 60 Ozorotter", "Lvl 75 Ozorotter"]);
+        sub2.follow_all();
+        sub2.follow("Lvl 75 Ozorotter");
+        core.turn(Some(Duration::from_millis(0)));
+
+        for i in 0..3 {
+            handle.push(testing::raid_info("Lvl 60 Ozorotter", &i.to_string()));
+        }
+        core.turn(Some(Duration::from_millis(0)));
+
+        let stats = core.run(client.subscription_stats()).unwrap();
+        let json = ::serde_json::to_value(&stats).unwrap();
+
+        assert_eq!(
+            json,
+            json!([
+                {
+                    "subscription_id": 0,
+                    "boss_name": "Lvl 60 Ozorotter",
+                    "delivered": 3,
+                    "dropped": 1,
+                    "last_delivered": "1970-01-01T00:01:40Z",
+                },
+                {
+                    // Only the boss whose tweets overflowed counts the drop
+                    "subscription_id": 0,
+                    "boss_name": "Lvl 75 Ozorotter",
+                    "delivered": 0,
+                    "dropped": 0,
+                },
+                {
+                    "subscription_id": 1,
+                    "delivered": 3,
+                    "dropped": 0,
+                    "last_delivered": "1970-01-01T00:01:40Z",
+                },
+                {
+                    "subscription_id": 1,
+                    "boss_name": "Lvl 75 Ozorotter",
+                    "delivered": 0,
+                    "dropped": 0,
+                },
+            ])
+        );
+
+        assert_eq!(core.run(client.dropped_events()).unwrap(), 1);
+
+        let infos = core.run(client.subscriptions()).unwrap();
+        let dropped = infos.iter().map(|i| i.dropped).collect::<Vec<_>>();
+        assert_eq!(dropped, vec![1, 0]);
+
+        drop(sub1);
+        let stats = core.run(client.subscription_stats()).unwrap();
+        assert!(stats.iter().all(|s| s.subscription_id == 1));
+
+        // Tweets for subscribers that are gone aren't counted as dropped
+        drop(rx2);
+        handle.push(testing::raid_info("Lvl 60 Ozorotter", "3"));
+        core.turn(Some(Duration::from_millis(0)));
+        assert_eq!(core.run(client.dropped_events()).unwrap(), 1);
+    }
+
+    #[test]
+    fn kill_subscription() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = testing::mock_stream();
+
+        let (client, worker) = client_builder(stream, tweet_raid_id)
+            .with_clock(|| Utc.timestamp(100, 0))
+            .build();
+
+        core.handle().spawn(worker.map_err(|_| ()));
+
+        let (tx1, rx1) = mpsc::unbounded();
+        let (tx2, rx2) = mpsc::unbounded();
+        let mut sub1 = core.run(client.subscribe(tx1)).unwrap();
+        let mut sub2 = core.run(client.subscribe(tx2)).unwrap();
+
+        sub1.follow_many(vec!["Lvl 75 Ozorotter", "Lvl 60 Ozorotter"]);
+        sub2.follow_all();
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.push(testing::raid_info("Lvl 60 Ozorotter", "AAAA0001"));
+        core.turn(Some(Duration::from_millis(0)));
+
+        let infos = core.run(client.subscriptions()).unwrap();
+        assert_eq!(
+            ::serde_json::to_value(&infos).unwrap(),
+            json!([
+                {
+                    "subscription_id": 0,
+                    "following": ["Lvl 60 Ozorotter", "Lvl 75 Ozorotter"],
+                    "follows_all": false,
+                    "subscribed_at": "1970-01-01T00:01:40Z",
+                    "delivered": 1,
+                    "dropped": 0,
+                },
+                {
+                    "subscription_id": 1,
+                    "following": [],
+                    "follows_all": true,
+                    "subscribed_at": "1970-01-01T00:01:40Z",
+                    "delivered": 1,
+                    "dropped": 0,
+                },
+            ])
+        );
+
+        client.kill_subscription(sub1.id());
+        core.turn(Some(Duration::from_millis(0)));
+
+        // The killed subscription's stream ends even though its handle is alive
+        assert_eq!(core.run(rx1.collect()).unwrap(), vec!["AAAA0001"]);
+        let infos = core.run(client.subscriptions()).unwrap();
+        assert_eq!(
+            infos.iter().map(|i| i.subscription_id).collect::<Vec<_>>(),
+            vec![1]
+        );
+
+        // Following again from the killed handle does nothing, and its ID
+        // isn't reused until the handle is dropped
+        sub1.follow("Lvl 60 Ozorotter");
+        let (tx3, rx3) = mpsc::unbounded();
+        let sub3 = core.run(client.subscribe(tx3)).unwrap();
+        assert_eq!(sub3.id(), 2);
+        drop(sub1);
+
+        handle.push(testing::raid_info("Lvl 60 Ozorotter", "AAAA0002"));
+        handle.end();
+        core.turn(Some(Duration::from_millis(0)));
+        drop((client, sub2, sub3));
+
+        assert_eq!(
+            core.run(rx2.collect()).unwrap(),
+            vec!["AAAA0001", "AAAA0002"]
+        );
+        assert!(core.run(rx3.collect()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tweets_since() {
+        let (_client, mut worker) = builder().build();
+
+        worker.handle_raid_info(raid_info("AAAA0003", "Lvl 60 Ozorotter", 30));
+        worker.handle_raid_info(raid_info("AAAA0001", "Lvl 60 Ozorotter", 10));
+        worker.handle_raid_info(raid_info("AAAA0002", "Lvl 60 Ozorotter", 20));
+        worker.handle_raid_info(raid_info("AAAA0004", "Lvl 75 Ozorotter", 40));
+
+        let (sender, receiver) = oneshot::channel();
+        worker.handle_event(Event::ClientGetTweetsSince {
+            boss_name: "Lvl 60 Ozorotter".into(),
+            since: Utc.timestamp(10, 0),
+            sender,
+        });
+
+        let tweets = receiver.wait().unwrap();
+        assert_eq!(raid_ids(&tweets), vec!["AAAA0002", "AAAA0003"]);
+    }
+
+    #[test]
+    fn tweets_before() {
+        let (_client, mut worker) = builder().build();
+
+        worker.handle_raid_info(raid_info("AAAA0003", "Lvl 60 Ozorotter", 30));
+        worker.handle_raid_info(raid_info("AAAA0001", "Lvl 60 Ozorotter", 10));
+        worker.handle_raid_info(raid_info("AAAA0002", "Lvl 60 Ozorotter", 20));
+        worker.handle_raid_info(raid_info("AAAA0004", "Lvl 75 Ozorotter", 25));
+
+        let mut tweets_before = |before: TweetPosition, limit| {
+            let (sender, receiver) = oneshot::channel();
+            worker.handle_event(Event::ClientGetTweetsBefore {
+                boss_name: "Lvl 60 Ozorotter".into(),
+                before,
+                limit,
+                sender,
+            });
+            receiver.wait().unwrap()
+        };
+        let at = |timestamp| Utc.timestamp(timestamp, 0).into();
+
+        assert_eq!(raid_ids(&tweets_before(at(40), 2)), vec!["AAAA0003", "AAAA0002"]);
+        assert_eq!(raid_ids(&tweets_before(at(20), 2)), vec!["AAAA0001"]);
+        assert!(tweets_before(at(10), 2).is_empty());
+    }
+
+    #[test]
+    fn tweets_before_same_time() {
+        let (_client, mut worker) = builder().build();
+
+        for raid_id in &["AAAA0001", "AAAA0002", "AAAA0003"] {
+            worker.handle_raid_info(raid_info(raid_id, "Lvl 60 Ozorotter", 10));
+        }
+
+        let mut tweets_before = |before: TweetPosition| {
+            let (sender, receiver) = oneshot::channel();
+            worker.handle_event(Event::ClientGetTweetsBefore {
+                boss_name: "Lvl 60 Ozorotter".into(),
+                before,
+                limit: 2,
+                sender,
+            });
+            receiver.wait().unwrap()
+        };
+
+        let page = tweets_before(Utc.timestamp(20, 0).into());
+        assert_eq!(raid_ids(&page), vec!["AAAA0003", "AAAA0002"]);
+
+        // The next page starts right after the last tweet, even though the
+        // next tweet has the same timestamp
+        let page = tweets_before(page[1].position());
+        assert_eq!(raid_ids(&page), vec!["AAAA0001"]);
+        assert!(tweets_before(page[0].position()).is_empty());
+    }
+
+    #[test]
+    fn follow_many() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = testing::mock_stream();
+
+        let (client, worker) = client_builder(stream, tweet_raid_id).build();
+
+        core.handle().spawn(worker.map_err(|_| ()));
+
+        let (tx, rx) = mpsc::unbounded();
+        let mut sub = core.run(client.subscribe(tx)).unwrap();
+
+        handle.push(testing::raid_info("Lvl 60 Ozorotter", "AAAA0001"));
+        core.turn(Some(Duration::from_millis(0)));
+
+        sub.follow_many(vec!["Lvl 60 Ozorotter", "Lvl 75 Ozorotter"]);
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.push(testing::raid_info("Lvl 60 Ozorotter", "AAAA0002"));
+        handle.push(testing::raid_info("Lvl 75 Ozorotter", "AAAA0003"));
+        handle.push(testing::raid_info("Lvl 100 Ozorotter", "AAAA0004"));
+        core.turn(Some(Duration::from_millis(0)));
+
+        sub.unfollow("Lvl 60 Ozorotter");
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.push(testing::raid_info("Lvl 60 Ozorotter", "AAAA0005"));
+        handle.push(testing::raid_info("Lvl 75 Ozorotter", "AAAA0006"));
+        handle.end();
+        core.turn(Some(Duration::from_millis(0)));
+        drop((client, sub));
+
+        assert_eq!(
+            core.run(rx.collect()).unwrap(),
+            vec!["AAAA0002", "AAAA0003", "AAAA0006"]
+        );
+    }
+
+    #[test]
+    fn follow_all_filtered() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = testing::mock_stream();
+
+        let (client, worker) = client_builder(stream, |message| match message {
+            Message::Tweet(tweet) => Some(tweet.boss_name.to_string()),
+            _ => None,
+        }).build();
+
+        core.handle().spawn(worker.map_err(|_| ()));
+
+        let (tx1, rx1) = mpsc::unbounded();
+        let (tx2, rx2) = mpsc::unbounded();
+        let (tx3, rx3) = mpsc::unbounded();
+        let mut sub1 = core.run(client.subscribe(tx1)).unwrap();
+        let mut sub2 = core.run(client.subscribe(tx2)).unwrap();
+        let mut sub3 = core.run(client.subscribe(tx3)).unwrap();
+
+        sub1.follow_all_filtered(SubscriptionFilter {
+            min_level: Some(100),
+            ..SubscriptionFilter::default()
+        });
+        sub2.follow_all_filtered(SubscriptionFilter {
+            min_level: Some(100),
+            include_unknown_level: true,
+            ..SubscriptionFilter::default()
+        });
+        sub3.follow_all_filtered(SubscriptionFilter {
+            max_level: Some(75),
+            ..SubscriptionFilter::default()
+        });
+        core.turn(Some(Duration::from_millis(0)));
+
+        for boss_name in &["Lvl 60 Ozorotter", "Lvl 100 Ozorotter", "Ozorotter"] {
+            handle.push(testing::raid_info(*boss_name, "AAAA0001"));
+        }
+        handle.end();
+        core.turn(Some(Duration::from_millis(0)));
+        drop((client, sub1, sub2, sub3));
+
+        assert_eq!(core.run(rx1.collect()).unwrap(), vec!["Lvl 100 Ozorotter"]);
+        assert_eq!(
+            core.run(rx2.collect()).unwrap(),
+            vec!["Lvl 100 Ozorotter", "Ozorotter"]
+        );
+        assert_eq!(
+            core.run(rx3.collect()).unwrap(),
+            vec!["Lvl 60 Ozorotter", "Ozorotter"]
+        );
+    }
+
+    #[test]
+    fn follow_all_filtered_language() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = testing::mock_stream();
+
+        let mut en = boss("Lvl 120 Grimnir", 0);
+        let mut jp = boss("Lv120 グリームニル", 0);
+        jp.boss.language = Language::Japanese;
+        en.boss.translations.insert(jp.boss.name.clone());
+        jp.boss.translations.insert(en.boss.name.clone());
+
+        let (client, worker) = client_builder(stream, tweet_raid_id)
+            .with_bosses(vec![en, jp])
+            .build();
+
+        core.handle().spawn(worker.map_err(|_| ()));
+
+        let (tx, rx) = mpsc::unbounded();
+        let mut sub = core.run(client.subscribe(tx)).unwrap();
+        sub.follow_all_filtered(SubscriptionFilter {
+            language: Some(Language::English),
+            ..SubscriptionFilter::default()
+        });
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.push(testing::raid_info("Lvl 120 Grimnir", "AAAA0001"));
+
+        // Tweets for the Japanese boss are not delivered, even though it is a
+        // translation of the English boss. The boss' stored language is used,
+        // rather than the language of the tweet.
+        handle.push(testing::raid_info("Lv120 グリームニル", "AAAA0002"));
+
+        handle.end();
+        core.turn(Some(Duration::from_millis(0)));
+        drop((client, sub));
+
+        assert_eq!(core.run(rx.collect()).unwrap(), vec!["AAAA0001"]);
+    }
+
+    #[test]
+    fn shared_serialized_payload() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = testing::mock_stream();
+
+        // Messages are serialized once, and the result is cloned for each
+        // subscriber. With an `Arc`, every subscriber gets the same bytes.
+        let (client, worker) = client_builder(stream, |message| match message {
+            Message::Tweet(_) => ::serde_json::to_vec(&message).ok().map(Arc::new),
+            _ => None,
+        }).build();
+
+        core.handle().spawn(worker.map_err(|_| ()));
+
+        let (tx1, rx1) = mpsc::unbounded();
+        let (tx2, rx2) = mpsc::unbounded();
+        let mut sub1 = core.run(client.subscribe(tx1)).unwrap();
+        let mut sub2 = core.run(client.subscribe(tx2)).unwrap();
+        sub1.follow("Lvl 60 Ozorotter");
+        sub2.follow_all();
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.push(testing::raid_info("Lvl 60 Ozorotter", "AAAA0001"));
+        handle.end();
+        core.turn(Some(Duration::from_millis(0)));
+        drop((client, sub1, sub2));
+
+        let payload1 = core.run(rx1.collect()).unwrap().pop().unwrap();
+        let payload2 = core.run(rx2.collect()).unwrap().pop().unwrap();
+
+        assert!(Arc::ptr_eq(&payload1, &payload2));
+
+        let json: ::serde_json::Value = ::serde_json::from_slice(&payload1).unwrap();
+        assert_eq!(json["Tweet"]["raid_id"], "AAAA0001");
+    }
+
+    #[test]
+    fn heartbeat_idle() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = testing::mock_stream();
+        let clock = clock::Mock::new(Utc.timestamp(0, 0));
+
+        let (client, worker) = client_builder(stream, |message| match message {
+            Message::Tweet(tweet) => Some(tweet.raid_id.clone()),
+            Message::Heartbeat => Some("heartbeat".to_string()),
+            _ => None,
+        }).with_clock(clock.clone())
+            .build();
+
+        core.handle().spawn(worker.map_err(|_| ()));
+
+        let (tx1, rx1) = mpsc::unbounded();
+        let (tx2, rx2) = mpsc::unbounded();
+        let mut sub1 = core.run(client.subscribe(tx1)).unwrap();
+        let mut sub2 = core.run(client.subscribe(tx2)).unwrap();
+        sub1.follow("Lvl 60 Ozorotter");
+        sub2.follow("Lvl 75 Ozorotter");
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.push(testing::raid_info("Lvl 60 Ozorotter", "AAAA0001"));
+        handle.push(testing::raid_info("Lvl 75 Ozorotter", "AAAA0002"));
+        core.turn(Some(Duration::from_millis(0)));
+
+        // Only the subscriber that hasn't received a tweet in the past
+        // 30 seconds gets a heartbeat
+        clock.set(Utc.timestamp(20, 0));
+        handle.push(testing::raid_info("Lvl 60 Ozorotter", "AAAA0003"));
+        core.turn(Some(Duration::from_millis(0)));
+
+        clock.set(Utc.timestamp(40, 0));
+        client.heartbeat_idle(Duration::from_secs(30));
+        core.turn(Some(Duration::from_millis(0)));
+
+        handle.end();
+        core.turn(Some(Duration::from_millis(0)));
+        drop((client, sub1, sub2));
+
+        assert_eq!(
+            core.run(rx1.collect()).unwrap(),
+            vec!["AAAA0001", "AAAA0003"]
+        );
+        assert_eq!(
+            core.run(rx2.collect()).unwrap(),
+            vec!["AAAA0002", "heartbeat"]
+        );
+    }
+
+    #[test]
+    fn bosses_active_within() {
+        let clock = clock::Mock::new(Utc.timestamp(100, 0));
+
+        let (_client, mut worker) = builder()
+            .with_bosses(vec![
+                boss("Lvl 60 Ozorotter", 90),
+                boss("Lvl 75 Ozorotter", 80),
+                boss("Lvl 100 Grimnir", 50),
+            ])
+            .with_clock(clock.clone())
+            .build();
+
+        let mut get_bosses = |filter| {
+            let (sender, receiver) = oneshot::channel();
+            worker.handle_event(Event::ClientGetBosses { filter, sender });
+            let mut bosses = receiver.wait().unwrap();
+            bosses.sort_by(|a, b| a.name.cmp(&b.name));
+            bosses
+        };
+
+        let active = BossFilter {
+            active_within: Some(::chrono::Duration::seconds(20)),
+            ..BossFilter::default()
+        };
+        assert_eq!(
+            boss_names(&get_bosses(active.clone())),
+            vec!["Lvl 60 Ozorotter", "Lvl 75 Ozorotter"]
+        );
+
+        let active_above_70 = BossFilter {
+            min_level: Some(70),
+            ..active.clone()
+        };
+        assert_eq!(
+            boss_names(&get_bosses(active_above_70)),
+            vec!["Lvl 75 Ozorotter"]
+        );
+
+        // Bosses that go quiet drop out of the results, but aren't removed
+        clock.advance(::chrono::Duration::seconds(5));
+        assert_eq!(boss_names(&get_bosses(active)), vec!["Lvl 60 Ozorotter"]);
+        assert_eq!(get_bosses(BossFilter::default()).len(), 3);
+    }
+
+    #[test]
+    fn bosses_sorted() {
+        let (_client, mut worker) = builder()
+            .with_bosses(vec![
+                boss("Lvl 75 Ozorotter", 10),
+                boss("Lvl 60 Ozorotter", 20),
+                boss("Lvl 75 Grimnir", 30),
+                boss("Unknown Ozorotter", 0),
+            ])
+            .build();
+
+        let mut get_sorted = |sort_key| {
+            let (sender, receiver) = oneshot::channel();
+            worker.handle_event(Event::ClientGetBossesSorted { sort_key, sender });
+            receiver.wait().unwrap()
+        };
+
+        assert_eq!(
+            boss_names(&get_sorted(BossSortKey::Level)),
+            vec![
+                "Lvl 60 Ozorotter",
+                "Lvl 75 Grimnir",
+                "Lvl 75 Ozorotter",
+                "Unknown Ozorotter",
+            ]
+        );
+        assert_eq!(
+            boss_names(&get_sorted(BossSortKey::LastSeen)),
+            vec![
+                "Lvl 75 Grimnir",
+                "Lvl 60 Ozorotter",
+                "Lvl 75 Ozorotter",
+                "Unknown Ozorotter",
+            ]
+        );
+        assert_eq!(
+            boss_names(&get_sorted(BossSortKey::Name)),
+            vec![
+                "Lvl 60 Ozorotter",
+                "Lvl 75 Grimnir",
+                "Lvl 75 Ozorotter",
+                "Unknown Ozorotter",
+            ]
+        );
+    }
+}
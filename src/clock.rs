@@ -0,0 +1,67 @@
+use chrono::{self, Utc};
+use model::DateTime;
+use std::cell::Cell;
+use std::rc::Rc;
+
+pub trait Clock {
+    fn now(&self) -> DateTime;
+}
+
+#[derive(Clone, Debug)]
+pub struct System;
+impl Clock for System {
+    fn now(&self) -> DateTime {
+        Utc::now()
+    }
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> DateTime,
+{
+    fn now(&self) -> DateTime {
+        self()
+    }
+}
+
+// A clock that only changes when told to. Clones share the same time, so a
+// test can keep one handle and pass another to the `ClientBuilder`.
+#[derive(Clone, Debug)]
+pub struct Mock(Rc<Cell<DateTime>>);
+impl Mock {
+    pub fn new(now: DateTime) -> Self {
+        Mock(Rc::new(Cell::new(now)))
+    }
+
+    pub fn set(&self, now: DateTime) {
+        self.0.set(now);
+    }
+
+    pub fn advance(&self, duration: chrono::Duration) {
+        self.0.set(self.0.get() + duration);
+    }
+}
+
+impl Clock for Mock {
+    fn now(&self) -> DateTime {
+        self.0.get()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn mock_shared_between_clones() {
+        let clock = Mock::new(Utc.timestamp(100, 0));
+        let other = clock.clone();
+
+        clock.advance(chrono::Duration::seconds(20));
+        assert_eq!(other.now(), Utc.timestamp(120, 0));
+
+        other.set(Utc.timestamp(50, 0));
+        assert_eq!(clock.now(), Utc.timestamp(50, 0));
+    }
+}
@@ -3,6 +3,23 @@ error_chain!{
         Twitter {
             description("Twitter streaming error")
         }
+        RateLimited(wait: ::std::time::Duration) {
+            description("rate limited by Twitter")
+            display("rate limited by Twitter, retry after {:?}", wait)
+        }
+        AuthenticationFailed {
+            description("Twitter rejected the credentials")
+        }
+        HttpStatus(code: u16) {
+            description("unexpected HTTP status from Twitter")
+            display("unexpected HTTP status from Twitter: {}", code)
+        }
+        ConnectionReset {
+            description("connection to Twitter failed or was closed")
+        }
+        Stalled {
+            description("no data received from Twitter within the stall timeout")
+        }
         Json(s: String) {
             description("could not parse JSON")
             display("failed to parse JSON: {}", s)
@@ -10,8 +27,22 @@ error_chain!{
         Closed {
             description("channel closed by sender")
         }
+        Timeout {
+            description("timed out waiting for a response")
+        }
         ImageHash {
             description("failed to compute image hash")
         }
+        Webhook(s: String) {
+            description("failed to send webhook")
+            display("failed to send webhook: {}", s)
+        }
+        BearerTokenNeedsClient {
+            description("bearer tokens can only connect with `connect_with_client`")
+        }
+        InvalidPattern(s: String) {
+            description("invalid parser pattern")
+            display("invalid parser pattern: {}", s)
+        }
     }
 }
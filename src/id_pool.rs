@@ -1,6 +1,18 @@
 #[derive(Clone, Hash, Debug, PartialEq, Eq)]
 pub struct Id(u32);
 
+impl Id {
+    #[inline]
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+
+    #[inline]
+    pub fn from_u32(id: u32) -> Self {
+        Id(id)
+    }
+}
+
 #[derive(Debug)]
 pub struct IdPool {
     max_id: u32,
@@ -97,8 +97,7 @@ where
                 // If image hashing fails, we don't want to error out,
                 // we can just retry next time we get an image.
                 if let Err(ref e) = image_hash {
-                    // TODO: Maybe don't eprintln
-                    eprintln!("Failed to get image hash: {:?}", e);
+                    warn!("Failed to get image hash for {}: {}", boss_name, e);
                 }
 
                 Ok(BossImageHash {
@@ -5,12 +5,17 @@ extern crate futures;
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
+extern crate log;
+#[macro_use]
 extern crate serde_derive;
 
 extern crate chrono;
 extern crate hyper;
 extern crate image;
 extern crate regex;
+extern crate serde;
+#[cfg_attr(test, macro_use)]
+extern crate serde_json;
 extern crate string_cache;
 extern crate tokio_core;
 extern crate twitter_stream;
@@ -18,13 +23,23 @@ extern crate twitter_stream;
 mod client;
 pub mod model;
 pub mod raid;
+pub mod twitter_v2;
 pub mod error;
 mod id_pool;
 mod broadcast;
 mod circular_buffer;
 mod image_hash;
 pub mod metrics;
+pub mod clock;
+pub mod webhook;
+pub mod bounded;
+mod rate_limit;
+mod capture;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 pub use broadcast::{NoOpSubscriber, Subscriber};
-pub use client::{Client, ClientBuilder, Subscription, Worker};
+pub use rate_limit::{RateLimit, RateLimitPolicy};
+pub use client::{Client, ClientBuilder, Subscription, SubscriptionFilter, Worker};
 pub use twitter_stream::Token;
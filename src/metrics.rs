@@ -48,6 +48,20 @@ pub struct SimpleMetrics {
     boss_counts: HashMap<BossName, Counts>,
 }
 
+impl SimpleMetrics {
+    pub fn total_subscriber_count(&self) -> u32 {
+        self.total_subscriber_count
+    }
+
+    pub fn follower_count(&self, boss_name: &BossName) -> Option<u32> {
+        self.boss_counts.get(boss_name).map(|c| c.followers)
+    }
+
+    pub fn tweet_count(&self, boss_name: &BossName) -> Option<u32> {
+        self.boss_counts.get(boss_name).map(|c| c.tweets)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize)]
 struct Counts {
     followers: u32,
@@ -72,8 +86,8 @@ where
             }
             Entry::Vacant(e) => {
                 e.insert(Counts {
-                    followers: 0,
-                    tweets: 1,
+                    followers: count,
+                    tweets: 0,
                 });
             }
         }
@@ -103,3 +117,30 @@ where
         (self.export_function)(&self.inner)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn simple_counts() {
+        let mut metrics = simple(|m| m.clone());
+        let boss_name = BossName::from("Lvl 60 Ozorotter");
+
+        metrics.set_follower_count(&boss_name, 3);
+        metrics.inc_tweet_count(&boss_name);
+        metrics.inc_tweet_count(&boss_name);
+        metrics.set_total_subscriber_count(5);
+
+        let exported = metrics.export();
+        assert_eq!(exported.total_subscriber_count(), 5);
+        assert_eq!(exported.follower_count(&boss_name), Some(3));
+        assert_eq!(exported.tweet_count(&boss_name), Some(2));
+
+        metrics.set_follower_count(&boss_name, 1);
+        assert_eq!(metrics.export().follower_count(&boss_name), Some(1));
+
+        metrics.remove_boss(&boss_name);
+        assert_eq!(metrics.export().follower_count(&boss_name), None);
+    }
+}
@@ -1,13 +1,14 @@
 use chrono;
 pub use image_hash::ImageHash;
 use regex::Regex;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::ops::Deref;
 use std::sync::Arc;
 use string_cache::DefaultAtom;
 pub type DateTime = chrono::DateTime<chrono::Utc>;
 pub type TweetId = u64;
+pub type UserId = u64;
 pub type RaidId = String;
 pub type BossLevel = i16;
 
@@ -25,16 +26,32 @@ pub enum Message<'a> {
     BossUpdate(&'a RaidBoss),
     BossList(&'a [&'a RaidBoss]),
     BossRemove(&'a BossName),
+    // Number of tweets for a rate limited follow that weren't sent since the
+    // last one that was. Comes right before the boss' next delivered tweet.
+    Skipped { boss_name: BossName, count: u64 },
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct RaidBoss {
     pub name: BossName,
-    pub level: BossLevel,
+    // `None` if the level couldn't be determined
+    #[serde(default, deserialize_with = "zero_level::deserialize")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<BossLevel>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub image: Option<BossImageUrl>,
     pub language: Language,
     pub translations: HashSet<BossName>,
+    // `None` if the element couldn't be determined from the name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub element: Option<Element>,
+}
+
+impl RaidBoss {
+    #[inline]
+    pub fn has_image(&self) -> bool {
+        self.image.is_some()
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
@@ -42,6 +59,73 @@ pub struct RaidBossMetadata {
     pub boss: RaidBoss,
     pub last_seen: DateTime,
     pub image_hash: Option<ImageHash>,
+    // Number of tweets ever seen for this boss, not just the ones in history
+    #[serde(default)]
+    pub total_seen: u64,
+    // Number of tweets that were collapsed into an earlier tweet for the
+    // same raid, if a duplicate window is set
+    #[serde(default)]
+    pub duplicate_count: u64,
+    // Number of tweets that weren't sent to subscribers because of the rate
+    // limit. They're still kept in the boss' tweet history.
+    #[serde(default)]
+    pub throttled_count: u64,
+    // Number of tweets seen with each image, ignoring size variants. The
+    // image seen most often becomes the boss' image.
+    #[serde(default)]
+    pub image_votes: HashMap<BossImageUrl, u32>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BossSortKey {
+    // Lowest level first, then by name
+    Level,
+    // Most recently seen first
+    LastSeen,
+    Name,
+}
+
+// Restricts which bosses are returned by `Client::bosses_filtered`. Fields
+// that are `None` match every boss, and bosses with an unknown level are
+// excluded if `min_level` is set, the same as `SubscriptionFilter`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BossFilter {
+    pub language: Option<Language>,
+    pub min_level: Option<BossLevel>,
+    pub max_level: Option<BossLevel>,
+    pub has_image: Option<bool>,
+    pub active_within: Option<chrono::Duration>,
+}
+
+impl BossFilter {
+    // `active_within` is measured back from `now`
+    pub fn matches(&self, data: &RaidBossMetadata, now: DateTime) -> bool {
+        let boss = &data.boss;
+        let language = self.language.map(|lang| lang == boss.language);
+        let has_image = self.has_image.map(|has_image| has_image == boss.has_image());
+        let is_active = self.active_within
+            .map(|within| now.signed_duration_since(data.last_seen) <= within);
+
+        language.unwrap_or(true) && has_image.unwrap_or(true) && is_active.unwrap_or(true)
+            && level_matches(boss.level, self.min_level, self.max_level, false)
+    }
+}
+
+// Level bounds for `BossFilter` and `SubscriptionFilter`. An unknown level is
+// excluded if there's a minimum, unless `include_unknown` is true.
+pub(crate) fn level_matches(
+    level: Option<BossLevel>,
+    min_level: Option<BossLevel>,
+    max_level: Option<BossLevel>,
+    include_unknown: bool,
+) -> bool {
+    match level {
+        Some(level) => {
+            min_level.map(|min| level >= min).unwrap_or(true)
+                && max_level.map(|max| level <= max).unwrap_or(true)
+        }
+        None => include_unknown || min_level.is_none(),
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
@@ -71,7 +155,20 @@ where
 
 impl BossName {
     pub fn parse_level(&self) -> Option<BossLevel> {
-        REGEX_BOSS_NAME.captures(self.0.as_ref()).and_then(|c| {
+        self.parse_level_with(&REGEX_BOSS_NAME)
+    }
+
+    // Looks for a known boss name within the name, so it works for both the
+    // English and Japanese names, with or without the level
+    pub fn parse_element(&self) -> Option<Element> {
+        BOSS_ELEMENTS
+            .iter()
+            .find(|&&(name, _)| self.as_str().contains(name))
+            .map(|&(_, element)| element)
+    }
+
+    pub(crate) fn parse_level_with(&self, regex: &Regex) -> Option<BossLevel> {
+        regex.captures(self.0.as_ref()).and_then(|c| {
             c.name("level")
                 .and_then(|l| l.as_str().parse::<BossLevel>().ok())
         })
@@ -83,6 +180,56 @@ impl BossName {
     }
 }
 
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[non_exhaustive]
+pub enum Element {
+    Fire,
+    Water,
+    Earth,
+    Wind,
+    Light,
+    Dark,
+}
+
+const BOSS_ELEMENTS: [(&str, Element); 36] = [
+    ("Colossus", Element::Fire),
+    ("コロッサス", Element::Fire),
+    ("Leviathan", Element::Water),
+    ("リヴァイアサン", Element::Water),
+    ("Yggdrasil", Element::Earth),
+    ("ユグドラシル", Element::Earth),
+    ("Tiamat", Element::Wind),
+    ("ティアマト", Element::Wind),
+    ("Luminiera", Element::Light),
+    ("シュヴァリエ", Element::Light),
+    ("Celeste", Element::Dark),
+    ("セレスト", Element::Dark),
+    ("Shiva", Element::Fire),
+    ("シヴァ", Element::Fire),
+    ("Europa", Element::Water),
+    ("エウロペ", Element::Water),
+    ("Alexiel", Element::Earth),
+    ("アレクシエル", Element::Earth),
+    ("Grimnir", Element::Wind),
+    ("グリームニル", Element::Wind),
+    ("Metatron", Element::Light),
+    ("メタトロン", Element::Light),
+    ("Avatar", Element::Dark),
+    ("アバター", Element::Dark),
+    ("Prometheus", Element::Fire),
+    ("プロメテウス", Element::Fire),
+    ("Ca Ong", Element::Water),
+    ("カー・オン", Element::Water),
+    ("Gilgamesh", Element::Earth),
+    ("ギルガメッシュ", Element::Earth),
+    ("Morrigna", Element::Wind),
+    ("バイヴカハ", Element::Wind),
+    ("Hector", Element::Light),
+    ("ヘクトル", Element::Light),
+    ("Anubis", Element::Dark),
+    ("アヌビス", Element::Dark),
+];
+
 #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct BossImageUrl(DefaultAtom);
 impl Deref for BossImageUrl {
@@ -92,11 +239,65 @@ impl Deref for BossImageUrl {
     }
 }
 
+// Size variants that Twitter media URLs can be suffixed with, e.g. `:small`
+const IMAGE_SIZE_VARIANTS: [&str; 5] = ["thumb", "small", "medium", "large", "orig"];
+const TWITTER_MEDIA_PREFIX: &str = "https://pbs.twimg.com/media/";
+const TWITTER_MEDIA_PREFIX_HTTP: &str = "http://pbs.twimg.com/media/";
+
 impl BossImageUrl {
     #[inline]
     pub fn as_str(&self) -> &str {
         self
     }
+
+    // Splits the URL into the base URL and the size variant, if any
+    pub(crate) fn split_variant(&self) -> (&str, Option<&str>) {
+        let url = self.as_str();
+
+        if let Some(index) = url.rfind(':') {
+            let (base, variant) = (&url[..index], &url[index + 1..]);
+            if IMAGE_SIZE_VARIANTS.contains(&variant) {
+                return (base, Some(variant));
+            }
+        }
+
+        (url, None)
+    }
+
+    // Returns true if both URLs point to the same image, but this one is a
+    // larger size variant. URLs without a variant are treated as `medium`.
+    pub fn is_larger_variant_of(&self, other: &BossImageUrl) -> bool {
+        fn rank(variant: Option<&str>) -> Option<usize> {
+            let variant = variant.unwrap_or("medium");
+            IMAGE_SIZE_VARIANTS.iter().position(|v| *v == variant)
+        }
+
+        let (base, variant) = self.split_variant();
+        let (other_base, other_variant) = other.split_variant();
+
+        base == other_base && rank(variant) > rank(other_variant)
+    }
+
+    // Rewrites Twitter media URLs to always use `https` and at least the
+    // `:large` variant, so the same image is usually represented by the
+    // same URL. `:orig` is kept, so it can still replace a `:large` image.
+    // Other URLs are unchanged.
+    pub fn normalize(&self) -> BossImageUrl {
+        let (base, variant) = self.split_variant();
+        let is_http = base.starts_with(TWITTER_MEDIA_PREFIX_HTTP);
+
+        if !is_http && !base.starts_with(TWITTER_MEDIA_PREFIX) {
+            return self.clone();
+        }
+
+        let canonical = if variant == Some("orig") { "orig" } else { "large" };
+        if !is_http && variant == Some(canonical) {
+            return self.clone();
+        }
+
+        let base = base.replacen(TWITTER_MEDIA_PREFIX_HTTP, TWITTER_MEDIA_PREFIX, 1);
+        BossImageUrl::from(format!("{}:{}", base, canonical))
+    }
 }
 
 impl<T> From<T> for BossImageUrl
@@ -117,16 +318,156 @@ impl fmt::Display for BossImageUrl {
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct RaidTweet {
+    #[serde(with = "id_string")]
     pub tweet_id: TweetId,
     pub boss_name: BossName,
     pub raid_id: String,
-    pub user: String,
+    // The @handle, which users can change
+    pub user_screen_name: String,
+    pub user_display_name: String,
+    #[serde(with = "id_string")]
+    pub user_id: UserId,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user_image: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub text: Option<String>,
     pub created_at: DateTime,
     pub language: Language,
+    // Assigned by the worker when the tweet is accepted. Increases
+    // monotonically across all bosses, starting at 1.
+    #[serde(default)]
+    pub seq: u64,
+    // Only kept if the client was built with `keep_raw_json`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+impl RaidTweet {
+    pub fn position(&self) -> TweetPosition {
+        TweetPosition {
+            created_at: self.created_at,
+            seq: self.seq,
+        }
+    }
+
+    pub fn tweet_url(&self) -> String {
+        format!("https://twitter.com/{}/status/{}", self.user_screen_name, self.tweet_id)
+    }
+
+    // Raids don't say when they expire, so this is an estimate based on the
+    // typical time limit for a boss of this level. `created_at` is the only
+    // measured time, and raids can end much earlier if the boss is defeated.
+    pub fn estimated_expiry(&self, level: BossLevel) -> DateTime {
+        self.created_at + estimated_raid_duration(level)
+    }
+}
+
+// Tweet and user IDs are too large to be exact as JavaScript numbers, so
+// they're serialized as strings. Numbers are still accepted when
+// deserializing.
+mod id_string {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrNumber {
+        String(String),
+        Number(u64),
+    }
+
+    pub fn serialize<S>(id: &u64, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(id)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<u64, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match StringOrNumber::deserialize(deserializer)? {
+            StringOrNumber::String(id) => id.parse().map_err(de::Error::custom),
+            StringOrNumber::Number(id) => Ok(id),
+        }
+    }
+}
+
+// Metadata from before levels were optional stores unknown levels as 0
+mod zero_level {
+    use super::BossLevel;
+    use serde::{Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<BossLevel>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let level = Option::<BossLevel>::deserialize(deserializer)?;
+        Ok(level.and_then(|level| if level == 0 { None } else { Some(level) }))
+    }
+}
+
+pub fn estimated_raid_duration(level: BossLevel) -> chrono::Duration {
+    let minutes = if level < 100 { 60 } else { 90 };
+
+    chrono::Duration::minutes(minutes)
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SubscriptionStats {
+    pub subscription_id: u32,
+    // `None` if this is for a subscription that follows all bosses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub boss_name: Option<BossName>,
+    // Messages accepted by the subscriber, including ones it later dropped
+    pub delivered: u64,
+    // Messages for this boss that the subscriber dropped
+    pub dropped: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_delivered: Option<DateTime>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SubscriptionInfo {
+    pub subscription_id: u32,
+    // Bosses followed individually, sorted by name
+    pub following: Vec<BossName>,
+    pub follows_all: bool,
+    pub subscribed_at: DateTime,
+    // Tweets sent and dropped, summed across everything the subscription
+    // follows
+    pub delivered: u64,
+    pub dropped: u64,
+}
+
+// A position in a boss' tweet history, for paging backwards through it.
+// Tweets are ordered by `created_at`, then by `seq`, since many tweets can
+// share the same second. A bare `DateTime` is before every tweet created at
+// that time.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct TweetPosition {
+    pub created_at: DateTime,
+    pub seq: u64,
+}
+
+impl From<DateTime> for TweetPosition {
+    fn from(created_at: DateTime) -> Self {
+        TweetPosition { created_at, seq: 0 }
+    }
+}
+
+impl<'a> From<&'a RaidTweet> for TweetPosition {
+    fn from(tweet: &'a RaidTweet) -> Self {
+        tweet.position()
+    }
+}
+
+// A batch of tweets returned by a cursor-based fetch. Passing `cursor`
+// back in the next request returns only tweets that arrived since.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TweetPage {
+    pub tweets: Vec<Arc<RaidTweet>>,
+    pub cursor: u64,
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -135,3 +476,231 @@ pub enum Language {
     English,
     Other,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn larger_image_variant() {
+        let url = |s: &str| BossImageUrl::from(format!("http://example.com/abcd.jpg{}", s));
+
+        assert!(url(":large").is_larger_variant_of(&url(":small")));
+        assert!(url(":orig").is_larger_variant_of(&url(":large")));
+        assert!(url(":small").is_larger_variant_of(&url(":thumb")));
+        assert!(url("").is_larger_variant_of(&url(":small")));
+        assert!(url(":large").is_larger_variant_of(&url("")));
+
+        assert!(!url(":small").is_larger_variant_of(&url(":large")));
+        assert!(!url(":large").is_larger_variant_of(&url(":large")));
+        assert!(!url(":medium").is_larger_variant_of(&url("")));
+
+        let other = BossImageUrl::from("http://example.com/efgh.jpg:thumb");
+        assert!(!url(":orig").is_larger_variant_of(&other));
+    }
+
+    #[test]
+    fn normalize_image_url() {
+        let large = BossImageUrl::from("https://pbs.twimg.com/media/abcd.jpg:large");
+
+        assert_eq!(
+            BossImageUrl::from("https://pbs.twimg.com/media/abcd.jpg").normalize(),
+            large
+        );
+        assert_eq!(
+            BossImageUrl::from("https://pbs.twimg.com/media/abcd.jpg:small").normalize(),
+            large
+        );
+        assert_eq!(large.normalize(), large);
+        assert_eq!(
+            BossImageUrl::from("http://pbs.twimg.com/media/abcd.jpg:large").normalize(),
+            large
+        );
+        assert_eq!(
+            BossImageUrl::from("http://pbs.twimg.com/media/abcd.jpg").normalize(),
+            large
+        );
+
+        let orig = BossImageUrl::from("https://pbs.twimg.com/media/abcd.jpg:orig");
+        assert_eq!(orig.normalize(), orig);
+        assert_eq!(
+            BossImageUrl::from("http://pbs.twimg.com/media/abcd.jpg:orig").normalize(),
+            orig
+        );
+    }
+
+    #[test]
+    fn normalize_image_url_other_host() {
+        let url = BossImageUrl::from("http://example.com/image.png");
+        assert_eq!(url.normalize(), url);
+
+        let url = BossImageUrl::from("http://example.com:8080/image.png:small");
+        assert_eq!(url.normalize(), url);
+    }
+
+    #[test]
+    fn parse_element() {
+        let element = |name: &str| BossName::from(name).parse_element();
+
+        assert_eq!(element("Lvl 70 Colossus Omega"), Some(Element::Fire));
+        assert_eq!(element("Lv60 リヴァイアサン・マグナ"), Some(Element::Water));
+        assert_eq!(element("Lvl 100 Celeste Omega"), Some(Element::Dark));
+        assert_eq!(element("Lvl 60 Ozorotter"), None);
+    }
+
+    #[test]
+    fn boss_filter() {
+        use chrono::TimeZone;
+
+        let now = chrono::Utc.timestamp(130, 0);
+        let boss = |level, image: Option<&str>, language| RaidBossMetadata {
+            boss: RaidBoss {
+                name: "Lvl 60 Ozorotter".into(),
+                level,
+                image: image.map(BossImageUrl::from),
+                language,
+                translations: HashSet::new(),
+                element: None,
+            },
+            last_seen: chrono::Utc.timestamp(100, 0),
+            image_hash: None,
+            total_seen: 0,
+            duplicate_count: 0,
+            throttled_count: 0,
+            image_votes: HashMap::new(),
+        };
+
+        let with_image = boss(Some(60), Some("http://example.com/a.png"), Language::English);
+        let without_image = boss(Some(120), None, Language::Japanese);
+        let unknown_level = boss(None, None, Language::English);
+
+        let filter = BossFilter::default();
+        assert!(filter.matches(&with_image, now));
+        assert!(filter.matches(&unknown_level, now));
+
+        let filter = BossFilter {
+            has_image: Some(true),
+            ..BossFilter::default()
+        };
+        assert!(filter.matches(&with_image, now));
+        assert!(!filter.matches(&without_image, now));
+
+        let filter = BossFilter {
+            language: Some(Language::Japanese),
+            min_level: Some(100),
+            ..BossFilter::default()
+        };
+        assert!(!filter.matches(&with_image, now));
+        assert!(filter.matches(&without_image, now));
+        assert!(!filter.matches(&unknown_level, now));
+
+        // Like `SubscriptionFilter`, an unknown level is only excluded by a minimum
+        let filter = BossFilter {
+            max_level: Some(100),
+            ..BossFilter::default()
+        };
+        assert!(filter.matches(&with_image, now));
+        assert!(!filter.matches(&without_image, now));
+        assert!(filter.matches(&unknown_level, now));
+
+        let filter = BossFilter {
+            active_within: Some(chrono::Duration::seconds(30)),
+            ..BossFilter::default()
+        };
+        assert!(filter.matches(&with_image, now));
+        assert!(!filter.matches(&with_image, now + chrono::Duration::seconds(1)));
+    }
+
+    #[test]
+    fn old_metadata_unknown_level() {
+        let old_json = |level: BossLevel| {
+            json!({
+                "boss": {
+                    "name": "Ozorotter",
+                    "level": level,
+                    "language": "English",
+                    "translations": [],
+                },
+                "last_seen": "2017-05-01T00:00:00Z",
+                "image_hash": null,
+            })
+        };
+
+        let metadata = serde_json::from_value::<RaidBossMetadata>(old_json(0)).unwrap();
+        assert_eq!(metadata.boss.level, None);
+        assert_eq!(metadata.total_seen, 0);
+        assert!(metadata.image_votes.is_empty());
+
+        let metadata = serde_json::from_value::<RaidBossMetadata>(old_json(60)).unwrap();
+        assert_eq!(metadata.boss.level, Some(60));
+
+        let mut json = serde_json::to_value(&metadata).unwrap();
+        json["boss"].as_object_mut().unwrap().remove("level");
+        let metadata = serde_json::from_value::<RaidBossMetadata>(json).unwrap();
+        assert_eq!(metadata.boss.level, None);
+    }
+
+    #[test]
+    fn tweet_id_as_string() {
+        use chrono::TimeZone;
+
+        let tweet = RaidTweet {
+            tweet_id: 9007199254740993,
+            boss_name: "Lvl 60 Ozorotter".into(),
+            raid_id: "ABCD1234".into(),
+            user_screen_name: "walfieee".into(),
+            user_display_name: "walfie".into(),
+            user_id: 1234567890,
+            user_image: None,
+            text: None,
+            created_at: chrono::Utc.timestamp(1000, 0),
+            language: Language::English,
+            seq: 0,
+            raw: None,
+        };
+        assert_eq!(
+            tweet.tweet_url(),
+            "https://twitter.com/walfieee/status/9007199254740993"
+        );
+
+        let json = serde_json::to_value(&tweet).unwrap();
+        assert_eq!(json["tweet_id"], json!("9007199254740993"));
+        assert_eq!(json["user_id"], json!("1234567890"));
+        assert_eq!(serde_json::from_value::<RaidTweet>(json.clone()).unwrap(), tweet);
+
+        // IDs serialized as numbers are still readable
+        let mut old_json = json;
+        old_json["tweet_id"] = json!(9007199254740993u64);
+        assert_eq!(serde_json::from_value::<RaidTweet>(old_json).unwrap(), tweet);
+    }
+
+    #[test]
+    fn estimated_expiry() {
+        use chrono::TimeZone;
+
+        let tweet = RaidTweet {
+            tweet_id: 0,
+            boss_name: "Lvl 60 Ozorotter".into(),
+            raid_id: "ABCD1234".into(),
+            user_screen_name: "walfieee".into(),
+            user_display_name: "walfie".into(),
+            user_id: 1234567890,
+            user_image: None,
+            text: None,
+            created_at: chrono::Utc.timestamp(1000, 0),
+            language: Language::English,
+            seq: 0,
+            raw: None,
+        };
+
+        assert_eq!(
+            tweet.estimated_expiry(60),
+            chrono::Utc.timestamp(1000 + 60 * 60, 0)
+        );
+        assert_eq!(
+            tweet.estimated_expiry(120),
+            chrono::Utc.timestamp(1000 + 90 * 60, 0)
+        );
+    }
+}
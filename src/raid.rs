@@ -1,16 +1,32 @@
 use error::*;
-use futures::{Async, Future, Poll, Stream};
-use futures::future::FlattenStream;
+use futures::{stream, Async, Future, Poll, Stream};
+use futures::unsync::mpsc;
 use hyper;
-use model::{BossImageUrl, Language, RaidTweet};
+use model::{BossImageUrl, BossLevel, BossName, DateTime, Language, RaidTweet, TweetId, UserId};
+use chrono::{TimeZone, Utc};
 use regex::Regex;
-use tokio_core::reactor::Handle;
-use twitter_stream::{FutureTwitterStream, Token, TwitterStreamBuilder};
+use serde_json;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{HashSet, VecDeque};
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio_core::reactor::{Handle, Timeout};
+use twitter_stream;
+use twitter_stream::{Token, TwitterStreamBuilder};
 use twitter_stream::message::StreamMessage;
 use twitter_stream::message::Tweet;
+use twitter_stream::message::entities::{Entities, Media};
+use twitter_stream::types::JsonStr;
+use twitter_v2;
 
 const GRANBLUE_APP_SOURCE: &'static str =
 r#"<a href="http://granbluefantasy.jp/" rel="nofollow">グランブルー ファンタジー</a>"#;
+// The v2 API only sends the name of the app
+const GRANBLUE_APP_NAME: &str = "グランブルー ファンタジー";
 
 lazy_static! {
     static ref REGEX_JAPANESE: Regex = Regex::new("\
@@ -29,186 +45,2654 @@ lazy_static! {
 
     static ref REGEX_IMAGE_URL: Regex = Regex::new("^https?://[^ ]+$")
         .expect("invalid image URL regex");
+
+    static ref DEFAULT_PARSER_CONFIG: ParserConfig = ParserConfig::default();
+}
+
+type TwitterJsonStream = Box<Stream<Item = JsonStr, Error = twitter_stream::Error>>;
+
+// Backoff defaults follow Twitter's guidance for HTTP errors: start at 5
+// seconds and double each attempt, up to 320 seconds. Rate limit responses
+// (420 or 429) start at one minute instead.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReconnectConfig {
+    pub initial_backoff: Duration,
+    pub initial_rate_limit_backoff: Duration,
+    // Applies to rate limit backoffs too, unless it's lower than the initial
+    // rate limit backoff
+    pub max_backoff: Duration,
+    // Consecutive failed attempts before the stream gives up and returns an
+    // error. `None` retries forever.
+    pub max_retries: Option<u32>,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            initial_backoff: Duration::from_secs(5),
+            initial_rate_limit_backoff: Duration::from_secs(60),
+            max_backoff: Duration::from_secs(320),
+            max_retries: None,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    // Delay before reconnecting, after `attempt` consecutive failures
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        exponential_backoff(self.initial_backoff, self.max_backoff, attempt)
+    }
+
+    // Like `backoff`, but for when Twitter responded with a rate limit error
+    pub fn rate_limit_backoff(&self, attempt: u32) -> Duration {
+        let initial = self.initial_rate_limit_backoff;
+        exponential_backoff(initial, self.max_backoff.max(initial), attempt)
+    }
+}
+
+// What the stream does when the connection fails or ends. This is shorthand
+// for `ReconnectConfig::max_retries`, which is the only retry limit: `Abort`
+// is no `ReconnectConfig`, and the others set `max_retries`. Retries wait for
+// the backoff from the `ReconnectConfig`, and a retry is counted as failed if
+// the new connection fails before receiving anything.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StreamErrorPolicy {
+    // Return the error from the stream, or end it if the connection ended
+    Abort,
+    // Give up after this many consecutive failed attempts
+    Retry(u32),
+    RetryForever,
+}
+
+fn exponential_backoff(initial: Duration, max: Duration, attempt: u32) -> Duration {
+    1u32.checked_shl(attempt)
+        .and_then(|factor| initial.checked_mul(factor))
+        .map(|backoff| backoff.min(max))
+        .unwrap_or(max)
+}
+
+fn is_rate_limited(error: &twitter_stream::Error) -> bool {
+    match *error {
+        twitter_stream::Error::Http(status) => {
+            let code = status.as_u16();
+            code == 420 || code == 429
+        }
+        _ => false,
+    }
+}
+
+// Errors from `twitter_stream` that aren't given a more specific kind are
+// `ErrorKind::Twitter`
+fn stream_error_kind(error: &twitter_stream::Error, rate_limit_wait: Duration) -> ErrorKind {
+    match *error {
+        twitter_stream::Error::Http(status) => match status.as_u16() {
+            401 | 403 => ErrorKind::AuthenticationFailed,
+            420 | 429 => ErrorKind::RateLimited(rate_limit_wait),
+            code => ErrorKind::HttpStatus(code),
+        },
+        twitter_stream::Error::Hyper(_) | twitter_stream::Error::TimedOut => {
+            ErrorKind::ConnectionReset
+        }
+        _ => ErrorKind::Twitter,
+    }
+}
+
+// Whether reconnecting could fix the error. Other 4xx responses mean the
+// request itself was rejected, so they're treated like bad credentials.
+fn is_transient(kind: &ErrorKind) -> bool {
+    match *kind {
+        ErrorKind::AuthenticationFailed => false,
+        ErrorKind::HttpStatus(code) => code >= 500,
+        _ => true,
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConnectionCounts {
+    // Including the first connection
+    pub connection_attempts: u64,
+    // Errors, stalls, and ends of the underlying stream
+    pub disconnects: u64,
+    // Statuses that couldn't be written by `RaidInfoStream::record_to`
+    pub record_errors: u64,
+    // Connections opened after the first one. A count that keeps rising
+    // usually means the credentials are bad or being rate limited.
+    pub reconnects: u64,
+    pub last_reconnect: Option<DateTime>,
+}
+
+// Sent to the receivers from `RaidInfoStream::connection_events`. A
+// connection only counts as connected once something is received from it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionEvent {
+    // `token` is the index of the token used for the connection, in the
+    // order they were given to `RaidInfoStreamBuilder::with_tokens`
+    Connected {
+        at: DateTime,
+        token: usize,
+    },
+    // Sent whether or not the connection was ever connected
+    Disconnected {
+        at: DateTime,
+        reason: String,
+    },
+    // `attempt` starts at 1, and is reset once a connection is connected
+    Reconnecting {
+        at: DateTime,
+        attempt: u32,
+        delay: Duration,
+        token: usize,
+    },
+}
+
+impl ConnectionEvent {
+    pub fn at(&self) -> DateTime {
+        match *self {
+            ConnectionEvent::Connected { at, .. }
+            | ConnectionEvent::Disconnected { at, .. }
+            | ConnectionEvent::Reconnecting { at, .. } => at,
+        }
+    }
+}
+
+// Shared with the stream that created it, so the counts can still be read
+// after the stream is moved into a `Worker`
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionStats(Rc<Cell<ConnectionCounts>>);
+impl ConnectionStats {
+    pub fn get(&self) -> ConnectionCounts {
+        self.0.get()
+    }
+
+    fn update<F>(&self, f: F)
+    where
+        F: FnOnce(&mut ConnectionCounts),
+    {
+        let mut counts = self.0.get();
+        f(&mut counts);
+        self.0.set(counts);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StreamCounts {
+    // Every status received, whether or not it's a tweet
+    pub received: usize,
+    // Tweets that were parsed as raid tweets
+    pub raids: usize,
+    // Statuses that couldn't be parsed as JSON
+    pub parse_failures: usize,
+    // Tweets with no usable date, which were given the current time instead
+    pub invalid_dates: usize,
+    // Tweets dropped because their ID was recently seen
+    pub duplicates: usize,
+}
+
+// Counters for what the stream has received. Unlike `ConnectionStats`, this
+// can be sent to other threads, e.g. to be logged periodically. Keep-alive
+// newlines are dropped by `twitter_stream`, so they aren't counted.
+#[derive(Clone, Debug, Default)]
+pub struct StreamMetrics(Arc<StreamCounters>);
+
+#[derive(Debug, Default)]
+struct StreamCounters {
+    received: AtomicUsize,
+    raids: AtomicUsize,
+    parse_failures: AtomicUsize,
+    invalid_dates: AtomicUsize,
+    duplicates: AtomicUsize,
+}
+
+impl StreamMetrics {
+    pub fn get(&self) -> StreamCounts {
+        StreamCounts {
+            received: self.0.received.load(Ordering::Relaxed),
+            raids: self.0.raids.load(Ordering::Relaxed),
+            parse_failures: self.0.parse_failures.load(Ordering::Relaxed),
+            invalid_dates: self.0.invalid_dates.load(Ordering::Relaxed),
+            duplicates: self.0.duplicates.load(Ordering::Relaxed),
+        }
+    }
+
+    // Sets every counter back to zero, and returns the counts from before
+    pub fn reset(&self) -> StreamCounts {
+        StreamCounts {
+            received: self.0.received.swap(0, Ordering::Relaxed),
+            raids: self.0.raids.swap(0, Ordering::Relaxed),
+            parse_failures: self.0.parse_failures.swap(0, Ordering::Relaxed),
+            invalid_dates: self.0.invalid_dates.swap(0, Ordering::Relaxed),
+            duplicates: self.0.duplicates.swap(0, Ordering::Relaxed),
+        }
+    }
+
+    fn inc(counter: &AtomicUsize) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+struct Reconnect {
+    // Called with the index of the token to connect with
+    connect: Box<Fn(usize) -> TwitterJsonStream>,
+    config: ReconnectConfig,
+    // Reset once a message is received from the new connection
+    failures: u32,
+    delay: Option<Timeout>,
+    tokens: usize,
+    token: usize,
+    // Tokens switched away from since the last message was received
+    rotations: usize,
+}
+
+const DEFAULT_TRACK_KEYWORDS: [&str; 4] = [
+    "参加者募集！",
+    ":参戦ID",
+    "I need backup!",
+    ":Battle ID",
+];
+
+// Phrases passed to Twitter's `track` parameter. Twitter only applies them
+// when connecting, so they can only be set when the stream is created.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackOptions {
+    pub keywords: Vec<String>,
+}
+
+impl Default for TrackOptions {
+    fn default() -> Self {
+        TrackOptions {
+            keywords: DEFAULT_TRACK_KEYWORDS.iter().map(|k| k.to_string()).collect(),
+        }
+    }
+}
+
+impl TrackOptions {
+    fn track(&self) -> String {
+        self.keywords.join(",")
+    }
+}
+
+impl Reconnect {
+    fn with_tokens<F>(config: ReconnectConfig, tokens: usize, connect: F) -> Self
+    where
+        F: Fn(usize) -> TwitterJsonStream + 'static,
+    {
+        Reconnect {
+            connect: Box::new(connect),
+            config,
+            failures: 0,
+            delay: None,
+            tokens,
+            token: 0,
+            rotations: 0,
+        }
+    }
+}
+
+const RECORD_FLUSH_INTERVAL_MILLIS: u64 = 1000;
+
+// Tweets containing these that can't be parsed are probably raid tweets in
+// a format the parser doesn't know about
+const NEAR_MISS_PHRASES: [&str; 2] = ["参加者募集", "I need backup"];
+
+// Writes each raw status as a line of JSON, flushing at most once per
+// interval. Anything written since the last flush is also flushed when the
+// stream is idle, and when it's dropped.
+struct Recorder {
+    writer: Box<Write>,
+    last_flush: Instant,
+    unflushed: bool,
+}
+
+impl Recorder {
+    // Returns false if the status couldn't be written
+    fn write(&mut self, json: &str) -> bool {
+        let mut line = String::with_capacity(json.len() + 1);
+        line.push_str(json);
+        line.push('\n');
+
+        let mut result = self.writer.write_all(line.as_bytes());
+        self.unflushed = true;
+
+        let interval = Duration::from_millis(RECORD_FLUSH_INTERVAL_MILLIS);
+        if result.is_ok() && self.last_flush.elapsed() >= interval {
+            result = self.flush();
+        }
+
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Failed to record status: {}", e);
+                false
+            }
+        }
+    }
+
+    fn flush(&mut self) -> ::std::io::Result<()> {
+        self.unflushed = false;
+        self.last_flush = Instant::now();
+        self.writer.flush()
+    }
+
+    fn flush_unflushed(&mut self) {
+        if self.unflushed {
+            if let Err(e) = self.flush() {
+                error!("Failed to flush recorded statuses: {}", e);
+            }
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.flush_unflushed();
+    }
+}
+
+const DEFAULT_RECENT_IDS_SIZE: usize = 10_000;
+
+// The IDs of the most recently seen tweets, for dropping tweets that are
+// received twice, e.g. after reconnecting. Once full, the oldest ID is
+// forgotten for each new one. This is first in, first out: receiving a
+// duplicate doesn't make its ID any more recent, since duplicates are only
+// expected shortly after the original.
+struct RecentIds {
+    ids: HashSet<TweetId>,
+    order: VecDeque<TweetId>,
+    capacity: usize,
+}
+
+impl RecentIds {
+    fn with_capacity(capacity: usize) -> Self {
+        RecentIds {
+            ids: HashSet::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    // Returns false if the ID was already seen
+    fn insert(&mut self, id: TweetId) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        if !self.ids.insert(id) {
+            return false;
+        }
+
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.order.push_back(id);
+        true
+    }
+}
+
+// Spaces out replayed raids by the time between their tweets
+struct Pacing {
+    last_created_at: Option<DateTime>,
+    delayed: Option<(RaidInfo, Timeout)>,
+}
+
+impl Pacing {
+    fn delay(&mut self, info: &RaidInfo, handle: &Handle) -> Option<Timeout> {
+        let created_at = info.tweet.created_at;
+        let gap = self.last_created_at
+            .and_then(|last| created_at.signed_duration_since(last).to_std().ok());
+        self.last_created_at = Some(created_at);
+
+        match gap {
+            Some(gap) if gap > Duration::from_secs(0) => Timeout::new(gap, handle).ok(),
+            _ => None,
+        }
+    }
+}
+
+#[must_use = "streams do nothing unless polled"]
+pub struct RaidInfoStream {
+    stream: TwitterJsonStream,
+    parser: Box<Parser>,
+    handle: Handle,
+    reconnect: Option<Reconnect>,
+    stall_timeout: Option<Duration>,
+    // Created on the first poll of each connection
+    stall_timer: Option<Timeout>,
+    stats: ConnectionStats,
+    pacing: Option<Pacing>,
+    recorder: Option<Recorder>,
+    raw_json: bool,
+    metrics: StreamMetrics,
+    on_parse_failure: Option<ParseFailureHook>,
+    // Statuses are v2 filtered stream payloads, rather than v1.1 tweets
+    api_v2: bool,
+    connection_watchers: Vec<mpsc::UnboundedSender<ConnectionEvent>>,
+    // Whether anything has been received from the current connection
+    connected: bool,
+    recent_ids: RecentIds,
+}
+
+type ParseFailureHook = Box<Fn(&str)>;
+
+// Collects the options for connecting to the Twitter stream. The defaults
+// connect the same way as `RaidInfoStream::with_handle`.
+#[derive(Clone, Debug)]
+pub struct RaidInfoStreamBuilder {
+    credentials: Credentials,
+    track: TrackOptions,
+    follow: Vec<UserId>,
+    stall_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    reconnect: Option<ReconnectConfig>,
+    raw_json: bool,
+    recent_ids_size: usize,
+}
+
+// A bearer token connects to the v2 filtered stream instead of v1.1. There's
+// always at least one token.
+#[derive(Clone, Debug)]
+enum Credentials {
+    Tokens(Vec<Token<'static>>),
+    Bearer(String),
+}
+
+impl Credentials {
+    fn count(&self) -> usize {
+        match *self {
+            Credentials::Tokens(ref tokens) => tokens.len(),
+            Credentials::Bearer(_) => 1,
+        }
+    }
+}
+
+// Passed through to `TwitterStreamBuilder` on each connection
+#[derive(Clone, Debug)]
+struct ConnectOptions {
+    track: String,
+    follow: Vec<UserId>,
+    timeout: Option<Duration>,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        ConnectOptions {
+            track: TrackOptions::default().track(),
+            follow: Vec::new(),
+            timeout: None,
+        }
+    }
+}
+
+impl ConnectOptions {
+    fn follow(&self) -> Option<&[UserId]> {
+        if self.follow.is_empty() {
+            None
+        } else {
+            Some(&self.follow)
+        }
+    }
+}
+
+impl RaidInfoStreamBuilder {
+    pub fn new(token: &Token) -> Self {
+        Self::with_credentials(Credentials::Tokens(vec![owned_token(token)]))
+    }
+
+    // Like `new`, but when reconnecting after Twitter rejects or rate limits
+    // the current token, the next token is used instead. After every token
+    // has been rate limited, the usual backoff applies, and the tokens are
+    // cycled through again. If every token is rejected for authentication
+    // instead, the stream ends with `ErrorKind::AuthenticationFailed`. The
+    // old connection is always closed before the next one is opened. Panics
+    // if there are no tokens.
+    pub fn with_tokens(tokens: &[Token]) -> Self {
+        assert!(!tokens.is_empty(), "at least one token is required");
+        Self::with_credentials(Credentials::Tokens(
+            tokens.iter().map(owned_token).collect(),
+        ))
+    }
+
+    // Connects to the v2 filtered stream, using an app's bearer token. Tweets
+    // are matched by the app's rules (see `twitter_v2::add_rules`) instead of
+    // the track options, and the timeout isn't used. Only
+    // `connect_with_client` can connect to it, since `connect` has no TLS
+    // connector of its own for it, and returns an error instead.
+    pub fn with_bearer_token(bearer_token: &str) -> Self {
+        Self::with_credentials(Credentials::Bearer(bearer_token.to_string()))
+    }
+
+    fn with_credentials(credentials: Credentials) -> Self {
+        RaidInfoStreamBuilder {
+            credentials,
+            track: TrackOptions::default(),
+            follow: Vec::new(),
+            stall_timeout: None,
+            timeout: None,
+            reconnect: None,
+            raw_json: false,
+            recent_ids_size: DEFAULT_RECENT_IDS_SIZE,
+        }
+    }
+
+    // See `RaidInfoStream::with_raw_json`
+    pub fn with_raw_json(mut self) -> Self {
+        self.raw_json = true;
+        self
+    }
+
+    // See `RaidInfoStream::with_recent_ids_size`
+    pub fn with_recent_ids_size(mut self, size: usize) -> Self {
+        self.recent_ids_size = size;
+        self
+    }
+
+    // Fails the connection if Twitter doesn't respond within the timeout,
+    // or if nothing is received for that long after connecting. Unlike the
+    // stall timeout, this is handled by `twitter_stream`, and only applies
+    // when connecting with this builder. There's no timeout by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_track_options(mut self, options: TrackOptions) -> Self {
+        self.track = options;
+        self
+    }
+
+    // Also receives every tweet by these users, e.g. accounts that repost
+    // raid tweets in a format the track keywords miss. Their tweets still go
+    // through the parser, so ones that aren't raid tweets are dropped.
+    // Twitter allows up to 5,000 IDs per connection, and rejects the
+    // connection if there are more. Not used by the v2 filtered stream,
+    // where users are followed by adding `from:` rules instead.
+    pub fn with_follow<I>(mut self, user_ids: I) -> Self
+    where
+        I: IntoIterator<Item = UserId>,
+    {
+        self.follow = user_ids.into_iter().collect();
+        self
+    }
+
+    // See `RaidInfoStream::with_stall_timeout`
+    pub fn with_stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall_timeout = Some(timeout);
+        self
+    }
+
+    // When the connection drops or fails, it's re-established after a
+    // backoff instead of ending the stream. Replaces any earlier
+    // `with_error_policy`, since the retry limit is `config.max_retries`.
+    pub fn with_reconnect(mut self, config: ReconnectConfig) -> Self {
+        self.reconnect = Some(config);
+        self
+    }
+
+    // Sets the `max_retries` of the current `ReconnectConfig`, so whichever
+    // of this and `with_reconnect` is called last wins. Backoffs are kept
+    // from an earlier `with_reconnect`, or are the defaults if there wasn't
+    // one (or if it was removed by `Abort`).
+    pub fn with_error_policy(mut self, policy: StreamErrorPolicy) -> Self {
+        let max_retries = match policy {
+            StreamErrorPolicy::Abort => {
+                self.reconnect = None;
+                return self;
+            }
+            StreamErrorPolicy::Retry(max) => Some(max),
+            StreamErrorPolicy::RetryForever => None,
+        };
+
+        self.reconnect = Some(ReconnectConfig {
+            max_retries,
+            ..self.reconnect.unwrap_or_default()
+        });
+        self
+    }
+
+    fn connect_options(&self) -> ConnectOptions {
+        ConnectOptions {
+            track: self.track.track(),
+            follow: self.follow.clone(),
+            timeout: self.timeout,
+        }
+    }
+
+    // Fails with `ErrorKind::BearerTokenNeedsClient` if the builder was
+    // created by `with_bearer_token`
+    pub fn connect(&self, handle: &Handle) -> Result<RaidInfoStream> {
+        match self.credentials {
+            Credentials::Tokens(ref tokens) => Ok(self.connect_v1(handle, tokens.clone())),
+            Credentials::Bearer(_) => bail!(ErrorKind::BearerTokenNeedsClient),
+        }
+    }
+
+    fn connect_v1(&self, handle: &Handle, tokens: Vec<Token<'static>>) -> RaidInfoStream {
+        let options = self.connect_options();
+        let stream = RaidInfoStream::connect_with_handle(handle, &tokens[0], &options);
+
+        let reconnect = self.reconnect.clone().map(|config| {
+            let handle = handle.clone();
+            Reconnect::with_tokens(config, tokens.len(), move |i| {
+                RaidInfoStream::connect_with_handle(&handle, &tokens[i], &options)
+            })
+        });
+
+        self.finish(stream, handle, reconnect)
+    }
+
+    // Like `connect`, but uses the connection pool of an existing client.
+    // To connect through a proxy, use a client with a connector that tunnels
+    // through it.
+    pub fn connect_with_client<C, B>(&self, hyper_client: &hyper::Client<C, B>) -> RaidInfoStream
+    where
+        C: hyper::client::Connect + Clone,
+        B: From<Vec<u8>> + Stream<Error = hyper::Error> + 'static,
+        B::Item: AsRef<[u8]>,
+    {
+        let options = self.connect_options();
+        let credentials = self.credentials.clone();
+        let connect = move |client: &hyper::Client<C, B>, i: usize| match credentials {
+            Credentials::Tokens(ref tokens) => {
+                RaidInfoStream::connect_with_client(client, &tokens[i], &options)
+            }
+            Credentials::Bearer(ref bearer_token) => twitter_v2::connect(client, bearer_token),
+        };
+        let stream = connect(hyper_client, 0);
+
+        let tokens = self.credentials.count();
+        let reconnect = self.reconnect.clone().map(|config| {
+            let client = hyper_client.clone();
+            Reconnect::with_tokens(config, tokens, move |i| connect(&client, i))
+        });
+
+        self.finish(stream, hyper_client.handle(), reconnect)
+    }
+
+    fn finish(
+        &self,
+        stream: TwitterJsonStream,
+        handle: &Handle,
+        reconnect: Option<Reconnect>,
+    ) -> RaidInfoStream {
+        let mut stream = RaidInfoStream::new(stream, handle, reconnect);
+        stream.stall_timeout = self.stall_timeout;
+        stream.raw_json = self.raw_json;
+        stream.recent_ids = RecentIds::with_capacity(self.recent_ids_size);
+        if let Credentials::Bearer(_) = self.credentials {
+            stream.api_v2 = true;
+        }
+        stream
+    }
+}
+
+impl RaidInfoStream {
+    fn new(stream: TwitterJsonStream, handle: &Handle, reconnect: Option<Reconnect>) -> Self {
+        let stats = ConnectionStats::default();
+        stats.update(|c| c.connection_attempts += 1);
+
+        RaidInfoStream {
+            stream,
+            parser: Box::new(ParserConfig::default()),
+            handle: handle.clone(),
+            reconnect,
+            stall_timeout: None,
+            stall_timer: None,
+            stats,
+            pacing: None,
+            recorder: None,
+            raw_json: false,
+            metrics: StreamMetrics::default(),
+            on_parse_failure: None,
+            api_v2: false,
+            connection_watchers: Vec::new(),
+            connected: false,
+            recent_ids: RecentIds::with_capacity(DEFAULT_RECENT_IDS_SIZE),
+        }
+    }
+
+    // Writes every status received to `writer` before it's parsed, one per
+    // line, whether or not it's a raid tweet. The output can be replayed with
+    // `from_reader`. Failed writes are logged and counted in the connection
+    // stats, and don't end the stream. Writes aren't buffered, so files
+    // should be wrapped in a `BufWriter`. It's flushed at most once a second
+    // while statuses are arriving, and whenever the stream is waiting for
+    // more, so a crash only loses what was received in the last second.
+    pub fn record_to<W>(mut self, writer: W) -> Self
+    where
+        W: Write + 'static,
+    {
+        self.recorder = Some(Recorder {
+            writer: Box::new(writer),
+            last_flush: Instant::now(),
+            unflushed: false,
+        });
+        self
+    }
+
+    // Replays statuses from newline-delimited JSON, such as a recording of
+    // the raw stream. Statuses that aren't raid tweets are skipped, and the
+    // stream ends at the end of the input. With `respect_timestamps`, raids
+    // are delayed by the time between their tweets, rather than yielded as
+    // fast as they can be read.
+    pub fn from_reader<R>(reader: R, handle: &Handle, respect_timestamps: bool) -> Self
+    where
+        R: BufRead + 'static,
+    {
+        let lines = reader
+            .lines()
+            .filter(|line| line.as_ref().map(|l| !l.trim().is_empty()).unwrap_or(true))
+            .map(|line| {
+                line.map(JsonStr::from)
+                    .map_err(|e| twitter_stream::Error::Custom(Box::new(e)))
+            });
+
+        let lines = stream::iter_ok(lines).and_then(|line| line);
+        let mut stream = Self::new(Box::new(lines), handle, None);
+        if respect_timestamps {
+            stream.pacing = Some(Pacing {
+                last_created_at: None,
+                delayed: None,
+            });
+        }
+        stream
+    }
+
+    fn connect_with_client<C, B>(
+        hyper_client: &hyper::Client<C, B>,
+        token: &Token,
+        options: &ConnectOptions,
+    ) -> TwitterJsonStream
+    where
+        C: hyper::client::Connect,
+        B: From<Vec<u8>> + Stream<Error = hyper::Error> + 'static,
+        B::Item: AsRef<[u8]>,
+    {
+        let stream = TwitterStreamBuilder::filter(token)
+            .client(&hyper_client)
+            .user_agent(Some("petronel")) // TODO: Make this configurable?
+            .timeout(options.timeout)
+            .track(Some(&options.track))
+            .follow(options.follow())
+            .listen()
+            .flatten_stream();
+
+        Box::new(stream)
+    }
+
+    fn connect_with_handle(
+        handle: &Handle,
+        token: &Token,
+        options: &ConnectOptions,
+    ) -> TwitterJsonStream {
+        let stream = TwitterStreamBuilder::filter(token)
+            .handle(handle)
+            .user_agent(Some("petronel")) // TODO: Make this configurable?
+            .timeout(options.timeout)
+            .track(Some(&options.track))
+            .follow(options.follow())
+            .listen()
+            .flatten_stream();
+
+        Box::new(stream)
+    }
+
+    // Unlike `RaidInfoStreamBuilder::connect_with_client`, this doesn't need
+    // the connector to be `Clone`, since it never reconnects
+    pub fn with_client<C, B>(hyper_client: &hyper::Client<C, B>, token: &Token) -> Self
+    where
+        C: hyper::client::Connect,
+        B: From<Vec<u8>> + Stream<Error = hyper::Error> + 'static,
+        B::Item: AsRef<[u8]>,
+    {
+        let options = ConnectOptions::default();
+        let stream = Self::connect_with_client(hyper_client, token, &options);
+        Self::new(stream, hyper_client.handle(), None)
+    }
+
+    pub fn with_handle(handle: &Handle, token: &Token) -> Self {
+        RaidInfoStreamBuilder::new(token).connect_v1(handle, vec![owned_token(token)])
+    }
+
+    // Like `with_handle`, but tracks the given keywords instead. Tweets for
+    // custom keywords are still only yielded if the parser recognizes them.
+    pub fn with_options(handle: &Handle, token: &Token, options: TrackOptions) -> Self {
+        RaidInfoStreamBuilder::new(token)
+            .with_track_options(options)
+            .connect_v1(handle, vec![owned_token(token)])
+    }
+
+    // Like `with_client`, but reconnects after a backoff instead of ending
+    pub fn with_client_reconnecting<C, B>(
+        hyper_client: &hyper::Client<C, B>,
+        token: &Token,
+        config: ReconnectConfig,
+    ) -> Self
+    where
+        C: hyper::client::Connect + Clone,
+        B: From<Vec<u8>> + Stream<Error = hyper::Error> + 'static,
+        B::Item: AsRef<[u8]>,
+    {
+        RaidInfoStreamBuilder::new(token)
+            .with_reconnect(config)
+            .connect_with_client(hyper_client)
+    }
+
+    // Like `with_handle`, but reconnects like `with_client_reconnecting`
+    pub fn with_handle_reconnecting(
+        handle: &Handle,
+        token: &Token,
+        config: ReconnectConfig,
+    ) -> Self {
+        RaidInfoStreamBuilder::new(token)
+            .with_reconnect(config)
+            .connect_v1(handle, vec![owned_token(token)])
+    }
+
+    // If nothing is received for this long, the connection is treated as
+    // dead, and is re-established if the stream reconnects. Otherwise, the
+    // stream returns an error. Keep-alive newlines sent by Twitter aren't
+    // seen here, so this should be longer than the usual gap between tweets.
+    pub fn with_stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall_timeout = Some(timeout);
+        self
+    }
+
+    // Keeps the JSON of each raid tweet in `RaidInfo::raw`, for fields that
+    // aren't parsed. This roughly doubles the size of each item.
+    pub fn with_raw_json(mut self) -> Self {
+        self.raw_json = true;
+        self
+    }
+
+    // Tweets with the same ID as one of the last `size` tweets are dropped
+    // before they're parsed, and counted in `StreamCounts::duplicates`. This
+    // catches tweets that Twitter sends again after a reconnect, or that
+    // match both the track keywords and followed users. The default is
+    // 10,000, and 0 keeps every tweet.
+    pub fn with_recent_ids_size(mut self, size: usize) -> Self {
+        self.recent_ids = RecentIds::with_capacity(size);
+        self
+    }
+
+    // Called with the text of tweets that look like raid tweets, but
+    // couldn't be parsed, e.g. because the game changed its tweet format.
+    // Unrelated tweets from the tracked keywords are ignored. Messages that
+    // aren't valid JSON are passed in whole, and skipped.
+    pub fn on_parse_failure<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&str) + 'static,
+    {
+        self.on_parse_failure = Some(Box::new(f));
+        self
+    }
+
+    pub fn connection_stats(&self) -> ConnectionStats {
+        self.stats.clone()
+    }
+
+    pub fn metrics(&self) -> StreamMetrics {
+        self.metrics.clone()
+    }
+
+    // Stream of changes to the connection, e.g. to show whether the stream
+    // is live. Events are sent as they happen, without waiting for the
+    // receiver. Ends when this stream is dropped.
+    pub fn connection_events(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.connection_watchers.push(tx);
+        rx
+    }
+
+    fn send_connection_event(&mut self, event: ConnectionEvent) {
+        self.connection_watchers
+            .retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+    }
+
+    // Index of the token used by the current connection
+    fn active_token(&self) -> usize {
+        self.reconnect.as_ref().map(|r| r.token).unwrap_or(0)
+    }
+
+    fn disconnected(&mut self, reason: String) {
+        self.stats.update(|c| c.disconnects += 1);
+        self.connected = false;
+        // Closes a stalled connection now, rather than when reconnecting
+        self.stream = Box::new(stream::empty());
+        self.send_connection_event(ConnectionEvent::Disconnected {
+            at: Utc::now(),
+            reason,
+        });
+    }
+
+    // Malformed messages are skipped rather than ending the stream, since
+    // the next message is usually fine
+    fn parse_failed<E: ::std::fmt::Display>(&self, json: &str, error: E) {
+        StreamMetrics::inc(&self.metrics.0.parse_failures);
+        error!("Failed to parse stream message ({}): {}", error, json);
+        if let Some(ref f) = self.on_parse_failure {
+            f(json);
+        }
+    }
+
+    // Only copies the text if there's a hook for it. Retweets and quoted
+    // tweets contain the text of a raid tweet, but aren't failures.
+    fn near_miss(&self, text: &str, is_retweet: bool) -> Option<String> {
+        match self.on_parse_failure {
+            Some(_) if !is_retweet && NEAR_MISS_PHRASES.iter().any(|p| text.contains(p)) => {
+                Some(text.to_string())
+            }
+            _ => None,
+        }
+    }
+
+    fn report_near_miss(&self, near_miss: Option<String>, parsed: &Option<RaidInfo>) {
+        if parsed.is_none() {
+            if let (Some(text), Some(f)) = (near_miss, self.on_parse_failure.as_ref()) {
+                f(&text);
+            }
+        }
+    }
+
+    // Returns true (and counts it) if the tweet was already received
+    fn check_duplicate(&mut self, id: TweetId) -> bool {
+        if self.recent_ids.insert(id) {
+            return false;
+        }
+
+        StreamMetrics::inc(&self.metrics.0.duplicates);
+        debug!("Dropped duplicate tweet {}", id);
+        true
+    }
+
+    // Returns `None` for statuses that aren't raid tweets
+    fn parse_v1(&mut self, json: &str) -> Option<RaidInfo> {
+        // Tweets with a missing or unparseable `created_at` are parsed
+        // again with a placeholder date, rather than being dropped
+        let patched;
+        let (msg, source, placeholder_date) = match StreamMessage::from_str(json) {
+            Ok(msg) => (msg, json, false),
+            Err(e) => match with_placeholder_created_at(json) {
+                Some(patched_json) => {
+                    patched = patched_json;
+                    match StreamMessage::from_str(&patched) {
+                        Ok(msg) => (msg, patched.as_str(), true),
+                        Err(e) => {
+                            self.parse_failed(json, e);
+                            return None;
+                        }
+                    }
+                }
+                None => {
+                    self.parse_failed(json, e);
+                    return None;
+                }
+            },
+        };
+
+        let mut tweet = match msg {
+            StreamMessage::Tweet(tweet) => tweet,
+            _ => return None,
+        };
+        if self.check_duplicate(tweet.id) {
+            return None;
+        }
+
+        // Fields that `Tweet` doesn't have are read in a single extra pass
+        let (timestamp_ms, extended_tweet) = match serde_json::from_str::<StatusExtras>(source) {
+            Ok(extras) => (extras.timestamp_ms, extras.extended_tweet),
+            Err(_) => (None, None),
+        };
+        untruncate(&mut tweet, extended_tweet);
+
+        let near_miss = self.near_miss(&tweet.text, is_retweet(&tweet));
+        let mut parsed = self.parser.parse(*tweet);
+        self.report_near_miss(near_miss, &parsed);
+
+        if let Some(ref mut raid_info) = parsed {
+            match timestamp_ms.and_then(|ms| parse_timestamp_ms(&ms)) {
+                Some(created_at) => raid_info.tweet.created_at = created_at,
+                None if placeholder_date => {
+                    StreamMetrics::inc(&self.metrics.0.invalid_dates);
+                    warn!("Invalid date for raid {}", raid_info.tweet.raid_id);
+                    raid_info.tweet.created_at = Utc::now();
+                }
+                None => {}
+            }
+        }
+        parsed
+    }
+
+    fn parse_v2(&mut self, json: &str) -> Option<RaidInfo> {
+        let payload = match serde_json::from_str::<twitter_v2::Payload>(json) {
+            Ok(payload) => payload,
+            Err(e) => {
+                self.parse_failed(json, e);
+                return None;
+            }
+        };
+
+        let id = payload.data.as_ref().and_then(|t| t.id.parse().ok());
+        if let Some(id) = id {
+            if self.check_duplicate(id) {
+                return None;
+            }
+        }
+
+        let (near_miss, has_date) = match payload.data {
+            Some(ref tweet) => (
+                self.near_miss(&tweet.text, tweet.is_retweet()),
+                tweet.created_at.is_some(),
+            ),
+            None => {
+                warn!("Received a message that isn't a tweet: {}", json);
+                return None;
+            }
+        };
+
+        let parsed = self.parser.parse_v2(&payload);
+        self.report_near_miss(near_miss, &parsed);
+
+        // Parsers use the current time for tweets without a date
+        if let Some(ref raid_info) = parsed {
+            if !has_date {
+                StreamMetrics::inc(&self.metrics.0.invalid_dates);
+                warn!("Invalid date for raid {}", raid_info.tweet.raid_id);
+            }
+        }
+        parsed
+    }
+
+    fn is_stalled(&mut self) -> bool {
+        let timeout = match self.stall_timeout {
+            Some(timeout) => timeout,
+            None => return false,
+        };
+
+        if self.stall_timer.is_none() {
+            self.stall_timer = Timeout::new(timeout, &self.handle).ok();
+        }
+
+        match self.stall_timer {
+            Some(ref mut timer) => timer.poll().map(|a| a.is_ready()).unwrap_or(false),
+            None => false,
+        }
+    }
+
+    // How long to wait before connecting again after being rate limited
+    fn rate_limit_wait(&self) -> Duration {
+        match self.reconnect {
+            Some(ref reconnect) => reconnect.config.rate_limit_backoff(reconnect.failures),
+            None => ReconnectConfig::default().rate_limit_backoff(0),
+        }
+    }
+
+    // Switches to the next token, if the error was caused by the current
+    // one. Returns true if the next token hasn't failed since the last
+    // message was received.
+    fn rotate_token(&mut self, kind: &ErrorKind) -> bool {
+        let reconnect = match self.reconnect {
+            Some(ref mut reconnect) if reconnect.tokens > 1 => reconnect,
+            _ => return false,
+        };
+
+        match *kind {
+            ErrorKind::AuthenticationFailed | ErrorKind::RateLimited(_) => {}
+            _ => return false,
+        }
+
+        reconnect.token = (reconnect.token + 1) % reconnect.tokens;
+        reconnect.rotations += 1;
+        warn!("Switching to Twitter token {}", reconnect.token);
+        reconnect.rotations < reconnect.tokens
+    }
+
+    // Returns false if the stream shouldn't reconnect. Switching to an
+    // untried token only waits for the initial backoff.
+    fn schedule_reconnect(&mut self, rate_limited: bool, untried_token: bool) -> bool {
+        let reconnect = match self.reconnect {
+            Some(ref mut reconnect) => reconnect,
+            None => return false,
+        };
+
+        let max_retries = reconnect.config.max_retries;
+        if max_retries.map(|max| reconnect.failures >= max).unwrap_or(false) {
+            error!("Giving up on Twitter stream after {} attempts", reconnect.failures);
+            return false;
+        }
+
+        let backoff = if untried_token {
+            reconnect.config.backoff(0)
+        } else if rate_limited {
+            reconnect.config.rate_limit_backoff(reconnect.failures)
+        } else {
+            reconnect.config.backoff(reconnect.failures)
+        };
+        reconnect.failures += 1;
+        let attempt = reconnect.failures;
+        let token = reconnect.token;
+
+        let scheduled = match Timeout::new(backoff, &self.handle) {
+            Ok(delay) => {
+                match max_retries {
+                    Some(max) => warn!(
+                        "Reconnecting to Twitter stream in {:?} (attempt {} of {})",
+                        backoff, attempt, max
+                    ),
+                    None => warn!(
+                        "Reconnecting to Twitter stream in {:?} (attempt {})",
+                        backoff, attempt
+                    ),
+                }
+                reconnect.delay = Some(delay);
+                true
+            }
+            Err(e) => {
+                error!("Failed to schedule Twitter stream reconnect: {}", e);
+                false
+            }
+        };
+
+        if scheduled {
+            self.send_connection_event(ConnectionEvent::Reconnecting {
+                at: Utc::now(),
+                attempt,
+                delay: backoff,
+                token,
+            });
+        }
+        scheduled
+    }
+
+    // Replaces the default `ParserConfig`, e.g. to match a custom boss level
+    pub fn with_parser_config(self, parser: ParserConfig) -> Self {
+        self.with_parser(parser)
+    }
+
+    // Replaces the parser entirely, for tweets that can't be described by
+    // `ParserConfig` patterns (e.g. a different game, or extra filtering)
+    pub fn with_parser<P>(mut self, parser: P) -> Self
+    where
+        P: Parser + 'static,
+    {
+        self.parser = Box::new(parser);
+        self
+    }
+}
+
+fn owned_token(token: &Token) -> Token<'static> {
+    Token::new(
+        token.consumer_key.to_string(),
+        token.consumer_secret.to_string(),
+        token.access_key.to_string(),
+        token.access_secret.to_string(),
+    )
+}
+
+#[derive(Deserialize)]
+struct StatusExtras<'a> {
+    timestamp_ms: Option<String>,
+    #[serde(borrow)]
+    extended_tweet: Option<ExtendedTweet<'a>>,
+}
+
+#[derive(Deserialize)]
+struct ExtendedTweet<'a> {
+    #[serde(borrow)]
+    full_text: Cow<'a, str>,
+    #[serde(borrow)]
+    entities: Option<Entities<'a>>,
+    #[serde(borrow)]
+    extended_entities: Option<ExtendedEntities<'a>>,
+}
+
+#[derive(Deserialize)]
+struct ExtendedEntities<'a> {
+    #[serde(borrow)]
+    media: Option<Vec<Media<'a>>>,
+}
+
+// Tweets over 140 characters have a truncated `text`, which can cut off the
+// raid ID, so the full text and entities are taken from `extended_tweet`
+fn untruncate<'a>(tweet: &mut Tweet<'a>, extended: Option<ExtendedTweet<'a>>) {
+    if !tweet.truncated {
+        return;
+    }
+
+    let extended = match extended {
+        Some(extended) => extended,
+        None => return,
+    };
+
+    tweet.text = extended.full_text;
+    if let Some(entities) = extended.entities {
+        tweet.entities = entities;
+    }
+    if let Some(media) = extended.extended_entities.and_then(|e| e.media) {
+        tweet.entities.media = Some(media);
+    }
+}
+
+// `timestamp_ms` is more precise than `created_at`
+fn parse_timestamp_ms(ms: &str) -> Option<DateTime> {
+    let ms = ms.parse::<i64>().ok()?;
+    Utc.timestamp_opt(ms / 1000, (ms % 1000) as u32 * 1_000_000)
+        .single()
+}
+
+// Returns `None` if the status isn't a tweet
+fn with_placeholder_created_at(json: &str) -> Option<String> {
+    const PLACEHOLDER_DATE: &str = "Thu Jan 01 00:00:00 +0000 1970";
+
+    let mut status = serde_json::from_str::<serde_json::Value>(json).ok()?;
+    {
+        let fields = status.as_object_mut()?;
+        if !fields.contains_key("text") {
+            return None;
+        }
+        fields.insert("created_at".into(), PLACEHOLDER_DATE.into());
+    }
+    serde_json::to_string(&status).ok()
+}
+
+impl Stream for RaidInfoStream {
+    type Item = RaidInfo;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(ref mut pacing) = self.pacing {
+                if let Some((info, mut delay)) = pacing.delayed.take() {
+                    if let Ok(Async::NotReady) = delay.poll() {
+                        pacing.delayed = Some((info, delay));
+                        return Ok(Async::NotReady);
+                    }
+                    return Ok(Async::Ready(Some(info)));
+                }
+            }
+
+            if let Some(ref mut reconnect) = self.reconnect {
+                if let Some(mut delay) = reconnect.delay.take() {
+                    // If the timer fails, reconnect right away
+                    if let Ok(Async::NotReady) = delay.poll() {
+                        reconnect.delay = Some(delay);
+                        return Ok(Async::NotReady);
+                    }
+
+                    info!("Reconnecting to Twitter stream");
+                    self.stream = (reconnect.connect)(reconnect.token);
+                    self.stall_timer = None;
+                    self.stats.update(|c| {
+                        c.connection_attempts += 1;
+                        c.reconnects += 1;
+                        c.last_reconnect = Some(Utc::now());
+                    });
+                }
+            }
+
+            let json = match self.stream.poll() {
+                Ok(Async::Ready(Some(json))) => json,
+                Ok(Async::NotReady) => {
+                    if let Some(ref mut recorder) = self.recorder {
+                        recorder.flush_unflushed();
+                    }
+                    if !self.is_stalled() {
+                        return Ok(Async::NotReady);
+                    }
+
+                    warn!("Twitter stream stalled");
+                    self.disconnected("stalled".to_string());
+
+                    if self.schedule_reconnect(false, false) {
+                        continue;
+                    } else {
+                        bail!(ErrorKind::Stalled);
+                    }
+                }
+                Ok(Async::Ready(None)) => {
+                    self.disconnected("stream ended".to_string());
+
+                    if self.reconnect.is_none() {
+                        return Ok(Async::Ready(None));
+                    } else if self.schedule_reconnect(false, false) {
+                        continue;
+                    } else {
+                        bail!(ErrorKind::ConnectionReset);
+                    }
+                }
+                Err(e) => {
+                    let rate_limited = is_rate_limited(&e);
+                    let kind = stream_error_kind(&e, self.rate_limit_wait());
+                    let e = Error::with_chain(e, kind);
+                    let reason = e.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+                    self.disconnected(reason.join(": "));
+
+                    if rate_limited {
+                        warn!("Rate limited by Twitter: {}", e);
+                    } else {
+                        error!("Twitter stream error: {}", e);
+                    }
+
+                    let untried_token = self.rotate_token(e.kind());
+                    if !is_transient(e.kind()) && !untried_token {
+                        error!("Not reconnecting to Twitter stream after: {}", e);
+                        return Err(e);
+                    } else if self.schedule_reconnect(rate_limited, untried_token) {
+                        continue;
+                    } else {
+                        return Err(e);
+                    }
+                }
+            };
+
+            if let Some(ref mut reconnect) = self.reconnect {
+                reconnect.failures = 0;
+                reconnect.rotations = 0;
+            }
+            if !self.connected {
+                self.connected = true;
+                let token = self.active_token();
+                self.send_connection_event(ConnectionEvent::Connected {
+                    at: Utc::now(),
+                    token,
+                });
+            }
+            if let (Some(timeout), Some(timer)) = (self.stall_timeout, self.stall_timer.as_mut()) {
+                timer.reset(Instant::now() + timeout);
+            }
+
+            StreamMetrics::inc(&self.metrics.0.received);
+
+            if let Some(ref mut recorder) = self.recorder {
+                if !recorder.write(json.as_ref()) {
+                    self.stats.update(|c| c.record_errors += 1);
+                }
+            }
+
+            let parsed = if self.api_v2 {
+                self.parse_v2(json.as_ref())
+            } else {
+                self.parse_v1(json.as_ref())
+            };
+
+            if let Some(mut raid_info) = parsed {
+                StreamMetrics::inc(&self.metrics.0.raids);
+                if self.raw_json {
+                    raid_info.raw = Some(json.to_string());
+                }
+
+                debug!(
+                    "Parsed raid {} for boss {}",
+                    raid_info.tweet.raid_id,
+                    raid_info.tweet.boss_name
+                );
+
+                if let Some(ref mut pacing) = self.pacing {
+                    if let Some(delay) = pacing.delay(&raid_info, &self.handle) {
+                        pacing.delayed = Some((raid_info, delay));
+                        continue;
+                    }
+                }
+                return Ok(Async::Ready(Some(raid_info)));
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct TweetParts<'a> {
+    language: Language,
+    text: Option<&'a str>,
+    raid_id: &'a str,
+    boss_name: &'a str,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RaidInfo {
+    pub tweet: RaidTweet,
+    pub image: Option<BossImageUrl>,
+    // All images attached to the tweet, in order. `image` is the last one.
+    pub images: Vec<BossImageUrl>,
+    pub level: Option<BossLevel>,
+    // The status as received, if the stream was created with `with_raw_json`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+impl RaidInfo {
+    pub fn from_tweet(tweet: Tweet) -> Option<RaidInfo> {
+        DEFAULT_PARSER_CONFIG.parse(tweet)
+    }
+}
+
+pub trait Parser {
+    fn parse(&self, tweet: Tweet) -> Option<RaidInfo>;
+
+    // Statuses from the v2 filtered stream have a different shape. Parsers
+    // that don't handle them skip every tweet from that stream.
+    fn parse_v2(&self, _payload: &twitter_v2::Payload) -> Option<RaidInfo> {
+        None
+    }
+}
+
+impl<P> Parser for Box<P>
+where
+    P: Parser + ?Sized,
+{
+    fn parse(&self, tweet: Tweet) -> Option<RaidInfo> {
+        (**self).parse(tweet)
+    }
+
+    fn parse_v2(&self, payload: &twitter_v2::Payload) -> Option<RaidInfo> {
+        (**self).parse_v2(payload)
+    }
+}
+
+// Patterns used to parse raid tweets. The Japanese and English patterns
+// must have `id` and `boss` capture groups, and may optionally capture
+// `text` (extra user-provided text) and `url` (the trailing image URL).
+// The boss level pattern must have a `level` capture group, and is
+// matched against the boss name. Retweets and quoted tweets are skipped by
+// default, since their raids are usually long over.
+#[derive(Clone, Debug)]
+pub struct ParserConfig {
+    japanese: Regex,
+    english: Regex,
+    boss_level: Option<Regex>,
+    include_retweets: bool,
+    full_size_user_images: bool,
+}
+
+impl Default for ParserConfig {
+    fn default() -> Self {
+        ParserConfig {
+            japanese: REGEX_JAPANESE.clone(),
+            english: REGEX_ENGLISH.clone(),
+            boss_level: None,
+            include_retweets: false,
+            full_size_user_images: false,
+        }
+    }
+}
+
+impl ParserConfig {
+    pub fn japanese(mut self, pattern: &str) -> Result<Self> {
+        self.japanese = compile_pattern(pattern, &["id", "boss"])?;
+        Ok(self)
+    }
+
+    pub fn english(mut self, pattern: &str) -> Result<Self> {
+        self.english = compile_pattern(pattern, &["id", "boss"])?;
+        Ok(self)
+    }
+
+    pub fn boss_level(mut self, pattern: &str) -> Result<Self> {
+        self.boss_level = Some(compile_pattern(pattern, &["level"])?);
+        Ok(self)
+    }
+
+    pub fn include_retweets(mut self, include: bool) -> Self {
+        self.include_retweets = include;
+        self
+    }
+
+    // Twitter sends 48x48 profile images. With this, the `_normal` suffix is
+    // removed from `user_image`, to get the original size instead.
+    pub fn full_size_user_images(mut self, full_size: bool) -> Self {
+        self.full_size_user_images = full_size;
+        self
+    }
+
+    fn parse_level(&self, boss_name: &BossName) -> Option<BossLevel> {
+        match self.boss_level {
+            Some(ref regex) => boss_name.parse_level_with(regex),
+            None => boss_name.parse_level(),
+        }
+    }
+
+    fn parse_text<'a>(&self, tweet_text: &'a str) -> Option<TweetParts<'a>> {
+        self.japanese
+            .captures(tweet_text)
+            .map(|c| (Language::Japanese, c))
+            .or_else(|| {
+                self.english
+                    .captures(tweet_text)
+                    .map(|c| (Language::English, c))
+            })
+            .and_then(|(lang, c)| {
+                if let (Some(id), Some(boss)) = (c.name("id"), c.name("boss")) {
+                    let boss_name = boss.as_str().trim();
+                    let url_str = c.name("url").map_or("", |url| url.as_str());
+
+                    if boss_name.contains("http")
+                        || !url_str.is_empty() && !REGEX_IMAGE_URL.is_match(url_str)
+                    {
+                        return None;
+                    }
+
+                    let t = c.name("text").map_or("", |text| text.as_str().trim());
+
+                    Some(TweetParts {
+                        language: lang,
+                        text: if t.is_empty() { None } else { Some(t) },
+                        raid_id: id.as_str().trim(),
+                        boss_name,
+                    })
+                } else {
+                    None
+                }
+            })
+    }
+}
+
+// The parts of a status that raids are parsed from, from either API version
+struct Status<'a> {
+    id: TweetId,
+    text: Cow<'a, str>,
+    is_granblue: bool,
+    is_retweet: bool,
+    created_at: DateTime,
+    user_id: UserId,
+    user_screen_name: Cow<'a, str>,
+    user_display_name: Cow<'a, str>,
+    // `None` if the user still has the default image
+    user_image: Option<Cow<'a, str>>,
+    images: Vec<BossImageUrl>,
+}
+
+impl<'a> Status<'a> {
+    fn from_v1(tweet: Tweet<'a>, images: Vec<BossImageUrl>) -> Self {
+        let is_retweet = is_retweet(&tweet);
+
+        let user = tweet.user;
+        let user_image = if user.default_profile_image
+            || user.profile_image_url_https.contains("default_profile")
+        {
+            None
+        } else {
+            Some(user.profile_image_url_https)
+        };
+
+        Status {
+            id: tweet.id,
+            text: tweet.text,
+            is_granblue: tweet.source == GRANBLUE_APP_SOURCE,
+            is_retweet,
+            created_at: tweet.created_at,
+            user_id: user.id,
+            user_screen_name: user.screen_name,
+            user_display_name: user.name,
+            user_image,
+            images,
+        }
+    }
+
+    // Returns `None` if the payload is missing the tweet's ID or author
+    fn from_v2(payload: &'a twitter_v2::Payload, images: Vec<BossImageUrl>) -> Option<Self> {
+        let tweet = payload.data.as_ref()?;
+        let author = payload.author()?;
+
+        let is_retweet = tweet.is_retweet();
+
+        let user_image = author.profile_image_url.as_ref().and_then(|url| {
+            if url.contains("default_profile") {
+                None
+            } else {
+                Some(Cow::from(url.as_str()))
+            }
+        });
+
+        Some(Status {
+            id: tweet.id.parse().ok()?,
+            text: Cow::from(tweet.text.as_str()),
+            // The source is only sent if it's requested, so tweets without
+            // one are left to the patterns
+            is_granblue: tweet
+                .source
+                .as_ref()
+                .map(|source| source == GRANBLUE_APP_NAME)
+                .unwrap_or(true),
+            is_retweet,
+            created_at: tweet.created_at.unwrap_or_else(Utc::now),
+            user_id: author.id.parse().ok()?,
+            user_screen_name: Cow::from(author.username.as_str()),
+            user_display_name: Cow::from(author.name.as_str()),
+            user_image,
+            images,
+        })
+    }
+}
+
+impl ParserConfig {
+    // Like `Parser::parse`, but with the given images instead of the ones
+    // attached to the tweet, for parsers that find the boss' image somewhere
+    // else. As with attached images, the last one becomes `RaidInfo::image`.
+    pub fn parse_with_images(&self, tweet: Tweet, images: Vec<BossImageUrl>) -> Option<RaidInfo> {
+        self.parse_status(Status::from_v1(tweet, images))
+    }
+
+    // Like `parse_with_images`, for the v2 filtered stream
+    pub fn parse_v2_with_images(
+        &self,
+        payload: &twitter_v2::Payload,
+        images: Vec<BossImageUrl>,
+    ) -> Option<RaidInfo> {
+        match Status::from_v2(payload, images) {
+            Some(status) => self.parse_status(status),
+            None => {
+                warn!("Missing tweet ID or author in v2 payload: {:?}", payload);
+                None
+            }
+        }
+    }
+
+    fn parse_status(&self, status: Status) -> Option<RaidInfo> {
+        let Status {
+            id,
+            text,
+            is_granblue,
+            is_retweet,
+            created_at,
+            user_id,
+            user_screen_name,
+            user_display_name,
+            user_image,
+            images,
+        } = status;
+
+        if !is_granblue {
+            return None;
+        }
+
+        if is_retweet && !self.include_retweets {
+            return None;
+        }
+
+        let text = normalize_width(text);
+        let parsed = self.parse_text(&text);
+
+        if parsed.is_none() {
+            warn!("Failed to parse tweet {}: {:?}", id, text);
+        }
+
+        parsed.map(move |parsed| {
+            let boss_name = BossName::from(parsed.boss_name);
+            let level = self.parse_level(&boss_name);
+            let user_image =
+                user_image.map(|url| normalize_user_image(&url, self.full_size_user_images));
+
+            let raid_tweet = RaidTweet {
+                tweet_id: id,
+                boss_name,
+                raid_id: parsed.raid_id.into(),
+                user_screen_name: user_screen_name.into(),
+                user_display_name: user_display_name.into(),
+                user_id,
+                user_image,
+                text: parsed.text.map(Into::into),
+                created_at,
+                language: parsed.language,
+                seq: 0,
+                raw: None,
+            };
+
+            RaidInfo {
+                tweet: raid_tweet,
+                image: images.last().cloned(),
+                images,
+                level,
+                raw: None,
+            }
+        })
+    }
+}
+
+impl Parser for ParserConfig {
+    fn parse(&self, tweet: Tweet) -> Option<RaidInfo> {
+        let images = media_images(&tweet);
+        self.parse_with_images(tweet, images)
+    }
+
+    fn parse_v2(&self, payload: &twitter_v2::Payload) -> Option<RaidInfo> {
+        let images = payload.media_urls().into_iter().map(BossImageUrl::from).collect();
+        self.parse_v2_with_images(payload, images)
+    }
+}
+
+// Retweets and quoted tweets, which `ParserConfig` skips by default
+pub fn is_retweet(tweet: &Tweet) -> bool {
+    tweet.retweeted_status.is_some() || tweet.is_quote_status || tweet.quoted_status.is_some()
+        || tweet.text.starts_with("RT @")
+}
+
+// The images attached to the tweet, which `ParserConfig` uses by default
+pub fn media_images(tweet: &Tweet) -> Vec<BossImageUrl> {
+    tweet.entities.media.as_ref().map_or(vec![], |media| {
+        media
+            .iter()
+            .map(|m| BossImageUrl::from(&m.media_url_https))
+            .collect()
+    })
+}
+
+pub(crate) fn normalize_user_image(url: &str, full_size: bool) -> String {
+    let url = if url.starts_with("http://") {
+        url.replacen("http://", "https://", 1)
+    } else {
+        url.to_string()
+    };
+
+    if !full_size {
+        return url;
+    }
+
+    // Only the file name has the suffix, e.g. `walfie_normal.png`
+    let name_start = url.rfind('/').map_or(0, |i| i + 1);
+    let name = &url[name_start..];
+    let stem_len = name.rfind('.').unwrap_or(name.len());
+    if name[..stem_len].ends_with("_normal") {
+        let suffix_start = name_start + stem_len - "_normal".len();
+        format!("{}{}", &url[..suffix_start], &name[stem_len..])
+    } else {
+        url
+    }
+}
+
+// Replaces full-width digits and letters with their ASCII equivalents, so
+// raid IDs and levels typed with a Japanese keyboard still match. Other
+// full-width characters are left alone, since the patterns rely on them.
+fn normalize_width(text: Cow<str>) -> Cow<str> {
+    if !text.chars().any(|c| half_width_alphanumeric(c).is_some()) {
+        return text;
+    }
+
+    let normalized = text.chars()
+        .map(|c| half_width_alphanumeric(c).unwrap_or(c))
+        .collect::<String>();
+
+    Cow::Owned(normalized)
+}
+
+fn half_width_alphanumeric(c: char) -> Option<char> {
+    match c {
+        '０'..='９' | 'Ａ'..='Ｚ' | 'ａ'..='ｚ' => ::std::char::from_u32(c as u32 - 0xFEE0),
+        _ => None,
+    }
 }
 
-#[must_use = "streams do nothing unless polled"]
-pub struct RaidInfoStream(FlattenStream<FutureTwitterStream>);
+fn compile_pattern(pattern: &str, required_groups: &[&str]) -> Result<Regex> {
+    let regex = Regex::new(pattern).chain_err(|| ErrorKind::InvalidPattern(pattern.to_string()))?;
+
+    for group in required_groups {
+        if !regex.capture_names().any(|name| name == Some(group)) {
+            bail!(ErrorKind::InvalidPattern(pattern.to_string()));
+        }
+    }
+
+    Ok(regex)
+}
+
+#[cfg(test)]
+impl Reconnect {
+    fn new<F>(config: ReconnectConfig, connect: F) -> Self
+    where
+        F: Fn() -> TwitterJsonStream + 'static,
+    {
+        Self::with_tokens(config, 1, move |_| connect())
+    }
+}
+
+#[cfg(test)]
+impl<'a> TweetParts<'a> {
+    fn new(
+        language: Language,
+        text: Option<&'a str>,
+        raid_id: &'a str,
+        boss_name: &'a str,
+    ) -> Self {
+        TweetParts {
+            language,
+            text,
+            raid_id,
+            boss_name,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::Language::{English, Japanese};
+    use std::io::Cursor;
+    use tokio_core::reactor::Core;
+
+    use serde_json;
+
+    fn parse_text<'a>(tweet_text: &'a str) -> Option<TweetParts<'a>> {
+        DEFAULT_PARSER_CONFIG.parse_text(tweet_text)
+    }
+
+    fn tweet_json(text: &str, media_urls: &[&str]) -> String {
+        let media = media_urls
+            .iter()
+            .enumerate()
+            .map(|(i, url)| {
+                let size = r#"{ "w": 1, "h": 1, "resize": "fit" }"#;
+                format!(
+                    r#"{{
+                        "id": {id},
+                        "id_str": "{id}",
+                        "indices": [0, 0],
+                        "media_url": {url},
+                        "media_url_https": {url},
+                        "url": "",
+                        "display_url": "",
+                        "expanded_url": "",
+                        "type": "photo",
+                        "sizes": {{
+                            "thumb": {size},
+                            "large": {size},
+                            "medium": {size},
+                            "small": {size}
+                        }}
+                    }}"#,
+                    id = i,
+                    url = serde_json::to_string(url).unwrap(),
+                    size = size,
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+
+        format!(
+            r#"{{
+                "created_at": "Mon May 01 00:00:00 +0000 2017",
+                "id": 123456789012345678,
+                "text": {text},
+                "source": {source},
+                "truncated": false,
+                "is_quote_status": false,
+                "retweet_count": 0,
+                "retweeted": false,
+                "entities": {{
+                    "hashtags": [],
+                    "urls": [],
+                    "user_mentions": [],
+                    "symbols": [],
+                    "media": [{media}]
+                }},
+                "user": {{
+                    "id": 1234567890,
+                    "name": "walfie",
+                    "screen_name": "walfieee",
+                    "protected": false,
+                    "verified": false,
+                    "followers_count": 1,
+                    "friends_count": 1,
+                    "listed_count": 1,
+                    "favourites_count": 1,
+                    "statuses_count": 1,
+                    "created_at": "Thu Jan 01 12:34:56 +0000 2015",
+                    "geo_enabled": false,
+                    "lang": "ja",
+                    "contributors_enabled": false,
+                    "is_translator": false,
+                    "profile_background_color": "F5F8FA",
+                    "profile_background_image_url": "",
+                    "profile_background_image_url_https": "",
+                    "profile_background_tile": false,
+                    "profile_link_color": "1DA1F2",
+                    "profile_sidebar_border_color": "C0DEED",
+                    "profile_sidebar_fill_color": "DDEEF6",
+                    "profile_text_color": "333333",
+                    "profile_use_background_image": true,
+                    "profile_image_url": "http://pbs.twimg.com/profile_images/1/walfie_normal.png",
+                    "profile_image_url_https": "https://pbs.twimg.com/profile_images/1/walfie_normal.png",
+                    "default_profile": false,
+                    "default_profile_image": false
+                }}
+            }}"#,
+            text = serde_json::to_string(text).unwrap(),
+            source = serde_json::to_string(GRANBLUE_APP_SOURCE).unwrap(),
+            media = media,
+        )
+    }
+
+    fn parse_tweet<'a>(json: &'a str) -> Tweet<'a> {
+        match StreamMessage::from_str(json).unwrap() {
+            StreamMessage::Tweet(tweet) => *tweet,
+            other => panic!("expected tweet, got {:?}", other),
+        }
+    }
+
+    fn replay_line(raid_id: &str, created_at: &str) -> String {
+        let text = format!("{} :参戦ID\n参加者募集！\nLv60 オオゾラッコ\nhttp://example.com/image", raid_id);
+        // Raid IDs are hex, so each raid gets its own tweet ID
+        let tweet_id = u64::from_str_radix(raid_id, 16).unwrap().to_string();
+        tweet_json(&text, &[])
+            .replace("123456789012345678", &tweet_id)
+            .replace("Mon May 01 00:00:00", created_at)
+            .replace('\n', " ")
+    }
+
+    #[test]
+    fn replay_from_reader() {
+        let mut core = Core::new().unwrap();
+        let input = format!(
+            "{}\n\n{}\n{}\n",
+            replay_line("ABCD1234", "Mon May 01 00:00:00"),
+            r#"{"delete":{"status":{"id":1,"id_str":"1","user_id":1,"user_id_str":"1"}}}"#,
+            replay_line("ABCD5678", "Mon May 01 00:00:00"),
+        );
+
+        let stream = RaidInfoStream::from_reader(Cursor::new(input), &core.handle(), false);
+        let ids = core.run(stream.map(|info| info.tweet.raid_id).collect()).unwrap();
+        assert_eq!(ids, vec!["ABCD1234", "ABCD5678"]);
+    }
+
+    #[test]
+    fn raw_json() {
+        let mut core = Core::new().unwrap();
+        let line = replay_line("ABCD1234", "Mon May 01 00:00:00");
+
+        let stream = RaidInfoStream::from_reader(Cursor::new(line.clone()), &core.handle(), false);
+        let items = core.run(stream.collect()).unwrap();
+        assert_eq!(items[0].raw, None);
+
+        let stream = RaidInfoStream::from_reader(Cursor::new(line.clone()), &core.handle(), false)
+            .with_raw_json();
+        let items = core.run(stream.collect()).unwrap();
+        assert_eq!(items[0].raw, Some(line));
+    }
+
+    #[test]
+    fn timestamp_ms_preferred() {
+        let mut core = Core::new().unwrap();
+        let created_at = r#""created_at": "Mon May 01 00:00:00 +0000 2017","#;
+        let line = replay_line("ABCD1234", "Mon May 01 00:00:00");
+        let with_timestamp = |date: &str| {
+            line.replacen(created_at, date, 1)
+                .replacen("{", r#"{ "timestamp_ms": "1493596800123","#, 1)
+        };
+        let input = format!(
+            "{}\n{}\n{}\n",
+            line,
+            with_timestamp(""),
+            with_timestamp(r#""created_at": "Mon May 01 12:00:00 +0000 2017","#),
+        );
+
+        // Every line is the same tweet
+        let stream = RaidInfoStream::from_reader(Cursor::new(input), &core.handle(), false)
+            .with_recent_ids_size(0);
+        let metrics = stream.metrics();
+        let dates = core.run(stream.map(|info| info.tweet.created_at).collect())
+            .unwrap();
+
+        let expected = Utc.ymd(2017, 5, 1).and_hms(0, 0, 0);
+        assert_eq!(dates[0], expected);
+        assert_eq!(dates[1], expected + ::chrono::Duration::milliseconds(123));
+        assert_eq!(dates[2], expected + ::chrono::Duration::milliseconds(123));
+        assert_eq!(metrics.get().invalid_dates, 0);
+    }
+
+    #[test]
+    fn invalid_date() {
+        let mut core = Core::new().unwrap();
+        let line = replay_line("ABCD1234", "Someday").replacen("Someday +0000 2017", "???", 1);
+
+        let before = Utc::now();
+        let stream = RaidInfoStream::from_reader(Cursor::new(line), &core.handle(), false);
+        let metrics = stream.metrics();
+        let items = core.run(stream.collect()).unwrap();
+
+        assert_eq!(items.len(), 1);
+        assert!(items[0].tweet.created_at >= before);
+        assert_eq!(metrics.get().invalid_dates, 1);
+        assert_eq!(metrics.get().parse_failures, 0);
+    }
+
+    #[test]
+    fn extended_tweet() {
+        let mut core = Core::new().unwrap();
+        let comment = "This comment is long enough that the raid ID gets cut off ";
+        let full_text = format!("{}{}", comment, RAID_TEXT);
+        let truncated_text = format!("{}ABCD12…", comment);
+        let image = "https://pbs.twimg.com/media/ozorotter.jpg";
+        assert!(parse_text(&truncated_text).is_none());
+
+        let full = serde_json::from_str::<serde_json::Value>(&tweet_json(&full_text, &[image]))
+            .unwrap();
+        let mut entities = full["entities"].clone();
+        entities["media"] = json!([]);
+
+        let mut status =
+            serde_json::from_str::<serde_json::Value>(&tweet_json(&truncated_text, &[])).unwrap();
+        status["truncated"] = json!(true);
+        status["extended_tweet"] = json!({
+            "full_text": full_text,
+            "entities": entities,
+            "extended_entities": { "media": full["entities"]["media"] },
+        });
+
+        let line = serde_json::to_string(&status).unwrap();
+        let stream = RaidInfoStream::from_reader(Cursor::new(line), &core.handle(), false);
+        let items = core.run(stream.collect()).unwrap();
+
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].tweet.raid_id, "ABCD1234");
+        assert_eq!(items[0].tweet.text, Some(comment.trim().into()));
+        assert_eq!(items[0].image, Some(image.into()));
+    }
+
+    fn v2_payload(text: &str) -> serde_json::Value {
+        json!({
+            "data": {
+                "id": "1234",
+                "text": text,
+                "author_id": "5678",
+                "created_at": "2017-05-01T00:00:00.000Z",
+                "source": GRANBLUE_APP_NAME,
+                "attachments": { "media_keys": ["3_1", "3_2"] },
+            },
+            "includes": {
+                "users": [{
+                    "id": "5678",
+                    "name": "Walfie",
+                    "username": "walfie",
+                    "profile_image_url": "https://pbs.twimg.com/profile_images/1/walfie_normal.png",
+                }],
+                "media": [
+                    { "media_key": "3_2", "type": "photo", "url": "https://example.com/b.jpg" },
+                    { "media_key": "3_1", "type": "photo", "url": "https://example.com/a.jpg" },
+                ],
+            },
+        })
+    }
+
+    #[test]
+    fn parse_v2() {
+        let mut core = Core::new().unwrap();
+        let payload = v2_payload(RAID_TEXT);
+
+        let parsed = serde_json::from_value::<twitter_v2::Payload>(payload.clone()).unwrap();
+        assert_eq!(parsed.author().map(|u| u.username.as_str()), Some("walfie"));
+        assert_eq!(
+            parsed.media_urls(),
+            vec!["https://example.com/a.jpg", "https://example.com/b.jpg"]
+        );
+
+        let mut retweet = v2_payload(RAID_TEXT);
+        retweet["data"]["id"] = json!("1235");
+        retweet["data"]["referenced_tweets"] = json!([{ "type": "retweeted", "id": "1" }]);
+        let error = json!({ "errors": [{ "title": "operational-disconnect" }] });
+
+        let input = format!("{}\n{}\n{}\n", error, retweet, payload);
+        let mut stream = RaidInfoStream::from_reader(Cursor::new(input), &core.handle(), false);
+        stream.api_v2 = true;
+        let items = core.run(stream.collect()).unwrap();
+
+        assert_eq!(items.len(), 1);
+        let tweet = &items[0].tweet;
+        assert_eq!(tweet.tweet_id, 1234);
+        assert_eq!(tweet.raid_id, "ABCD1234");
+        assert_eq!(tweet.boss_name, "Lv60 オオゾラッコ".into());
+        assert_eq!(tweet.user_screen_name, "walfie");
+        assert_eq!(tweet.user_display_name, "Walfie");
+        assert_eq!(tweet.user_id, 5678);
+        assert_eq!(tweet.created_at, Utc.ymd(2017, 5, 1).and_hms(0, 0, 0));
+        assert_eq!(items[0].image, Some("https://example.com/b.jpg".into()));
+    }
+
+    #[test]
+    fn drop_duplicate_tweets() {
+        let mut core = Core::new().unwrap();
+        let input = [
+            replay_line("ABCD1234", "Mon May 01 00:00:00"),
+            replay_line("ABCD5678", "Mon May 01 00:00:00"),
+            replay_line("ABCD1234", "Mon May 01 00:00:00"),
+            replay_line("ABCD9999", "Mon May 01 00:00:00"),
+            replay_line("ABCD1234", "Mon May 01 00:00:00"),
+        ].join("\n");
+
+        // With room for 2 IDs, the first one is forgotten by the last line
+        let stream = RaidInfoStream::from_reader(Cursor::new(input), &core.handle(), false)
+            .with_recent_ids_size(2);
+        let metrics = stream.metrics();
+        let raid_ids = core.run(stream.map(|info| info.tweet.raid_id).collect())
+            .unwrap();
+
+        assert_eq!(raid_ids, vec!["ABCD1234", "ABCD5678", "ABCD9999", "ABCD1234"]);
+        assert_eq!(metrics.get().duplicates, 1);
+        assert_eq!(metrics.get().received, 5);
+
+        let mut recent = RecentIds::with_capacity(0);
+        assert!(recent.insert(1));
+        assert!(recent.insert(1));
+    }
+
+    #[test]
+    fn stream_metrics() {
+        let mut core = Core::new().unwrap();
+        let input = format!(
+            "{}\n{}\n{}\n",
+            replay_line("ABCD1234", "Mon May 01 00:00:00"),
+            r#"{"delete":{"status":{"id":1,"id_str":"1","user_id":1,"user_id_str":"1"}}}"#,
+            "not json",
+        );
+
+        let stream = RaidInfoStream::from_reader(Cursor::new(input), &core.handle(), false);
+        let metrics = stream.metrics();
+        let (first, stream) = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+        assert!(first.is_some());
+        // The malformed line is skipped, and the stream ends with the input
+        assert_eq!(core.run(stream.collect()).unwrap(), vec![]);
+
+        let expected = StreamCounts {
+            received: 3,
+            raids: 1,
+            parse_failures: 1,
+            invalid_dates: 0,
+            duplicates: 0,
+        };
+        assert_eq!(metrics.reset(), expected);
+        assert_eq!(metrics.get(), StreamCounts::default());
+    }
+
+    #[test]
+    fn parse_failure_hook() {
+        let mut core = Core::new().unwrap();
+        let lines = [
+            replay_line("ABCD1234", "Mon May 01 00:00:00"),
+            "not json".to_string(),
+            tweet_json("ABCD5678 :参戦ID\n参加者募集！", &[]).replace('\n', " "),
+            tweet_json("Unrelated tweet", &[]).replace('\n', " "),
+            replay_line("ABCD9999", "Mon May 01 00:01:00"),
+        ];
+        let input = lines.join("\n");
+
+        let failures = Rc::new(::std::cell::RefCell::new(Vec::new()));
+        let stream = {
+            let failures = failures.clone();
+            RaidInfoStream::from_reader(Cursor::new(input), &core.handle(), false)
+                .on_parse_failure(move |text| failures.borrow_mut().push(text.to_string()))
+        };
+
+        assert_eq!(core.run(stream.collect()).unwrap().len(), 2);
+        assert_eq!(
+            *failures.borrow(),
+            vec!["not json", "ABCD5678 :参戦ID\n参加者募集！"]
+        );
+    }
+
+    #[test]
+    fn replay_respect_timestamps() {
+        let mut core = Core::new().unwrap();
+        let input = format!(
+            "{}\n{}\n",
+            replay_line("ABCD1234", "Mon May 01 00:00:00"),
+            replay_line("ABCD5678", "Mon May 01 00:01:00"),
+        );
+
+        let stream = RaidInfoStream::from_reader(Cursor::new(input), &core.handle(), true);
+        let (first, mut stream) = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+        assert_eq!(first.unwrap().tweet.raid_id, "ABCD1234");
+
+        // The second raid was tweeted a minute later
+        let polled = core.run(::futures::future::lazy(|| stream.poll())).unwrap();
+        assert!(polled.is_not_ready());
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedWriter(Rc<::std::cell::RefCell<Vec<u8>>>);
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct FailingWriter;
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> ::std::io::Result<usize> {
+            Err(::std::io::ErrorKind::Other.into())
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            Err(::std::io::ErrorKind::Other.into())
+        }
+    }
+
+    #[test]
+    fn record_statuses() {
+        let mut core = Core::new().unwrap();
+        let lines = [
+            replay_line("ABCD1234", "Mon May 01 00:00:00"),
+            r#"{"delete":{"status":{"id":1,"id_str":"1","user_id":1,"user_id_str":"1"}}}"#.into(),
+        ];
+        let input = format!("{}\n", lines.join("\n"));
+
+        let writer = SharedWriter::default();
+        let stream = RaidInfoStream::from_reader(Cursor::new(input.clone()), &core.handle(), false)
+            .record_to(writer.clone());
+        assert_eq!(core.run(stream.collect()).unwrap().len(), 1);
+
+        let recorded = String::from_utf8(writer.0.borrow().clone()).unwrap();
+        assert_eq!(recorded, input);
+    }
+
+    #[test]
+    fn record_flushed_when_idle() {
+        let mut core = Core::new().unwrap();
+        let line = replay_line("ABCD1234", "Mon May 01 00:00:00");
+        let input: TwitterJsonStream = Box::new(
+            stream::once(Ok(JsonStr::from(line.clone()))).chain(quiet_stream()),
+        );
+
+        let writer = SharedWriter::default();
+        let buffered = ::std::io::BufWriter::new(writer.clone());
+        let mut stream = RaidInfoStream::new(input, &core.handle(), None).record_to(buffered);
 
-// TODO: Add version that reconnects on disconnect/error
-impl RaidInfoStream {
-    fn track() -> &'static str {
-        "参加者募集！,:参戦ID,I need backup!,:Battle ID"
+        let polled = core.run(::futures::future::lazy(|| stream.poll())).unwrap();
+        assert!(polled.is_ready());
+        assert!(writer.0.borrow().is_empty());
+
+        let polled = core.run(::futures::future::lazy(|| stream.poll())).unwrap();
+        assert!(polled.is_not_ready());
+        let recorded = String::from_utf8(writer.0.borrow().clone()).unwrap();
+        assert_eq!(recorded, format!("{}\n", line));
     }
 
-    pub fn with_client<C, B>(hyper_client: &hyper::Client<C, B>, token: &Token) -> Self
-    where
-        C: hyper::client::Connect,
-        B: From<Vec<u8>> + Stream<Error = hyper::Error> + 'static,
-        B::Item: AsRef<[u8]>,
-    {
-        let stream = TwitterStreamBuilder::filter(token)
-            .client(&hyper_client)
-            .user_agent(Some("petronel")) // TODO: Make this configurable?
-            .timeout(None)
-            .track(Some(Self::track()))
-            .listen()
-            .flatten_stream();
+    #[test]
+    fn record_errors_counted() {
+        let mut core = Core::new().unwrap();
+        let input = format!(
+            "{}\n{}\n",
+            replay_line("ABCD1234", "Mon May 01 00:00:00"),
+            replay_line("ABCD5678", "Mon May 01 00:00:00"),
+        );
 
-        RaidInfoStream(stream)
+        let stream = RaidInfoStream::from_reader(Cursor::new(input), &core.handle(), false)
+            .record_to(FailingWriter);
+        let stats = stream.connection_stats();
+
+        assert_eq!(core.run(stream.collect()).unwrap().len(), 2);
+        assert_eq!(stats.get().record_errors, 2);
     }
 
-    // TODO: Clean up duplicated code
-    pub fn with_handle(handle: &Handle, token: &Token) -> Self {
-        let stream = TwitterStreamBuilder::filter(token)
-            .handle(handle)
-            .user_agent(Some("petronel")) // TODO: Make this configurable?
-            .timeout(None)
-            .track(Some(&Self::track()))
-            .listen()
-            .flatten_stream();
+    #[test]
+    fn reconnect_backoff() {
+        let config = ReconnectConfig::default();
 
-        RaidInfoStream(stream)
+        assert_eq!(config.backoff(0), Duration::from_secs(5));
+        assert_eq!(config.backoff(1), Duration::from_secs(10));
+        assert_eq!(config.backoff(6), Duration::from_secs(320));
+        assert_eq!(config.backoff(7), Duration::from_secs(320));
+        assert_eq!(config.backoff(100), Duration::from_secs(320));
     }
-}
 
-impl Stream for RaidInfoStream {
-    type Item = RaidInfo;
-    type Error = Error;
+    #[test]
+    fn stream_error_policy() {
+        let token = Token::new("", "", "", "");
+        let config = ReconnectConfig {
+            initial_backoff: Duration::from_secs(1),
+            max_retries: Some(3),
+            ..ReconnectConfig::default()
+        };
+        let builder = RaidInfoStreamBuilder::new(&token).with_reconnect(config.clone());
+        assert_eq!(builder.reconnect, Some(config.clone()));
 
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        loop {
-            let polled = self.0.poll().chain_err(|| ErrorKind::Twitter);
-            if let Some(json) = try_ready!(polled) {
-                let msg = StreamMessage::from_str(json.as_ref())
-                    .chain_err(|| ErrorKind::Json(json.to_string()))?;
-
-                if let StreamMessage::Tweet(tweet) = msg {
-                    if let Some(raid_info) = RaidInfo::from_tweet(*tweet) {
-                        return Ok(Async::Ready(Some(raid_info)));
-                    }
+        let policy = |policy| builder.clone().with_error_policy(policy).reconnect;
+        assert_eq!(policy(StreamErrorPolicy::Abort), None);
+        assert_eq!(
+            policy(StreamErrorPolicy::Retry(5)),
+            Some(ReconnectConfig {
+                max_retries: Some(5),
+                ..config.clone()
+            })
+        );
+        assert_eq!(
+            policy(StreamErrorPolicy::RetryForever),
+            Some(ReconnectConfig {
+                max_retries: None,
+                ..config
+            })
+        );
+
+        let retry = RaidInfoStreamBuilder::new(&token)
+            .with_error_policy(StreamErrorPolicy::RetryForever)
+            .reconnect;
+        assert_eq!(retry, Some(ReconnectConfig::default()));
+
+        // The last call wins
+        let reconnect = RaidInfoStreamBuilder::new(&token)
+            .with_error_policy(StreamErrorPolicy::Abort)
+            .with_reconnect(config.clone())
+            .reconnect;
+        assert_eq!(reconnect, Some(config.clone()));
+    }
+
+    #[test]
+    fn connect_bearer_token() {
+        let core = Core::new().unwrap();
+        let builder = RaidInfoStreamBuilder::with_bearer_token("token");
+
+        match builder.connect(&core.handle()) {
+            Err(Error(ErrorKind::BearerTokenNeedsClient, _)) => {}
+            Err(e) => panic!("expected BearerTokenNeedsClient, got {}", e),
+            Ok(_) => panic!("expected BearerTokenNeedsClient"),
+        }
+    }
+
+    #[test]
+    fn follow_user_ids() {
+        let token = Token::new("", "", "", "");
+        let builder = RaidInfoStreamBuilder::new(&token);
+        assert_eq!(builder.connect_options().follow(), None);
+
+        let options = builder.with_follow(vec![1, 2]).connect_options();
+        assert_eq!(options.follow(), Some(&[1, 2][..]));
+        assert_eq!(options.track, TrackOptions::default().track());
+    }
+
+    #[test]
+    fn default_track_keywords() {
+        assert_eq!(
+            TrackOptions::default().track(),
+            "参加者募集！,:参戦ID,I need backup!,:Battle ID"
+        );
+    }
+
+    #[test]
+    fn rate_limit_backoff() {
+        let config = ReconnectConfig::default();
+
+        assert_eq!(config.rate_limit_backoff(0), Duration::from_secs(60));
+        assert_eq!(config.rate_limit_backoff(1), Duration::from_secs(120));
+        assert_eq!(config.rate_limit_backoff(3), Duration::from_secs(320));
+
+        let config = ReconnectConfig {
+            max_backoff: Duration::from_secs(10),
+            ..config
+        };
+        assert_eq!(config.rate_limit_backoff(2), Duration::from_secs(60));
+    }
+
+    fn http_error_stream(code: u16) -> TwitterJsonStream {
+        let status = ::hyper::StatusCode::try_from(code).unwrap();
+        Box::new(stream::once(Err(twitter_stream::Error::Http(status))))
+    }
+
+    #[test]
+    fn rate_limited() {
+        let mut core = Core::new().unwrap();
+
+        for &code in &[420, 429] {
+            let stream = RaidInfoStream::new(http_error_stream(code), &core.handle(), None);
+
+            match core.run(stream.into_future()) {
+                Err((Error(ErrorKind::RateLimited(wait), _), _)) => {
+                    assert_eq!(wait, Duration::from_secs(60));
                 }
-            } else {
-                return Ok(Async::Ready(None));
+                Err((e, _)) => panic!("expected rate limit error, got {}", e),
+                Ok(_) => panic!("expected rate limit error"),
             }
         }
     }
-}
 
-#[derive(Clone, Debug, PartialEq)]
-struct TweetParts<'a> {
-    language: Language,
-    text: Option<&'a str>,
-    raid_id: &'a str,
-    boss_name: &'a str,
-}
+    #[test]
+    fn stream_error_kinds() {
+        let wait = Duration::from_secs(1);
+        let kind = |e| stream_error_kind(&e, wait);
+        let http = |code| twitter_stream::Error::Http(::hyper::StatusCode::try_from(code).unwrap());
+        let io = ::std::io::Error::new(::std::io::ErrorKind::ConnectionReset, "reset");
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct RaidInfo {
-    pub tweet: RaidTweet,
-    pub image: Option<BossImageUrl>,
-}
+        match kind(http(401)) {
+            ErrorKind::AuthenticationFailed => {}
+            other => panic!("expected authentication failure, got {:?}", other),
+        }
+        match kind(http(429)) {
+            ErrorKind::RateLimited(d) => assert_eq!(d, wait),
+            other => panic!("expected rate limit, got {:?}", other),
+        }
+        match kind(http(503)) {
+            ErrorKind::HttpStatus(503) => {}
+            other => panic!("expected HTTP status, got {:?}", other),
+        }
+        match kind(twitter_stream::Error::Hyper(::hyper::Error::Io(io))) {
+            ErrorKind::ConnectionReset => {}
+            other => panic!("expected connection reset, got {:?}", other),
+        }
+        match kind(twitter_stream::Error::TimedOut) {
+            ErrorKind::ConnectionReset => {}
+            other => panic!("expected connection reset, got {:?}", other),
+        }
 
-impl RaidInfo {
-    pub fn from_tweet(mut tweet: Tweet) -> Option<RaidInfo> {
-        if tweet.source != GRANBLUE_APP_SOURCE {
-            return None;
+        assert!(!is_transient(&ErrorKind::AuthenticationFailed));
+        assert!(!is_transient(&ErrorKind::HttpStatus(406)));
+        assert!(is_transient(&ErrorKind::HttpStatus(503)));
+        assert!(is_transient(&ErrorKind::ConnectionReset));
+        assert!(is_transient(&ErrorKind::RateLimited(wait)));
+    }
+
+    #[test]
+    fn authentication_failure_no_reconnect() {
+        let mut core = Core::new().unwrap();
+
+        let config = ReconnectConfig {
+            initial_backoff: Duration::from_millis(1),
+            ..ReconnectConfig::default()
+        };
+        let reconnect = Reconnect::new(config, || http_error_stream(401));
+        let stream = RaidInfoStream::new(http_error_stream(401), &core.handle(), Some(reconnect));
+        let stats = stream.connection_stats();
+
+        match core.run(stream.into_future()) {
+            Err((Error(ErrorKind::AuthenticationFailed, _), _)) => {}
+            Err((e, _)) => panic!("expected authentication failure, got {}", e),
+            Ok(_) => panic!("expected authentication failure"),
         }
+        assert_eq!(stats.get().connection_attempts, 1);
+    }
 
-        let text = ::std::mem::replace(&mut tweet.text, "".into());
+    #[test]
+    fn rate_limited_reconnect() {
+        let mut core = Core::new().unwrap();
 
-        parse_text(&text).map(move |parsed| {
-            let user_image = if tweet.user.default_profile_image
-                || tweet
-                    .user
-                    .profile_image_url_https
-                    .contains("default_profile")
-            {
-                None
-            } else {
-                Some(tweet.user.profile_image_url_https.into())
-            };
+        let config = ReconnectConfig {
+            initial_backoff: Duration::from_secs(60),
+            initial_rate_limit_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+            max_retries: Some(1),
+        };
+        let reconnect = Reconnect::new(config, || http_error_stream(420));
+        let stream = RaidInfoStream::new(http_error_stream(420), &core.handle(), Some(reconnect));
+        let stats = stream.connection_stats();
 
-            let raid_tweet = RaidTweet {
-                tweet_id: tweet.id,
-                boss_name: parsed.boss_name.into(),
-                raid_id: parsed.raid_id.into(),
-                user: tweet.user.screen_name.into(),
-                user_image,
-                text: parsed.text.map(Into::into),
-                created_at: tweet.created_at,
-                language: parsed.language,
-            };
+        match core.run(stream.into_future()) {
+            Err((Error(ErrorKind::RateLimited(wait), _), _)) => {
+                assert_eq!(wait, Duration::from_millis(1));
+            }
+            Err((e, _)) => panic!("expected rate limit error, got {}", e),
+            Ok(_) => panic!("expected rate limit error"),
+        }
+        assert_eq!(stats.get().connection_attempts, 2);
+    }
 
-            let image = tweet
-                .entities
-                .media
-                .and_then(|mut media| media.pop().map(|m| m.media_url_https.into()));
+    #[test]
+    fn connection_events() {
+        let mut core = Core::new().unwrap();
 
-            RaidInfo {
-                tweet: raid_tweet,
-                image,
+        let delete = r#"{"delete":{"status":{"id":1,"id_str":"1","user_id":1,"user_id_str":"1"}}}"#;
+        let first = stream::once(Ok(JsonStr::from(delete.to_string())))
+            .chain(http_error_stream(503));
+        let config = ReconnectConfig {
+            initial_backoff: Duration::from_millis(1),
+            max_retries: Some(1),
+            ..ReconnectConfig::default()
+        };
+        let reconnect = Reconnect::new(config, || http_error_stream(503));
+        let mut stream = RaidInfoStream::new(Box::new(first), &core.handle(), Some(reconnect));
+        let events = stream.connection_events();
+
+        let before = Utc::now();
+        assert!(core.run(stream.collect()).is_err());
+        let events = core.run(events.collect()).unwrap();
+
+        assert_eq!(events.len(), 4);
+        match events[0] {
+            ConnectionEvent::Connected { .. } => {}
+            ref other => panic!("expected connected, got {:?}", other),
+        }
+        match events[1] {
+            ConnectionEvent::Disconnected { ref reason, .. } => {
+                assert!(reason.contains("503"), "{}", reason);
             }
-        })
+            ref other => panic!("expected disconnected, got {:?}", other),
+        }
+        match events[2] {
+            ConnectionEvent::Reconnecting { attempt, delay, .. } => {
+                assert_eq!(attempt, 1);
+                assert_eq!(delay, Duration::from_millis(1));
+            }
+            ref other => panic!("expected reconnecting, got {:?}", other),
+        }
+        match events[3] {
+            ConnectionEvent::Disconnected { .. } => {}
+            ref other => panic!("expected disconnected, got {:?}", other),
+        }
+        assert!(events.iter().all(|e| e.at() >= before));
     }
-}
 
-fn parse_text<'a>(tweet_text: &'a str) -> Option<TweetParts<'a>> {
-    REGEX_JAPANESE
-        .captures(tweet_text)
-        .map(|c| (Language::Japanese, c))
-        .or_else(|| {
-            REGEX_ENGLISH
-                .captures(tweet_text)
-                .map(|c| (Language::English, c))
-        })
-        .and_then(|(lang, c)| {
-            if let (Some(text), Some(id), Some(boss), Some(url)) =
-                (c.name("text"), c.name("id"), c.name("boss"), c.name("url"))
-            {
-                let boss_name = boss.as_str().trim();
-                let url_str = url.as_str();
-
-                if boss_name.contains("http")
-                    || !url_str.is_empty() && !REGEX_IMAGE_URL.is_match(url_str)
-                {
-                    return None;
+    #[test]
+    fn rotate_tokens() {
+        let mut core = Core::new().unwrap();
+
+        let connects = Rc::new(::std::cell::RefCell::new(Vec::new()));
+        let reconnect = {
+            let connects = connects.clone();
+            let config = ReconnectConfig {
+                initial_backoff: Duration::from_millis(1),
+                initial_rate_limit_backoff: Duration::from_secs(60),
+                ..ReconnectConfig::default()
+            };
+
+            // Token 1 works until it's rejected, and token 0 is always rejected
+            Reconnect::with_tokens(config, 2, move |token| -> TwitterJsonStream {
+                connects.borrow_mut().push(token);
+                if token == 1 {
+                    let delete = r#"{"delete":{"status":{"id":1,"user_id":1}}}"#;
+                    Box::new(
+                        stream::once(Ok(JsonStr::from(delete.to_string())))
+                            .chain(http_error_stream(401)),
+                    )
+                } else {
+                    http_error_stream(401)
                 }
+            })
+        };
 
-                let t = text.as_str().trim();
+        let handle = core.handle();
+        let mut stream = RaidInfoStream::new(http_error_stream(429), &handle, Some(reconnect));
+        let events = stream.connection_events();
 
-                Some(TweetParts {
-                    language: lang,
-                    text: if t.is_empty() { None } else { Some(t) },
-                    raid_id: id.as_str().trim(),
-                    boss_name,
-                })
-            } else {
-                None
-            }
-        })
-}
+        match core.run(stream.collect()) {
+            Err(Error(ErrorKind::AuthenticationFailed, _)) => {}
+            Err(e) => panic!("expected authentication failure, got {}", e),
+            Ok(_) => panic!("expected authentication failure"),
+        }
+        assert_eq!(*connects.borrow(), vec![1, 0]);
 
-#[cfg(test)]
-impl<'a> TweetParts<'a> {
-    fn new(
-        language: Language,
-        text: Option<&'a str>,
-        raid_id: &'a str,
-        boss_name: &'a str,
-    ) -> Self {
-        TweetParts {
-            language,
-            text,
-            raid_id,
-            boss_name,
+        let tokens = core.run(events.collect())
+            .unwrap()
+            .into_iter()
+            .filter_map(|event| match event {
+                ConnectionEvent::Connected { token, .. } => Some(("connected", token)),
+                ConnectionEvent::Reconnecting { token, delay, .. } => {
+                    assert_eq!(delay, Duration::from_millis(1));
+                    Some(("reconnecting", token))
+                }
+                ConnectionEvent::Disconnected { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![("reconnecting", 1), ("connected", 1), ("reconnecting", 0)]
+        );
+    }
+
+    fn quiet_stream() -> TwitterJsonStream {
+        Box::new(stream::poll_fn(|| Ok(Async::NotReady)))
+    }
+
+    #[test]
+    fn stall_timeout() {
+        let mut core = Core::new().unwrap();
+
+        let stream = RaidInfoStream::new(quiet_stream(), &core.handle(), None)
+            .with_stall_timeout(Duration::from_millis(10));
+        let stats = stream.connection_stats();
+
+        match core.run(stream.into_future()) {
+            Err((Error(ErrorKind::Stalled, _), _)) => {}
+            Err((e, _)) => panic!("expected stall, got {}", e),
+            Ok(_) => panic!("expected stall"),
         }
+        assert_eq!(
+            stats.get(),
+            ConnectionCounts {
+                connection_attempts: 1,
+                disconnects: 1,
+                record_errors: 0,
+                reconnects: 0,
+                last_reconnect: None,
+            }
+        );
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use super::Language::{English, Japanese};
+    #[test]
+    fn stall_timeout_reconnect() {
+        let mut core = Core::new().unwrap();
+
+        let config = ReconnectConfig {
+            initial_backoff: Duration::from_millis(1),
+            initial_rate_limit_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+            max_retries: Some(2),
+        };
+        let reconnect = Reconnect::new(config, quiet_stream);
+        let stream = RaidInfoStream::new(quiet_stream(), &core.handle(), Some(reconnect))
+            .with_stall_timeout(Duration::from_millis(10));
+        let stats = stream.connection_stats();
+
+        let before = Utc::now();
+        assert!(core.run(stream.into_future()).is_err());
+        let last_reconnect = stats.get().last_reconnect;
+        assert!(last_reconnect.map(|at| at >= before).unwrap_or(false));
+        assert_eq!(
+            stats.get(),
+            ConnectionCounts {
+                connection_attempts: 3,
+                disconnects: 3,
+                record_errors: 0,
+                reconnects: 2,
+                last_reconnect,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_full_width() {
+        assert_eq!(normalize_width("ＡＢＣＤ１２３４ ｘ！".into()), "ABCD1234 x！");
+
+        let json = tweet_json("ＡＢＣＤ１２３４ :参戦ID\n参加者募集！\nＬｖ６０ オオゾラッコ", &[]);
+        let info = RaidInfo::from_tweet(parse_tweet(&json)).unwrap();
+
+        assert_eq!(info.tweet.raid_id, "ABCD1234");
+        assert_eq!(info.tweet.boss_name, "Lv60 オオゾラッコ".into());
+        assert_eq!(info.level, Some(60));
+    }
 
     #[test]
     fn parse_ignore_invalid_text() {
@@ -431,4 +2915,244 @@ mod test {
             ))
         );
     }
+
+    #[test]
+    fn parser_config_custom_pattern() {
+        let config = ParserConfig::default()
+            .english("(?P<id>[0-9A-F]{8}) :Battle ID\nI need backup!\n(?P<boss>.+)")
+            .unwrap();
+
+        assert_eq!(
+            config.parse_text(
+                "ABCD1234 :Battle ID\n\
+                 I need backup!\n\
+                 Lvl 60 Ozorotter",
+            ),
+            Some(TweetParts::new(
+                English,
+                None,
+                "ABCD1234",
+                "Lvl 60 Ozorotter",
+            ))
+        );
+    }
+
+    #[test]
+    fn parser_config_custom_level_pattern() {
+        let config = ParserConfig::default()
+            .boss_level("Level (?P<level>[0-9]+)")
+            .unwrap();
+
+        assert_eq!(config.parse_level(&"Level 75 Ozorotter".into()), Some(75));
+        assert_eq!(config.parse_level(&"Lvl 60 Ozorotter".into()), None);
+    }
+
+    #[test]
+    fn parser_config_invalid_pattern() {
+        assert!(ParserConfig::default().japanese("(?P<id>[0-9A-F]{8}").is_err());
+    }
+
+    #[test]
+    fn parser_config_missing_group() {
+        assert!(
+            ParserConfig::default()
+                .english("(?P<id>[0-9A-F]{8}) :Battle ID")
+                .is_err()
+        );
+        assert!(ParserConfig::default().boss_level("Lvl [0-9]+").is_err());
+    }
+
+    #[test]
+    fn stream_with_parser_config() {
+        let core = Core::new().unwrap();
+        let token = Token::new("consumer", "secret", "access", "secret");
+        let config = ParserConfig::default()
+            .boss_level("Level (?P<level>[0-9]+)")
+            .unwrap();
+        let json = tweet_json(
+            "ABCD1234 :Battle ID\n\
+             I need backup!\n\
+             Level 75 Ozorotter",
+            &[],
+        );
+
+        let client = hyper::Client::new(&core.handle());
+        let stream = RaidInfoStream::with_client(&client, &token);
+        let info = stream.parser.parse(parse_tweet(&json)).unwrap();
+        assert_eq!(info.level, None);
+
+        let stream = stream.with_parser_config(config);
+        let info = stream.parser.parse(parse_tweet(&json)).unwrap();
+        assert_eq!(info.level, Some(75));
+    }
+
+    #[test]
+    fn parser_custom_images() {
+        struct LinkedImageParser(ParserConfig);
+        impl Parser for LinkedImageParser {
+            fn parse(&self, tweet: Tweet) -> Option<RaidInfo> {
+                let images = tweet
+                    .text
+                    .split_whitespace()
+                    .filter(|word| word.ends_with(".jpg"))
+                    .map(BossImageUrl::from)
+                    .collect();
+                self.0.parse_with_images(tweet, images)
+            }
+        }
+
+        let attached = "https://pbs.twimg.com/media/attached.jpg";
+        let linked = "https://example.com/linked.jpg";
+        let text = format!("{}\n{}", RAID_TEXT, linked);
+        let json = tweet_json(&text, &[attached]);
+
+        let default = DEFAULT_PARSER_CONFIG.parse(parse_tweet(&json)).unwrap();
+        assert_eq!(default.image, Some(attached.into()));
+        assert_eq!(media_images(&parse_tweet(&json)), vec![attached.into()]);
+
+        let custom = LinkedImageParser(ParserConfig::default());
+        let info = custom.parse(parse_tweet(&json)).unwrap();
+        assert_eq!(info.tweet.raid_id, "ABCD1234");
+        assert_eq!(info.image, Some(linked.into()));
+        assert_eq!(info.images, vec![linked.into()]);
+    }
+
+    #[test]
+    fn parser_trait_object() {
+        let json = tweet_json(
+            "Help me ABCD1234 :Battle ID\n\
+             I need backup!\n\
+             Lvl 60 Ozorotter",
+            &[],
+        );
+
+        let lenient: Box<Parser> = Box::new(ParserConfig::default());
+        let strict: Box<Parser> = Box::new(
+            ParserConfig::default()
+                .english("^(?P<id>[0-9A-F]{8}) :Battle ID\nI need backup!\n(?P<boss>.+)$")
+                .unwrap(),
+        );
+
+        let info = lenient.parse(parse_tweet(&json)).unwrap();
+        assert_eq!(info.tweet.raid_id, "ABCD1234");
+        assert_eq!(info.tweet.boss_name, "Lvl 60 Ozorotter".into());
+        assert_eq!(info.tweet.text, Some("Help me".into()));
+        assert_eq!(info.level, Some(60));
+
+        assert_eq!(strict.parse(parse_tweet(&json)), None);
+    }
+
+    const RAID_TEXT: &str = "ABCD1234 :参戦ID\n参加者募集！\nLv60 オオゾラッコ";
+
+    const RETWEET_JSON: &str = include_str!("testdata/retweet.json");
+    const QUOTE_TWEET_JSON: &str = include_str!("testdata/quote_tweet.json");
+
+    #[test]
+    fn parse_ignore_retweets() {
+        let parser = ParserConfig::default();
+        let (retweet, quote) = (RETWEET_JSON, QUOTE_TWEET_JSON);
+
+        assert!(parser.parse(parse_tweet(&tweet_json(RAID_TEXT, &[]))).is_some());
+        assert_eq!(parser.parse(parse_tweet(retweet)), None);
+        assert_eq!(parser.parse(parse_tweet(quote)), None);
+
+        let parser = parser.include_retweets(true);
+        assert!(parser.parse(parse_tweet(quote)).is_some());
+        assert!(parser.parse(parse_tweet(retweet)).is_some());
+    }
+
+    #[test]
+    fn parse_failure_hook_ignore_retweets() {
+        let mut core = Core::new().unwrap();
+        let input = [RETWEET_JSON, QUOTE_TWEET_JSON]
+            .iter()
+            .map(|json| json.replace('\n', " "))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let failures = Rc::new(::std::cell::RefCell::new(Vec::new()));
+        let stream = {
+            let failures = failures.clone();
+            RaidInfoStream::from_reader(Cursor::new(input), &core.handle(), false)
+                .on_parse_failure(move |text| failures.borrow_mut().push(text.to_string()))
+        };
+
+        assert_eq!(core.run(stream.collect()).unwrap(), vec![]);
+        assert!(failures.borrow().is_empty());
+    }
+
+    #[test]
+    fn parse_user() {
+        let json = tweet_json(RAID_TEXT, &[]);
+        let tweet = RaidInfo::from_tweet(parse_tweet(&json)).unwrap().tweet;
+
+        assert_eq!(tweet.user_screen_name, "walfieee");
+        assert_eq!(tweet.user_display_name, "walfie");
+        assert_eq!(tweet.user_id, 1234567890);
+    }
+
+    #[test]
+    fn user_image_urls() {
+        let normalize = |url, full_size| normalize_user_image(url, full_size);
+        let prefix = "https://pbs.twimg.com/profile_images/1";
+
+        let url = "http://pbs.twimg.com/profile_images/1/walfie_normal.png";
+        assert_eq!(normalize(url, false), format!("{}/walfie_normal.png", prefix));
+        assert_eq!(normalize(url, true), format!("{}/walfie.png", prefix));
+
+        let url = "https://pbs.twimg.com/profile_images/1/walfie_normal.jpeg";
+        assert_eq!(normalize(url, false), url);
+        assert_eq!(normalize(url, true), format!("{}/walfie.jpeg", prefix));
+
+        // Already full size, or with no extension
+        let url = "https://pbs.twimg.com/profile_images/1/walfie.png";
+        assert_eq!(normalize(url, true), url);
+        let url = "https://pbs.twimg.com/profile_images/1/walfie_normal";
+        assert_eq!(normalize(url, true), format!("{}/walfie", prefix));
+
+        // Only the file name is changed
+        let url = "https://pbs.twimg.com/profile_images/1_normal/walfie.png";
+        assert_eq!(normalize(url, true), url);
+
+        let json = tweet_json(RAID_TEXT, &[]);
+        let parser = ParserConfig::default().full_size_user_images(true);
+        let info = parser.parse(parse_tweet(&json)).unwrap();
+        assert_eq!(info.tweet.user_image, Some(format!("{}/walfie.png", prefix)));
+    }
+
+    #[test]
+    fn parse_multiple_images() {
+        let json = tweet_json(
+            "ABCD1234 :Battle ID\n\
+             I need backup!\n\
+             Lvl 60 Ozorotter",
+            &[
+                "https://pbs.twimg.com/media/first.jpg",
+                "https://pbs.twimg.com/media/second.jpg",
+            ],
+        );
+        let info = RaidInfo::from_tweet(parse_tweet(&json)).unwrap();
+
+        assert_eq!(
+            info.images,
+            vec![
+                BossImageUrl::from("https://pbs.twimg.com/media/first.jpg"),
+                BossImageUrl::from("https://pbs.twimg.com/media/second.jpg"),
+            ]
+        );
+        assert_eq!(
+            info.image,
+            Some("https://pbs.twimg.com/media/second.jpg".into())
+        );
+
+        let json = tweet_json(
+            "ABCD1234 :Battle ID\n\
+             I need backup!\n\
+             Lvl 60 Ozorotter",
+            &[],
+        );
+        let info = RaidInfo::from_tweet(parse_tweet(&json)).unwrap();
+        assert_eq!(info.images, vec![]);
+        assert_eq!(info.image, None);
+    }
 }
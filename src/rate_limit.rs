@@ -0,0 +1,115 @@
+use chrono::Duration;
+use model::DateTime;
+
+// Allows bursts of up to `capacity` events, refilling at a rate of
+// `capacity` events per `per`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub capacity: u32,
+    pub per: Duration,
+}
+
+// What happens to tweets over a follow's rate limit. With `Coalesce`, the
+// next tweet that gets through is preceded by a `Message::Skipped` with the
+// number of tweets that didn't.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RateLimitPolicy {
+    Drop,
+    Coalesce,
+}
+
+// A token is split into as many units as there are milliseconds in `per`,
+// so that refills are exact
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    units: i64,
+    last_refill: Option<DateTime>,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        let mut bucket = TokenBucket {
+            limit,
+            units: 0,
+            last_refill: None,
+        };
+        bucket.units = bucket.max_units();
+        bucket
+    }
+
+    fn units_per_token(&self) -> i64 {
+        self.limit.per.num_milliseconds().max(1)
+    }
+
+    // Saturates for very long periods, e.g. `Duration::max_value()`, which
+    // leaves room for fewer tokens, but they'd never be refilled anyway
+    fn max_units(&self) -> i64 {
+        (self.limit.capacity as i64).saturating_mul(self.units_per_token())
+    }
+
+    // Returns false if there are no tokens left
+    pub fn try_take(&mut self, now: DateTime) -> bool {
+        if let Some(last_refill) = self.last_refill {
+            // The clock could go backwards, in which case nothing is refilled
+            let elapsed = now.signed_duration_since(last_refill)
+                .num_milliseconds()
+                .max(0);
+            let refill = elapsed.saturating_mul(self.limit.capacity as i64);
+
+            self.units = self.units.saturating_add(refill).min(self.max_units());
+        }
+        self.last_refill = Some(now);
+
+        if self.units >= self.units_per_token() {
+            self.units -= self.units_per_token();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn refill() {
+        let mut bucket = TokenBucket::new(RateLimit {
+            capacity: 2,
+            per: Duration::seconds(10),
+        });
+        let at = |ms| Utc.timestamp(0, 0) + Duration::milliseconds(ms);
+
+        assert!(bucket.try_take(at(0)));
+        assert!(bucket.try_take(at(0)));
+        assert!(!bucket.try_take(at(1000)));
+
+        // One token every 5 seconds
+        assert!(!bucket.try_take(at(4999)));
+        assert!(bucket.try_take(at(5000)));
+        assert!(!bucket.try_take(at(5000)));
+
+        // Never more than the capacity
+        assert!(bucket.try_take(at(60_000)));
+        assert!(bucket.try_take(at(60_000)));
+        assert!(!bucket.try_take(at(60_000)));
+    }
+
+    #[test]
+    fn long_period() {
+        let mut bucket = TokenBucket::new(RateLimit {
+            capacity: 10,
+            per: Duration::max_value(),
+        });
+        let at = |days| Utc.timestamp(0, 0) + Duration::days(days);
+
+        assert!(bucket.try_take(at(0)));
+        for _ in 0..20 {
+            bucket.try_take(at(0));
+        }
+        assert!(!bucket.try_take(at(365)));
+    }
+}
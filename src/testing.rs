@@ -0,0 +1,184 @@
+// Helpers for testing code that uses a `Client`, without needing to connect
+// to Twitter. Only available with the `testing` feature.
+
+use chrono::{TimeZone, Utc};
+use error::*;
+use futures::{Async, Poll, Stream};
+use futures::unsync::mpsc;
+use model::{BossName, Language, RaidTweet};
+use raid::RaidInfo;
+
+// A stream of `RaidInfo` that can be used with `ClientBuilder::with_stream`.
+// Items are pushed through the `MockStreamHandle`.
+#[derive(Debug)]
+pub struct MockStream {
+    receiver: mpsc::UnboundedReceiver<Result<RaidInfo>>,
+    // Keeps the stream from ending when the other handles are dropped
+    keep_open: Option<MockStreamHandle>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MockStreamHandle(mpsc::UnboundedSender<Result<RaidInfo>>);
+
+pub fn mock_stream() -> (MockStreamHandle, MockStream) {
+    let (tx, rx) = mpsc::unbounded();
+    let stream = MockStream {
+        receiver: rx,
+        keep_open: None,
+    };
+    (MockStreamHandle(tx), stream)
+}
+
+// Yields the items in order, then stays `NotReady` rather than ending, so
+// the worker keeps handling requests. More items can be pushed later with
+// the returned handle.
+pub fn from_vec(items: Vec<RaidInfo>) -> (MockStreamHandle, MockStream) {
+    let (handle, mut stream) = mock_stream();
+    for info in items {
+        handle.push(info);
+    }
+    stream.keep_open = Some(handle.clone());
+    (handle, stream)
+}
+
+impl MockStreamHandle {
+    pub fn push(&self, info: RaidInfo) {
+        let _ = self.0.unbounded_send(Ok(info));
+    }
+
+    pub fn error<E>(&self, error: E)
+    where
+        E: Into<Error>,
+    {
+        let _ = self.0.unbounded_send(Err(error.into()));
+    }
+
+    // The stream ends once every handle has been dropped. This is the same,
+    // but more explicit. Streams from `from_vec` never end.
+    pub fn end(self) {}
+}
+
+impl Stream for MockStream {
+    type Item = RaidInfo;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.receiver.poll() {
+            Ok(Async::Ready(Some(Ok(info)))) => Ok(Async::Ready(Some(info))),
+            Ok(Async::Ready(Some(Err(e)))) => Err(e),
+            Ok(Async::Ready(None)) => Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(()) => Err(ErrorKind::Closed.into()),
+        }
+    }
+}
+
+// Creates a `RaidInfo` with placeholder values for everything other than the
+// boss name and raid ID. Since fields are public, they can be changed after.
+pub fn raid_info<B>(boss_name: B, raid_id: &str) -> RaidInfo
+where
+    B: Into<BossName>,
+{
+    let boss_name = boss_name.into();
+    let level = boss_name.parse_level();
+
+    RaidInfo {
+        tweet: RaidTweet {
+            tweet_id: 0,
+            boss_name,
+            raid_id: raid_id.to_string(),
+            user_screen_name: "user".to_string(),
+            user_display_name: "User".to_string(),
+            user_id: 1,
+            user_image: None,
+            text: None,
+            created_at: Utc.timestamp(0, 0),
+            language: Language::English,
+            seq: 0,
+            raw: None,
+        },
+        image: None,
+        images: vec![],
+        level,
+        raw: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ClientBuilder;
+    use broadcast::NoOpSubscriber;
+    use futures::Future;
+    use futures::future;
+    use image_hash::{BossImageHash, ImageHasher};
+    use std::time::Duration;
+    use tokio_core::reactor::Core;
+
+    struct NoOpImageHasher;
+    impl ImageHasher for NoOpImageHasher {
+        type Future = future::Empty<BossImageHash, Error>;
+
+        fn hash(&self, _boss_name: BossName, _uri: ::hyper::Uri) -> Self::Future {
+            future::empty()
+        }
+    }
+
+    #[test]
+    fn from_vec_stays_open() {
+        let mut core = Core::new().unwrap();
+        let items = vec![
+            raid_info("Lvl 60 Ozorotter", "AAAA0001"),
+            raid_info("Lvl 75 Ozorotter", "AAAA0002"),
+        ];
+        let (handle, stream) = from_vec(items);
+        let mut stream = stream.map(|info| info.tweet.raid_id);
+
+        let ids = core.run((&mut stream).take(2).collect()).unwrap();
+        assert_eq!(ids, vec!["AAAA0001", "AAAA0002"]);
+
+        // Dropping every handle doesn't end the stream
+        handle.push(raid_info("Lvl 60 Ozorotter", "AAAA0003"));
+        handle.end();
+
+        let (next, mut stream) = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+        assert_eq!(next, Some("AAAA0003".to_string()));
+        let polled = core.run(future::lazy(|| stream.poll())).unwrap();
+        assert_eq!(polled, Async::NotReady);
+    }
+
+    #[test]
+    fn mock_stream_client() {
+        let mut core = Core::new().unwrap();
+        let (handle, stream) = mock_stream();
+
+        let (client, worker) = ClientBuilder::new()
+            .with_stream(stream)
+            .with_image_hasher(NoOpImageHasher)
+            .with_subscriber::<NoOpSubscriber>()
+            .filter_map_message(|_| None)
+            .build();
+
+        let (worker_tx, worker_rx) = ::futures::sync::oneshot::channel();
+        core.handle()
+            .spawn(worker.then(|result| worker_tx.send(result).map_err(|_| ())));
+
+        handle.push(raid_info("Lvl 60 Ozorotter", "AAAA0001"));
+        handle.push(raid_info("Lvl 60 Ozorotter", "AAAA0002"));
+        core.turn(Some(Duration::from_millis(0)));
+
+        let bosses = core.run(client.bosses()).unwrap();
+        assert_eq!(bosses.len(), 1);
+        assert_eq!(bosses[0].name, "Lvl 60 Ozorotter".into());
+        assert_eq!(bosses[0].level, Some(60));
+
+        let tweets = core.run(client.tweets("Lvl 60 Ozorotter")).unwrap();
+        assert_eq!(tweets.len(), 2);
+
+        handle.error(ErrorKind::Twitter);
+        match core.run(worker_rx).unwrap() {
+            Err(Error(ErrorKind::Twitter, _)) => {}
+            other => panic!("expected Twitter error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,286 @@
+// Support for Twitter's v2 filtered stream, which replaces the v1.1
+// `statuses/filter` endpoint. It authenticates with an app's bearer token,
+// and instead of a `track` parameter, tweets are matched against rules that
+// are added to the app ahead of time with `add_rules`. Rules stay added
+// between connections.
+//
+// Use `RaidInfoStreamBuilder::with_bearer_token` to get a `RaidInfoStream`
+// from the filtered stream.
+
+use error::*;
+use futures::{Async, Future, Poll, Stream};
+use hyper::{self, Method, Request};
+use hyper::client::Connect;
+use hyper::header::{Authorization, Bearer, ContentType, UserAgent};
+use model::DateTime;
+use serde_json;
+use twitter_stream;
+use twitter_stream::types::JsonStr;
+
+const STREAM_URL: &str = "https://api.twitter.com/2/tweets/search/stream\
+                          ?tweet.fields=created_at,source,referenced_tweets,attachments,author_id\
+                          &expansions=author_id,attachments.media_keys\
+                          &user.fields=name,username,profile_image_url\
+                          &media.fields=url";
+const RULES_URL: &str = "https://api.twitter.com/2/tweets/search/stream/rules";
+
+// Matches the same tweets as the default `TrackOptions`
+pub const DEFAULT_RULE: &str = r#""参加者募集！" OR ":参戦ID" OR "I need backup!" OR ":Battle ID""#;
+
+// A message from the filtered stream. Messages without `data`, such as
+// errors sent before a disconnect, aren't tweets.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Payload {
+    pub data: Option<Tweet>,
+    #[serde(default)]
+    pub includes: Includes,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Tweet {
+    // IDs are strings in v2
+    pub id: String,
+    pub text: String,
+    #[serde(default)]
+    pub author_id: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<DateTime>,
+    // The name of the app, rather than the HTML link sent by v1.1
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub referenced_tweets: Vec<ReferencedTweet>,
+    #[serde(default)]
+    pub attachments: Option<Attachments>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ReferencedTweet {
+    // One of `retweeted`, `quoted`, or `replied_to`
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Attachments {
+    #[serde(default)]
+    pub media_keys: Vec<String>,
+}
+
+// Users and media referenced by the tweet are sent separately from it
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Includes {
+    #[serde(default)]
+    pub users: Vec<User>,
+    #[serde(default)]
+    pub media: Vec<Media>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct User {
+    pub id: String,
+    pub name: String,
+    pub username: String,
+    #[serde(default)]
+    pub profile_image_url: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Media {
+    pub media_key: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    // Only photos have a URL
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+impl Tweet {
+    // Retweets and quoted tweets, which `ParserConfig` skips by default
+    pub fn is_retweet(&self) -> bool {
+        self.referenced_tweets
+            .iter()
+            .any(|t| t.kind == "retweeted" || t.kind == "quoted")
+            || self.text.starts_with("RT @")
+    }
+}
+
+impl Payload {
+    pub fn author(&self) -> Option<&User> {
+        let author_id = self.data.as_ref().and_then(|t| t.author_id.as_ref())?;
+        self.includes.users.iter().find(|u| u.id == *author_id)
+    }
+
+    // In the order they're attached to the tweet
+    pub fn media_urls(&self) -> Vec<&str> {
+        let keys = self.data
+            .as_ref()
+            .and_then(|t| t.attachments.as_ref())
+            .map_or(&[][..], |a| &a.media_keys[..]);
+
+        keys.iter()
+            .filter_map(|key| self.includes.media.iter().find(|m| m.media_key == *key))
+            .filter_map(|m| m.url.as_ref())
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+fn set_headers<B>(request: &mut Request<B>, bearer_token: &str) {
+    let headers = request.headers_mut();
+    headers.set(Authorization(Bearer {
+        token: bearer_token.to_string(),
+    }));
+    headers.set(UserAgent::new("petronel"));
+}
+
+pub(crate) fn connect<C, B>(
+    hyper_client: &hyper::Client<C, B>,
+    bearer_token: &str,
+) -> Box<Stream<Item = JsonStr, Error = twitter_stream::Error>>
+where
+    C: Connect,
+    B: Stream<Error = hyper::Error> + 'static,
+    B::Item: AsRef<[u8]>,
+{
+    let mut request = Request::new(Method::Get, STREAM_URL.parse().expect("invalid stream URL"));
+    set_headers(&mut request, bearer_token);
+
+    let lines = hyper_client
+        .request(request)
+        .map_err(twitter_stream::Error::Hyper)
+        .and_then(|response| {
+            if response.status().is_success() {
+                Ok(Lines {
+                    body: response.body(),
+                    buffer: Vec::new(),
+                })
+            } else {
+                Err(twitter_stream::Error::Http(response.status()))
+            }
+        })
+        .flatten_stream();
+
+    Box::new(lines)
+}
+
+#[derive(Serialize)]
+struct AddRules<'a> {
+    add: Vec<Rule<'a>>,
+}
+
+#[derive(Serialize)]
+struct Rule<'a> {
+    value: &'a str,
+}
+
+// Adds rules to the app's filtered stream, e.g. `DEFAULT_RULE`. Adding a
+// rule that already exists isn't an error.
+pub fn add_rules<C, B>(
+    hyper_client: &hyper::Client<C, B>,
+    bearer_token: &str,
+    rules: &[&str],
+) -> Box<Future<Item = (), Error = Error>>
+where
+    C: Connect,
+    B: From<Vec<u8>> + Stream<Error = hyper::Error> + 'static,
+    B::Item: AsRef<[u8]>,
+{
+    let body = AddRules {
+        add: rules.iter().map(|value| Rule { value }).collect(),
+    };
+
+    let mut request = Request::new(Method::Post, RULES_URL.parse().expect("invalid rules URL"));
+    set_headers(&mut request, bearer_token);
+    request.headers_mut().set(ContentType::json());
+    request.set_body(serde_json::to_vec(&body).expect("failed to serialize rules"));
+
+    let response = hyper_client.request(request).then(|result| match result {
+        Ok(ref response) if response.status().is_success() => Ok(()),
+        Ok(response) => Err(Error::with_chain(
+            twitter_stream::Error::Http(response.status()),
+            ErrorKind::Twitter,
+        )),
+        Err(e) => Err(Error::with_chain(e, ErrorKind::Twitter)),
+    });
+
+    Box::new(response)
+}
+
+// Splits the response body into lines. The filtered stream sends blank
+// lines to keep the connection alive, which are skipped.
+struct Lines {
+    body: hyper::Body,
+    buffer: Vec<u8>,
+}
+
+impl Lines {
+    fn take_line(
+        &mut self,
+        end: usize,
+    ) -> ::std::result::Result<Option<JsonStr>, twitter_stream::Error> {
+        let line = self.buffer.drain(..end).collect::<Vec<_>>();
+        let line =
+            String::from_utf8(line).map_err(|e| twitter_stream::Error::Utf8(e.utf8_error()))?;
+
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(JsonStr::from(trimmed.to_string())))
+        }
+    }
+}
+
+impl Stream for Lines {
+    type Item = JsonStr;
+    type Error = twitter_stream::Error;
+
+    fn poll(&mut self) -> Poll<Option<JsonStr>, twitter_stream::Error> {
+        loop {
+            if let Some(i) = self.buffer.iter().position(|&b| b == b'\n') {
+                match self.take_line(i + 1)? {
+                    Some(line) => return Ok(Async::Ready(Some(line))),
+                    None => continue,
+                }
+            }
+
+            match try_ready!(self.body.poll().map_err(twitter_stream::Error::Hyper)) {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk),
+                None => {
+                    // Anything after the last newline is still a line
+                    let end = self.buffer.len();
+                    return Ok(Async::Ready(self.take_line(end)?));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::Sink;
+    use futures::sync::mpsc;
+    use tokio_core::reactor::Core;
+
+    #[test]
+    fn split_lines() {
+        let mut core = Core::new().unwrap();
+        let chunks = ["{\"a\"", ":1}\r\n\r\n", "\r\n{\"b\":2}\r\n{\"c\"", ":3}"];
+        let (mut tx, rx) = mpsc::channel(chunks.len());
+        for chunk in chunks.iter() {
+            let sent = tx.start_send(Ok(hyper::Chunk::from(*chunk))).unwrap();
+            assert!(sent.is_ready());
+        }
+        drop(tx);
+
+        let lines = Lines {
+            body: rx.into(),
+            buffer: Vec::new(),
+        };
+        let lines = core.run(lines.map(|line| line.to_string()).collect()).unwrap();
+        assert_eq!(lines, vec![r#"{"a":1}"#, r#"{"b":2}"#, r#"{"c":3}"#]);
+    }
+}
@@ -0,0 +1,178 @@
+// Sends messages to a webhook URL as JSON POST requests. The returned sender
+// can be used as a `Subscriber`, and the future needs to be spawned on the
+// event loop for requests to actually be sent.
+//
+// Messages are queued in a bounded buffer. If the buffer is full (e.g., the
+// webhook is slow to respond), new messages are logged and dropped rather
+// than holding up the rest of the pipeline. Dropped messages are counted in
+// `Client::subscription_stats`.
+
+use bounded::{self, DropPolicy};
+use broadcast::Subscriber;
+use error::*;
+use futures::{Future, Stream};
+use futures::future;
+use hyper::{self, Method, Request, Uri};
+use hyper::client::Connect;
+use hyper::header::ContentType;
+use std::rc::Rc;
+use std::time::Duration;
+use tokio_core::reactor::{Handle, Timeout};
+
+const MAX_RETRIES: u32 = 3;
+const INITIAL_BACKOFF_MILLIS: u64 = 500;
+
+#[derive(Clone, Debug)]
+pub struct WebhookSender(bounded::Sender<String>);
+
+impl Subscriber for WebhookSender {
+    type Item = String;
+
+    fn send(&mut self, message: &Self::Item) -> ::std::result::Result<(), ()> {
+        let overflow_count = self.0.overflow_count();
+        let result = self.0.send(message);
+        if self.0.overflow_count() > overflow_count {
+            warn!("Webhook queue is full, dropping message");
+        }
+        result
+    }
+
+    fn overflow_count(&self) -> u64 {
+        self.0.overflow_count()
+    }
+
+    fn is_closed(&self) -> bool {
+        self.0.is_closed()
+    }
+}
+
+pub fn channel<C>(
+    hyper_client: hyper::Client<C>,
+    uri: Uri,
+    handle: &Handle,
+    queue_size: usize,
+) -> (WebhookSender, Box<Future<Item = (), Error = ()>>)
+where
+    C: Connect,
+{
+    let (tx, rx) = bounded::channel(queue_size, DropPolicy::DropNewest);
+    let handle = handle.clone();
+    let hyper_client = Rc::new(hyper_client);
+
+    let future = rx.map(move |body: String| {
+        let (hyper_client, uri) = (hyper_client.clone(), uri.clone());
+
+        let post = move || {
+            let mut request = Request::new(Method::Post, uri.clone());
+            request.headers_mut().set(ContentType::json());
+            request.set_body(body.clone());
+
+            hyper_client
+                .request(request)
+                .then(|result| match result {
+                    Ok(ref resp) if resp.status().is_success() => Ok(()),
+                    Ok(resp) => Err(ErrorKind::Webhook(resp.status().to_string()).into()),
+                    Err(e) => Err(Error::with_chain(e, ErrorKind::Webhook("request".into()))),
+                })
+        };
+
+        with_retry(post, &handle, MAX_RETRIES, Duration::from_millis(INITIAL_BACKOFF_MILLIS))
+            .or_else(|e| {
+                warn!("Failed to send webhook: {}", e);
+                Ok(())
+            })
+    });
+
+    let future = future.buffer_unordered(queue_size).for_each(|()| Ok(()));
+    (WebhookSender(tx), Box::new(future))
+}
+
+// Retries the future returned by `f`, doubling the delay between each attempt
+fn with_retry<F, R>(
+    f: F,
+    handle: &Handle,
+    retries: u32,
+    backoff: Duration,
+) -> Box<Future<Item = (), Error = Error>>
+where
+    F: Fn() -> R + 'static,
+    R: Future<Item = (), Error = Error> + 'static,
+{
+    let handle = handle.clone();
+
+    Box::new(f().or_else(move |e| -> Box<Future<Item = (), Error = Error>> {
+        if retries == 0 {
+            return Box::new(future::err(e));
+        }
+
+        match Timeout::new(backoff, &handle) {
+            Ok(timeout) => Box::new(
+                timeout
+                    .then(move |_| with_retry(f, &handle, retries - 1, backoff * 2)),
+            ),
+            Err(_) => Box::new(future::err(e)),
+        }
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+    use tokio_core::reactor::Core;
+
+    // Fails the first `count` attempts
+    fn attempt(count: u32, attempts: &Cell<u32>) -> future::FutureResult<(), Error> {
+        attempts.set(attempts.get() + 1);
+
+        if attempts.get() > count {
+            future::ok(())
+        } else {
+            future::err(ErrorKind::Webhook("500".into()).into())
+        }
+    }
+
+    #[test]
+    fn retry_until_success() {
+        let mut core = Core::new().unwrap();
+        let attempts = Rc::new(Cell::new(0));
+
+        let f = {
+            let attempts = attempts.clone();
+            move || attempt(2, &attempts)
+        };
+        let result = core.run(with_retry(f, &core.handle(), 3, Duration::from_millis(1)));
+
+        assert!(result.is_ok());
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[test]
+    fn retry_gives_up() {
+        let mut core = Core::new().unwrap();
+        let attempts = Rc::new(Cell::new(0));
+
+        let f = {
+            let attempts = attempts.clone();
+            move || attempt(10, &attempts)
+        };
+        let result = core.run(with_retry(f, &core.handle(), 3, Duration::from_millis(1)));
+
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 4);
+    }
+
+    #[test]
+    fn full_queue_drops_messages() {
+        let core = Core::new().unwrap();
+        let hyper_client = hyper::Client::new(&core.handle());
+        let uri = "http://localhost/webhook".parse().unwrap();
+
+        // The future isn't run, so nothing is taken off the queue
+        let (mut tx, _future) = channel(hyper_client, uri, &core.handle(), 2);
+        for message in &["a", "b", "c"] {
+            assert_eq!(tx.send(&message.to_string()), Ok(()));
+        }
+        assert_eq!(tx.overflow_count(), 1);
+    }
+}